@@ -0,0 +1,88 @@
+use crate::error::TdbError;
+use crate::object::{Key, Val};
+use crate::transaction::{to_owned_bound, TimeStamp};
+use parking_lot::Mutex;
+use std::borrow::Borrow;
+use std::ops::{Bound, RangeBounds};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum BatchOp {
+    Insert(Key, Val),
+    Remove(Key),
+    RemoveRange(Bound<Key>, Bound<Key>),
+}
+
+/// Changes built without holding any lock, applied atomically by KVStore::write
+/// Changes are applied in the order they are added
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteBatch {
+    pub(crate) ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert<K: Into<Key>, V: Into<Val>>(&mut self, key: K, val: V) -> &mut Self {
+        self.ops.push(BatchOp::Insert(key.into(), val.into()));
+        self
+    }
+
+    pub fn remove<K: Into<Key>>(&mut self, key: K) -> &mut Self {
+        self.ops.push(BatchOp::Remove(key.into()));
+        self
+    }
+
+    /// Remove all keys in range, range can be any of `a..b`, `a..=b`, `a..`, `..b`, `..=b` or `..`
+    pub fn remove_range<K: Borrow<[u8]>, R: RangeBounds<K>>(&mut self, range: R) -> &mut Self {
+        let start = to_owned_bound(range.start_bound());
+        let end = to_owned_bound(range.end_bound());
+        self.ops.push(BatchOp::RemoveRange(start, end));
+        self
+    }
+
+    /// Return number of changes
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+}
+
+// group is not extended after it has more changes
+pub(crate) const MAX_GROUP_OPS: usize = 1 << 14;
+
+/// Batch queued by KVStore::write, the first one in queue commits queued batches as a group
+pub(crate) struct PendingWrite {
+    batch: Mutex<Option<WriteBatch>>,
+    result: Mutex<Option<Result<TimeStamp, TdbError>>>,
+}
+
+impl PendingWrite {
+    pub fn new(batch: WriteBatch) -> Self {
+        Self {
+            batch: Mutex::new(Some(batch)),
+            result: Mutex::new(None),
+        }
+    }
+
+    pub fn take_batch(&self) -> Option<WriteBatch> {
+        self.batch.lock().take()
+    }
+
+    pub fn set_result(&self, result: Result<TimeStamp, TdbError>) {
+        *self.result.lock() = Some(result);
+    }
+
+    /// Return result if it's committed by another one
+    pub fn take_result(&self) -> Option<Result<TimeStamp, TdbError>> {
+        self.result.lock().take()
+    }
+}
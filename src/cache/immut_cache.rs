@@ -1,4 +1,4 @@
-use crate::object::{Entry, Object};
+use crate::object::Object;
 use crate::storage::ObjectPos;
 use crossbeam::{
     channel::{unbounded, Receiver, Sender, TryRecvError},
@@ -8,7 +8,7 @@ use lru_cache::LruCache;
 use std::sync::Arc;
 use std::thread;
 
-const DEFAULT_CACHE_SIZE: usize = 4096;
+pub const DEFAULT_CACHE_SIZE: usize = 4096;
 
 enum ObjectOp {
     Insert(ObjectPos, Arc<Object>),
@@ -36,9 +36,13 @@ impl Clone for ImMutCache {
 }
 
 impl ImMutCache {
+    #[inline]
+    pub fn with_capacity(cap: usize) -> Self {
+        ImMutCacheInner::with_capacity(cap)
+    }
     /// Send Arc<Object> to cache
     pub fn insert(&self, obj_pos: ObjectPos, arc_obj: Arc<Object>) {
-        if !arc_obj.is::<Entry>() {
+        if arc_obj.is_node() {
             self.sender
                 .try_send(ObjectOp::Insert(obj_pos, arc_obj))
                 .expect("send error");
@@ -60,8 +64,8 @@ impl ImMutCacheInner {
         let lru_cache = LruCache::new(cap);
         let (sender, receiver) = unbounded();
         let cache = ImMutCacheInner {
-            lru_cache: lru_cache,
-            receiver: receiver,
+            lru_cache,
+            receiver,
         };
         let handler = ImMutCache { sender };
         cache.work();
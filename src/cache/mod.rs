@@ -1,4 +1,4 @@
 mod immut_cache;
 mod mut_cache;
-pub use immut_cache::{ImMutCache, ImMutCacheInner};
+pub use immut_cache::{ImMutCache, DEFAULT_CACHE_SIZE};
 pub use mut_cache::MutCache;
@@ -2,23 +2,20 @@ use crate::object::{Object, ObjectId, ObjectState};
 use std::collections::HashMap;
 
 /// Dirty Object cache for MutTable
+#[derive(Default)]
 pub struct MutCache {
     dirties: HashMap<ObjectId, ObjectState>,
 }
 
-impl Default for MutCache {
-    fn default() -> Self {
-        MutCache {
-            dirties: HashMap::default(),
-        }
-    }
-}
-
 impl MutCache {
     ///  Return true if oid in dirties
     pub fn contain(&mut self, oid: ObjectId) -> bool {
         self.dirties.contains_key(&oid)
     }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.dirties.is_empty()
+    }
     pub fn remove(&mut self, oid: ObjectId) -> Option<ObjectState> {
         self.dirties.remove(&oid)
     }
@@ -29,7 +26,7 @@ impl MutCache {
         let obj_mut = self.dirties.remove(&oid)?;
         if obj_mut.is_readonly() {
             // if object is on disk, Logically remove it
-            let obj_dirty = obj_mut.to_dirty();
+            let obj_dirty = obj_mut.into_dirty();
             self.dirties.insert(oid, obj_dirty);
         } else {
             self.dirties.insert(oid, obj_mut);
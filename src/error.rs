@@ -1,3 +1,5 @@
+use crate::object::ObjectId;
+use crate::storage::ObjectPos;
 use std::error::Error;
 use std::fmt;
 use std::io;
@@ -10,6 +12,20 @@ pub enum TdbError {
     NoSpace,
     ObjectTooBig,
     NotFindObject,
+    // files are written in a format this version can't read
+    UnsupportedFormat,
+    // checksum mismatch of object or checkpoint
+    Corruption { oid: ObjectId, pos: ObjectPos },
+    // options differ from the ones persisted when store is created
+    OptionsMismatch,
+    // store is opened by open_read_only
+    ReadOnly,
+    // LOCK file is held by another process or store
+    Locked,
+    // commit isn't visible before timeout
+    Timeout,
+    // key of transaction is changed by another commit after transaction starts
+    Conflict,
 }
 
 impl PartialEq for TdbError {
@@ -21,6 +37,15 @@ impl PartialEq for TdbError {
             (DeserializeError, DeserializeError) => true,
             (NoSpace, NoSpace) => true,
             (NotFindObject, NotFindObject) => true,
+            (UnsupportedFormat, UnsupportedFormat) => true,
+            (OptionsMismatch, OptionsMismatch) => true,
+            (ReadOnly, ReadOnly) => true,
+            (Locked, Locked) => true,
+            (Timeout, Timeout) => true,
+            (Conflict, Conflict) => true,
+            (Corruption { oid: o1, pos: p1 }, Corruption { oid: o2, pos: p2 }) => {
+                o1 == o2 && p1 == p2
+            }
             (IoError(e1), IoError(e2)) => e1.kind() == e2.kind(),
             _ => false,
         }
@@ -29,6 +54,31 @@ impl PartialEq for TdbError {
 
 impl Eq for TdbError {}
 
+impl Clone for TdbError {
+    // io::Error isn't Clone, keep its kind and message
+    fn clone(&self) -> Self {
+        use TdbError::*;
+        match self {
+            IoError(err) => IoError(io::Error::new(err.kind(), err.to_string())),
+            SerializeError => SerializeError,
+            DeserializeError => DeserializeError,
+            NoSpace => NoSpace,
+            ObjectTooBig => ObjectTooBig,
+            NotFindObject => NotFindObject,
+            UnsupportedFormat => UnsupportedFormat,
+            Corruption { oid, pos } => Corruption {
+                oid: *oid,
+                pos: *pos,
+            },
+            OptionsMismatch => OptionsMismatch,
+            ReadOnly => ReadOnly,
+            Locked => Locked,
+            Timeout => Timeout,
+            Conflict => Conflict,
+        }
+    }
+}
+
 impl fmt::Display for TdbError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "TxnError")
@@ -54,3 +104,12 @@ impl From<io::Error> for TdbError {
         Self::IoError(err)
     }
 }
+
+impl From<TdbError> for io::Error {
+    fn from(err: TdbError) -> Self {
+        match err {
+            TdbError::IoError(err) => err,
+            err => io::Error::other(err),
+        }
+    }
+}
@@ -1,15 +1,27 @@
+use crate::batch::{PendingWrite, WriteBatch, MAX_GROUP_OPS};
 use crate::cache::ImMutCache;
 use crate::error::TdbError;
 use crate::meta::{CheckPoint, InnerTable};
-use crate::object::{Key, ObjectId, Val, UNUSED_OID};
-use crate::storage::Dev;
-use crate::transaction::{ImMutContext, Iter, MutContext, TimeStamp};
-use log::{debug, info};
-use parking_lot::{Mutex, MutexGuard, RwLock};
+use crate::object::{Key, ObjectId, Val, DEFAULT_NODE_SIZE, MAX_KEY_SIZE, UNUSED_OID};
+use crate::options::{Options, PersistedOptions};
+use crate::storage::{Dev, SyncMode, SEGMENT_SIZE};
+use crate::transaction::{
+    is_valid_range, prefix_range, to_owned_bound, ImMutContext, Iter, MutContext, MutIter,
+    TimeStamp, ValReader,
+};
+use log::{debug, info, warn};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
 use std::borrow::Borrow;
-use std::ops::Range;
+use std::cmp::Ordering;
+use std::collections::{btree_map, BTreeMap, BTreeSet, VecDeque};
+use std::io::{self, Read};
+use std::iter::{Peekable, Rev};
+use std::mem;
+use std::ops::{Bound, RangeBounds};
 use std::path::Path;
 use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub struct Context {
@@ -25,13 +37,177 @@ impl Default for Context {
     }
 }
 
+/// What is recovered when store is opened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryReport {
+    // valid checkpoints read from meta log
+    pub checkpoints_replayed: usize,
+    // torn or invalid tail discarded from meta log
+    pub bytes_truncated: u64,
+    pub root_oid: ObjectId,
+}
+impl Default for RecoveryReport {
+    fn default() -> Self {
+        Self {
+            checkpoints_replayed: 0,
+            bytes_truncated: 0,
+            root_oid: UNUSED_OID,
+        }
+    }
+}
+
+/// Keys changed by commits after the oldest active transaction starts
+/// Conflicts are checked by keys, not by Versions in InnerTable, which are kept per object
+/// and may be gc before transaction commits
+#[derive(Default)]
+struct CommitLog {
+    // start ts of active transactions and their num
+    active_txns: BTreeMap<TimeStamp, usize>,
+    // ts and changed keys of commits
+    commits: VecDeque<(TimeStamp, BTreeSet<Key>)>,
+}
+
+impl CommitLog {
+    /// Remove commits which no active transaction starts before
+    fn prune(&mut self) {
+        match self.active_txns.keys().next() {
+            Some(min_ts) => {
+                while self.commits.front().is_some_and(|(ts, _)| ts <= min_ts) {
+                    self.commits.pop_front();
+                }
+            }
+            None => self.commits.clear(),
+        }
+    }
+}
+
+/// Ctx of last commit, waiters of commit are notified when it is changed
+struct GlobalCtx {
+    ctx: RwLock<Arc<Context>>,
+    commit_log: Mutex<CommitLog>,
+    commit_cond: Condvar,
+}
+
+impl GlobalCtx {
+    fn new(ctx: Arc<Context>) -> Self {
+        Self {
+            ctx: RwLock::new(ctx),
+            commit_log: Mutex::new(CommitLog::default()),
+            commit_cond: Condvar::new(),
+        }
+    }
+
+    #[inline]
+    fn get(&self) -> Arc<Context> {
+        self.ctx.read().clone()
+    }
+
+    /// Make ctx visible, changed keys are logged if any transaction is active
+    fn set(&self, ctx: Arc<Context>, changed_keys: BTreeSet<Key>) {
+        // hold lock so that waiter doesn't miss notification between check and wait
+        let mut commit_log = self.commit_log.lock();
+        if !commit_log.active_txns.is_empty() && !changed_keys.is_empty() {
+            commit_log.commits.push_back((ctx.ts, changed_keys));
+        }
+        *self.ctx.write() = ctx;
+        self.commit_cond.notify_all();
+    }
+
+    /// Return ctx whose ts is not less than ts, None if timeout
+    fn wait_for(&self, ts: TimeStamp, timeout: Duration) -> Option<Arc<Context>> {
+        let deadline = Instant::now() + timeout;
+        let mut guard = self.commit_log.lock();
+        loop {
+            let ctx = self.get();
+            if ctx.ts >= ts {
+                return Some(ctx);
+            }
+            if self
+                .commit_cond
+                .wait_until(&mut guard, deadline)
+                .timed_out()
+            {
+                let ctx = self.get();
+                return if ctx.ts >= ts { Some(ctx) } else { None };
+            }
+        }
+    }
+
+    /// Return ctx that transaction starts at, commits after it are logged until transaction ends
+    /// Ctx is got under commit log lock like set, so no commit after it is missed
+    fn start_txn(&self) -> Arc<Context> {
+        let mut commit_log = self.commit_log.lock();
+        let ctx = self.get();
+        *commit_log.active_txns.entry(ctx.ts).or_insert(0) += 1;
+        ctx
+    }
+
+    fn end_txn(&self, start_ts: TimeStamp) {
+        let mut commit_log = self.commit_log.lock();
+        if let Some(num) = commit_log.active_txns.get_mut(&start_ts) {
+            *num -= 1;
+            if *num == 0 {
+                commit_log.active_txns.remove(&start_ts);
+            }
+        }
+        commit_log.prune();
+    }
+
+    /// Return true if any key or any key in ranges is changed by commits after start ts
+    fn is_changed_since<'k, I: IntoIterator<Item = &'k Key>>(
+        &self,
+        start_ts: TimeStamp,
+        keys: I,
+        ranges: &[(Bound<Key>, Bound<Key>)],
+    ) -> bool {
+        let commit_log = self.commit_log.lock();
+        let commits: Vec<&BTreeSet<Key>> = commit_log
+            .commits
+            .iter()
+            .filter(|(ts, _)| *ts > start_ts)
+            .map(|(_, changed_keys)| changed_keys)
+            .collect();
+        if commits.is_empty() {
+            return false;
+        }
+        let range_changed = ranges.iter().any(|(start, end)| {
+            is_valid_range(start, end)
+                && commits.iter().any(|changed_keys| {
+                    changed_keys
+                        .range::<Key, _>((start.clone(), end.clone()))
+                        .next()
+                        .is_some()
+                })
+        });
+        range_changed
+            || keys.into_iter().any(|key| {
+                commits
+                    .iter()
+                    .any(|changed_keys| changed_keys.contains(key))
+            })
+    }
+}
+
 pub struct KVReader(ImMutContext, Arc<Context>);
 
 impl KVReader {
-    pub fn get<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<Key>, TdbError> {
+    /// Return timestamp of the commit this reader sees
+    pub fn timestamp(&self) -> TimeStamp {
+        self.1.ts
+    }
+
+    pub fn get<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<Val>, TdbError> {
         self.0.get(key)
     }
 
+    /// Return reader of val which implements Read and Seek, val is read part by part
+    pub fn get_reader<K: Borrow<[u8]>>(
+        &mut self,
+        key: &K,
+    ) -> Result<Option<ValReader<'_>>, TdbError> {
+        self.0.get_reader(key)
+    }
+
     pub fn get_min(&mut self) -> Result<Option<(Key, Val)>, TdbError> {
         self.0.get_min()
     }
@@ -40,97 +216,786 @@ impl KVReader {
         self.0.get_max()
     }
 
-    pub fn range<'a, K: Borrow<[u8]>>(
-        &'a mut self,
-        range: Range<&'a K>,
-    ) -> Result<Option<Iter<'a, K>>, TdbError> {
+    /// Return iterator of (key, val) in range, range can be any of `a..b`, `a..=b`, `a..`, `..b`, `..=b` or `..`
+    pub fn range<K: Borrow<[u8]>, R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<Iter<'_>, TdbError> {
         self.0.range(range)
     }
+
+    /// Return iterator of (key, val) in range from the last one
+    pub fn range_rev<K: Borrow<[u8]>, R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<Rev<Iter<'_>>, TdbError> {
+        self.0.range_rev(range)
+    }
+
+    /// Return iterator of all (key, val)
+    pub fn iter(&mut self) -> Result<Iter<'_>, TdbError> {
+        self.0.range::<Key, _>(..)
+    }
+
+    /// Return iterator of (key, val) whose key starts with prefix
+    pub fn scan_prefix<K: Borrow<[u8]>>(&mut self, prefix: &K) -> Result<Iter<'_>, TdbError> {
+        self.0.scan_prefix(prefix.borrow())
+    }
 }
 
-pub struct KVWriter<'a>(MutexGuard<'a, MutContext>, &'a RwLock<Arc<Context>>);
+pub struct KVWriter<'a>(MutexGuard<'a, MutContext>, &'a GlobalCtx);
 
 impl<'a> KVWriter<'a> {
     pub fn insert<K: Into<Key>, V: Into<Val>>(&mut self, key: K, val: V) -> Result<(), TdbError> {
         self.0.insert(key, val)
     }
 
+    /// Insert val of len read from reader, large val is written to data file without holding it in memory
+    pub fn insert_from<K: Into<Key>, R: Read>(
+        &mut self,
+        key: K,
+        reader: R,
+        len: u64,
+    ) -> Result<(), TdbError> {
+        self.0.insert_from(key, reader, len)
+    }
+
     pub fn remove<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<(Key, Val)>, TdbError> {
         self.0.remove(key)
     }
 
+    /// Remove all keys in range, return number of removed keys
+    pub fn remove_range<K: Borrow<[u8]>, R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<usize, TdbError> {
+        self.0.remove_range(range)
+    }
+
+    /// Set val of key to new if its val equals expected, None means key is absent or removed
+    /// Return true if applied
+    pub fn compare_and_swap<K: Into<Key>>(
+        &mut self,
+        key: K,
+        expected: Option<Val>,
+        new: Option<Val>,
+    ) -> Result<bool, TdbError> {
+        self.0.compare_and_swap(key, expected, new)
+    }
+
+    /// Insert key only if it is absent, return true if inserted
+    pub fn insert_if_absent<K: Into<Key>, V: Into<Val>>(
+        &mut self,
+        key: K,
+        val: V,
+    ) -> Result<bool, TdbError> {
+        self.0.insert_if_absent(key, val)
+    }
+
+    /// Update val of key only if it is present, return true if updated
+    pub fn update<K: Into<Key>, V: Into<Val>>(&mut self, key: K, val: V) -> Result<bool, TdbError> {
+        self.0.update(key, val)
+    }
+
     pub fn get<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<Val>, TdbError> {
-        Ok(self.0.get_entry(key)?.map(|entry| entry.val.clone()))
+        self.0.get(key)
+    }
+
+    /// Return iterator of (key, val) in range, changes not committed are visible
+    pub fn range<K: Borrow<[u8]>, R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<MutIter<'_>, TdbError> {
+        self.0.range(range)
     }
 
-    pub fn commit(mut self) -> Result<(), TdbError> {
+    /// Return iterator of all (key, val), changes not committed are visible
+    pub fn iter(&mut self) -> Result<MutIter<'_>, TdbError> {
+        self.0.range::<Key, _>(..)
+    }
+
+    /// Return iterator of (key, val) whose key starts with prefix, changes not committed are visible
+    pub fn scan_prefix<K: Borrow<[u8]>>(&mut self, prefix: &K) -> Result<MutIter<'_>, TdbError> {
+        self.0.scan_prefix(prefix.borrow())
+    }
+
+    /// Return timestamp of the commit, readers with timestamp not less than it see the changes
+    pub fn commit(mut self) -> Result<TimeStamp, TdbError> {
         let arc_ctx = self.0.commit()?;
-        *self.1.write() = arc_ctx;
+        let ts = arc_ctx.ts;
+        let changed_keys = self.0.take_changed_keys();
+        self.1.set(arc_ctx, changed_keys);
+        self.0.remove_stale_segments()?;
+        auto_compact(&mut self.0, self.1);
+        Ok(ts)
+    }
+
+    /// Discard all changes of this writer, same as dropping it without commit
+    pub fn rollback(mut self) {
+        self.0.rollback();
+    }
+}
+
+impl<'a> Drop for KVWriter<'a> {
+    fn drop(&mut self) {
+        // nothing to discard if committed
+        self.0.rollback();
+    }
+}
+
+// Batches of KVStore::write committed by leader, results are sent to followers when dropped,
+// so followers are woken up even if leader panics
+struct WriteGroup<'a> {
+    store: &'a KVStore,
+    mut_ctx: MutexGuard<'a, MutContext>,
+    // result of each batch in queue order, the first one is leader's
+    results: Vec<Result<TimeStamp, TdbError>>,
+}
+
+impl<'a> WriteGroup<'a> {
+    // Commit batches in one commit, a failed batch is rolled back with others,
+    // which are applied again without it
+    fn commit(&mut self, batches: &[WriteBatch]) {
+        let mut failed = vec![false; batches.len()];
+        let result = loop {
+            if failed.iter().all(|failed| *failed) {
+                return;
+            }
+            self.mut_ctx.increase_ts();
+            let applied = batches
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !failed[*i])
+                .try_for_each(|(i, batch)| self.mut_ctx.write_batch(batch).map_err(|err| (i, err)));
+            match applied {
+                Ok(()) => break self.mut_ctx.commit(),
+                Err((i, err)) => {
+                    debug!("batch {:?} of group fails: {:?}", i, err);
+                    self.mut_ctx.rollback();
+                    failed[i] = true;
+                    self.results[i] = Err(err);
+                }
+            }
+        };
+        let result = match result {
+            Ok(arc_ctx) => {
+                let ts = arc_ctx.ts;
+                let changed_keys = self.mut_ctx.take_changed_keys();
+                self.store.global_ctx.set(arc_ctx, changed_keys);
+                let result = self.mut_ctx.remove_stale_segments().map(|_| ts);
+                auto_compact(&mut self.mut_ctx, &self.store.global_ctx);
+                result
+            }
+            Err(err) => {
+                self.mut_ctx.rollback();
+                Err(err)
+            }
+        };
+        for (i, failed) in failed.into_iter().enumerate() {
+            if !failed {
+                self.results[i] = result.clone();
+            }
+        }
+    }
+}
+
+impl<'a> Drop for WriteGroup<'a> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.mut_ctx.rollback();
+        }
+        let mut queue = self.store.write_queue.lock();
+        // leader is removed even if it panics before its batch is taken
+        let group_len = self.results.len().max(1);
+        // skip leader, which is the first one
+        for (pending, result) in queue.drain(..group_len).zip(self.results.iter()).skip(1) {
+            pending.set_result(result.clone());
+        }
+        self.store.write_cond.notify_all();
+    }
+}
+
+#[inline]
+fn leader_panicked() -> TdbError {
+    TdbError::IoError(io::Error::other("group commit leader panicked"))
+}
+
+// Called after commit is visible, so commit is not failed by compaction and its ts is returned
+fn auto_compact(mut_ctx: &mut MutContext, global_ctx: &GlobalCtx) {
+    if !mut_ctx.should_compact() {
+        return;
+    }
+    match mut_ctx.compact() {
+        Ok(arc_ctx) => {
+            global_ctx.set(arc_ctx, BTreeSet::default());
+            if let Err(err) = mut_ctx.remove_stale_segments() {
+                warn!("remove stale segments failed: {:?}", err);
+            }
+        }
+        Err(err) => warn!("auto compaction failed: {:?}", err),
+    }
+}
+
+/// Isolation level of transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IsolationLevel {
+    /// Conflict if any key it changes is committed by others after it starts, write skew is possible
+    #[default]
+    Snapshot,
+    /// Also conflict if any key or range it reads is changed by others after it starts
+    /// # Notes
+    /// Only reads of Transaction are tracked, reads of KVWriter need no check
+    /// as no other commit is possible while it holds the writer lock
+    Serializable,
+}
+
+/// Optimistic transaction, changes are buffered and applied at commit
+/// Reads see the snapshot when it starts and its own changes
+/// Commit fails with Conflict if any key it changes, or reads in serializable level,
+/// is committed by others after it starts
+pub struct Transaction<'a> {
+    store: &'a KVStore,
+    reader: KVReader,
+    isolation: IsolationLevel,
+    // None for removed key
+    writes: BTreeMap<Key, Option<Val>>,
+    // keys and ranges read, only recorded in serializable level
+    read_keys: BTreeSet<Key>,
+    read_ranges: Vec<(Bound<Key>, Bound<Key>)>,
+}
+
+/// Iterator of (key, val) in range seen by transaction, which merges snapshot and its changes
+pub struct TxnIter<'a> {
+    iter: Peekable<Iter<'a>>,
+    writes: Peekable<btree_map::Range<'a, Key, Option<Val>>>,
+}
+
+impl<'a> Iterator for TxnIter<'a> {
+    type Item = Result<(Key, Val), TdbError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ordering = match (self.iter.peek(), self.writes.peek()) {
+                (None, None) => return None,
+                (Some(Err(_)), _) | (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(Ok((key, _))), Some((write_key, _))) => key.cmp(write_key),
+            };
+            match ordering {
+                Ordering::Less => return self.iter.next(),
+                // val in snapshot is replaced
+                Ordering::Equal => {
+                    self.iter.next();
+                }
+                Ordering::Greater => {}
+            }
+            if let (key, Some(val)) = self.writes.next().unwrap() {
+                return Some(Ok((key.clone(), val.clone())));
+            }
+        }
+    }
+}
+
+impl<'a> Transaction<'a> {
+    /// Return timestamp of the snapshot it reads
+    pub fn timestamp(&self) -> TimeStamp {
+        self.reader.timestamp()
+    }
+
+    pub fn get<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<Val>, TdbError> {
+        if self.isolation == IsolationLevel::Serializable {
+            self.read_keys.insert(key.borrow().to_vec());
+        }
+        match self.writes.get(key.borrow()) {
+            Some(val) => Ok(val.clone()),
+            None => self.reader.get(key),
+        }
+    }
+
+    /// Return iterator of (key, val) in range, range can be any of `a..b`, `a..=b`, `a..`, `..b`, `..=b` or `..`
+    /// In serializable level the whole range is read even if iterator isn't consumed
+    pub fn range<K: Borrow<[u8]>, R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<TxnIter<'_>, TdbError> {
+        let start = to_owned_bound(range.start_bound());
+        let end = to_owned_bound(range.end_bound());
+        if self.isolation == IsolationLevel::Serializable {
+            self.read_ranges.push((start.clone(), end.clone()));
+        }
+        let writes = if is_valid_range(&start, &end) {
+            self.writes.range::<Key, _>((start.clone(), end.clone()))
+        } else {
+            // empty
+            self.writes
+                .range::<Key, _>((Bound::Excluded(vec![]), Bound::Included(vec![])))
+        };
+        Ok(TxnIter {
+            iter: self.reader.range::<Key, _>((start, end))?.peekable(),
+            writes: writes.peekable(),
+        })
+    }
+
+    /// Return iterator of (key, val) whose key starts with prefix
+    pub fn scan_prefix<K: Borrow<[u8]>>(&mut self, prefix: &K) -> Result<TxnIter<'_>, TdbError> {
+        self.range(prefix_range(prefix.borrow()))
+    }
+
+    pub fn insert<K: Into<Key>, V: Into<Val>>(&mut self, key: K, val: V) -> Result<(), TdbError> {
+        let key: Key = key.into();
+        if key.len() > MAX_KEY_SIZE as usize {
+            return Err(TdbError::ObjectTooBig);
+        }
+        self.writes.insert(key, Some(val.into()));
         Ok(())
     }
+
+    /// Remove key and return its val seen by transaction
+    /// Absent key is also recorded as removed, so that a concurrent insert of it conflicts
+    pub fn remove<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<Val>, TdbError> {
+        let val = self.get(key)?;
+        self.writes.insert(key.borrow().to_vec(), None);
+        Ok(val)
+    }
+
+    /// Apply changes in one commit and return its timestamp
+    /// Return timestamp of snapshot if nothing is changed
+    /// # Errors
+    /// Return Conflict if any changed key, or read key and range in serializable level,
+    /// is committed by others after transaction starts, nothing is applied and transaction can be retried
+    pub fn commit(mut self) -> Result<TimeStamp, TdbError> {
+        if self.writes.is_empty() {
+            return Ok(self.timestamp());
+        }
+        // no other commit until writer is dropped
+        let mut mut_ctx = self.store.lock_mut_ctx()?;
+        // read only transaction is serialized at its snapshot
+        if self.store.global_ctx.is_changed_since(
+            self.timestamp(),
+            self.writes.keys().chain(self.read_keys.iter()),
+            &self.read_ranges,
+        ) {
+            debug!("transaction at {:?} conflicts", self.timestamp());
+            return Err(TdbError::Conflict);
+        }
+        // ts is only increased for transaction without conflict
+        mut_ctx.increase_ts();
+        let mut writer = KVWriter(mut_ctx, &self.store.global_ctx);
+        for (key, val) in mem::take(&mut self.writes) {
+            match val {
+                Some(val) => writer.insert(key, val)?,
+                None => {
+                    writer.remove(&key)?;
+                }
+            }
+        }
+        writer.commit()
+    }
+
+    /// Discard all changes, same as dropping it without commit
+    pub fn rollback(self) {}
+}
+
+impl<'a> Drop for Transaction<'a> {
+    fn drop(&mut self) {
+        self.store.global_ctx.end_txn(self.timestamp());
+    }
+}
+
+/// Background thread of SyncMode::Periodic, syncs commits once interval passed since last sync
+/// so that they are durable even if no commit follows
+struct Flusher {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    handle: JoinHandle<()>,
+}
+
+impl Flusher {
+    fn start(mut_ctx: Arc<Mutex<MutContext>>, interval: Duration) -> Self {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let stopped_clone = stopped.clone();
+        let handle = thread::spawn(move || {
+            let (lock, cond) = &*stopped_clone;
+            let mut timeout = interval;
+            loop {
+                {
+                    let mut stopped = lock.lock();
+                    if !*stopped {
+                        cond.wait_for(&mut stopped, timeout);
+                    }
+                    if *stopped {
+                        return;
+                    }
+                }
+                timeout = match mut_ctx.lock().sync_if_due() {
+                    Ok(timeout) => timeout,
+                    Err(err) => {
+                        warn!("periodic sync failed: {:?}", err);
+                        interval
+                    }
+                };
+            }
+        });
+        Self { stopped, handle }
+    }
+
+    fn stop(self) {
+        let (lock, cond) = &*self.stopped;
+        *lock.lock() = true;
+        cond.notify_all();
+        let _ = self.handle.join();
+    }
 }
 
 pub struct KVStore {
     dev: Dev,
     immut_cache: ImMutCache,
     table: Arc<InnerTable>,
-    global_ctx: RwLock<Arc<Context>>,
-    mut_ctx: Mutex<MutContext>,
+    global_ctx: GlobalCtx,
+    // None if store is opened read only
+    mut_ctx: Option<Arc<Mutex<MutContext>>>,
+    // running if sync mode is periodic
+    flusher: Mutex<Option<Flusher>>,
+    // batches waiting for KVStore::write, the first one is the leader of group commit
+    write_queue: Mutex<VecDeque<Arc<PendingWrite>>>,
+    write_cond: Condvar,
+    recovery_report: RecoveryReport,
 }
 
 impl Drop for KVStore {
     fn drop(&mut self) {
+        if let Some(flusher) = self.flusher.get_mut().take() {
+            flusher.stop();
+        }
+        if let Some(mut_ctx) = self.mut_ctx.as_ref() {
+            let mut mut_ctx = mut_ctx.lock();
+            if mut_ctx.get_sync_mode() != SyncMode::None {
+                let _ = mut_ctx.sync();
+            }
+        }
         self.immut_cache.close();
     }
 }
 impl KVStore {
     pub fn get_reader(&self) -> Result<KVReader, TdbError> {
-        let ctx = self.global_ctx.read().clone();
+        self.new_reader(self.global_ctx.get())
+    }
+    /// Return reader whose timestamp is not less than ts, wait until the commit of ts is visible
+    /// # Errors
+    /// Return Timeout if the commit isn't visible in timeout
+    pub fn get_reader_at_least(
+        &self,
+        ts: TimeStamp,
+        timeout: Duration,
+    ) -> Result<KVReader, TdbError> {
+        match self.global_ctx.wait_for(ts, timeout) {
+            Some(ctx) => self.new_reader(ctx),
+            None => Err(TdbError::Timeout),
+        }
+    }
+    /// Start an optimistic transaction of snapshot isolation, multiple transactions can run concurrently
+    /// # Errors
+    /// Return ReadOnly if store is opened by open_read_only
+    pub fn begin_transaction(&self) -> Result<Transaction<'_>, TdbError> {
+        self.begin_transaction_with(IsolationLevel::default())
+    }
+    /// Start an optimistic transaction of isolation level
+    /// # Errors
+    /// Return ReadOnly if store is opened by open_read_only
+    pub fn begin_transaction_with(
+        &self,
+        isolation: IsolationLevel,
+    ) -> Result<Transaction<'_>, TdbError> {
+        if self.is_read_only() {
+            return Err(TdbError::ReadOnly);
+        }
+        let ctx = self.global_ctx.start_txn();
+        let start_ts = ctx.ts;
+        match self.new_reader(ctx) {
+            Ok(reader) => Ok(Transaction {
+                store: self,
+                reader,
+                isolation,
+                writes: BTreeMap::default(),
+                read_keys: BTreeSet::default(),
+                read_ranges: vec![],
+            }),
+            Err(err) => {
+                self.global_ctx.end_txn(start_ts);
+                Err(err)
+            }
+        }
+    }
+    fn new_reader(&self, ctx: Arc<Context>) -> Result<KVReader, TdbError> {
         let table = self.table.clone();
         let data_log_reader = self.dev.get_data_reader()?;
         let cache = self.immut_cache.clone();
         let immut_ctx = ImMutContext::new(ctx.root_oid, ctx.ts, table, data_log_reader, cache);
         Ok(KVReader(immut_ctx, ctx))
     }
-    pub fn get_writer(&self) -> KVWriter {
-        let mut mut_ctx = self.mut_ctx.lock();
+    /// # Errors
+    /// Return ReadOnly if store is opened by open_read_only
+    pub fn get_writer(&self) -> Result<KVWriter<'_>, TdbError> {
+        let mut mut_ctx = self.lock_mut_ctx()?;
         mut_ctx.increase_ts();
-        KVWriter(mut_ctx, &self.global_ctx)
+        Ok(KVWriter(mut_ctx, &self.global_ctx))
+    }
+    /// Apply all changes of batch in one commit, return timestamp of the commit
+    /// Writer lock is only held while applying
+    /// With SyncMode::GroupCommit, batches queued by concurrent callers are merged into one commit
+    /// and each caller returns when the group is committed
+    /// # Errors
+    /// Nothing of batch is applied if any change of it fails, other batches of the group are still committed
+    pub fn write(&self, batch: WriteBatch) -> Result<TimeStamp, TdbError> {
+        let mut_ctx = self.mut_ctx.as_ref().ok_or(TdbError::ReadOnly)?;
+        let pending = Arc::new(PendingWrite::new(batch));
+        let mut queue = self.write_queue.lock();
+        queue.push_back(pending.clone());
+        // wait until committed by leader or become leader
+        loop {
+            if let Some(result) = pending.take_result() {
+                return result;
+            }
+            if queue
+                .front()
+                .is_some_and(|front| Arc::ptr_eq(front, &pending))
+            {
+                break;
+            }
+            self.write_cond.wait(&mut queue);
+        }
+        drop(queue);
+        let mut group = WriteGroup {
+            store: self,
+            mut_ctx: mut_ctx.lock(),
+            results: vec![],
+        };
+        let group_commit = group.mut_ctx.get_sync_mode() == SyncMode::GroupCommit;
+        // batches queued while waiting for writer lock join the group
+        let mut batches = vec![];
+        let mut ops_num = 0;
+        for pending in self.write_queue.lock().iter() {
+            if !batches.is_empty() && (!group_commit || ops_num >= MAX_GROUP_OPS) {
+                break;
+            }
+            let batch = pending.take_batch().unwrap();
+            ops_num += batch.len();
+            batches.push(batch);
+            group.results.push(Err(leader_panicked()));
+        }
+        debug!(
+            "commit group of {:?} batches, {:?} changes",
+            batches.len(),
+            ops_num
+        );
+        group.commit(&batches);
+        group.results[0].clone()
+    }
+    /// Copy live data to new segments and remove the old ones
+    pub fn compact(&self) -> Result<(), TdbError> {
+        let mut mut_ctx = self.lock_mut_ctx()?;
+        let arc_ctx = mut_ctx.compact()?;
+        self.global_ctx.set(arc_ctx, BTreeSet::default());
+        mut_ctx.remove_stale_segments()
+    }
+    /// Set when commits are synced to disk, default is SyncMode::PerCommit
+    /// Ignored if store is opened read only
+    pub fn set_sync_mode(&self, sync_mode: SyncMode) {
+        if let Some(mut_ctx) = self.mut_ctx.as_ref() {
+            mut_ctx.lock().set_sync_mode(sync_mode);
+            self.restart_flusher(sync_mode);
+        }
+    }
+    // Stop flusher of previous mode, and start one if mode is periodic
+    fn restart_flusher(&self, sync_mode: SyncMode) {
+        let mut flusher = self.flusher.lock();
+        if let Some(flusher) = flusher.take() {
+            flusher.stop();
+        }
+        if let (SyncMode::Periodic(interval), Some(mut_ctx)) = (sync_mode, self.mut_ctx.as_ref()) {
+            *flusher = Some(Flusher::start(mut_ctx.clone(), interval));
+        }
+    }
+    /// Sync all committed data to disk, nothing to sync if store is opened read only
+    pub fn sync(&self) -> Result<(), TdbError> {
+        match self.mut_ctx.as_ref() {
+            Some(mut_ctx) => mut_ctx.lock().sync(),
+            None => Ok(()),
+        }
+    }
+    /// Compact automatically after commit when removed size more than ratio of data file
+    /// Auto compaction is disabled by default or if ratio is None, it copies all live data
+    /// while holding writer lock
+    /// Ignored if store is opened read only
+    pub fn set_compact_ratio(&self, ratio: Option<f64>) {
+        if let Some(mut_ctx) = self.mut_ctx.as_ref() {
+            mut_ctx.lock().set_compact_ratio(ratio);
+        }
+    }
+    /// Put vals not longer than size in leaf instead of a separate entry, default is 64 bytes
+    /// Ignored if store is opened read only
+    /// # Panics
+    /// Panic if size is more than MAX_INLINE_VAL_SIZE
+    pub fn set_inline_val_size(&self, size: usize) {
+        if let Some(mut_ctx) = self.mut_ctx.as_ref() {
+            mut_ctx.lock().set_inline_val_size(size);
+        }
+    }
+    /// Return true if store is opened by open_read_only
+    pub fn is_read_only(&self) -> bool {
+        self.mut_ctx.is_none()
+    }
+    #[inline]
+    fn lock_mut_ctx(&self) -> Result<MutexGuard<'_, MutContext>, TdbError> {
+        match self.mut_ctx.as_ref() {
+            Some(mut_ctx) => Ok(mut_ctx.lock()),
+            None => Err(TdbError::ReadOnly),
+        }
+    }
+    /// Return what is recovered when store is opened
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+    /// Return default options, which opens store with them
+    pub fn builder() -> Options {
+        Options::default()
     }
     pub fn open<P: AsRef<Path>>(dir_path: P) -> Result<Self, TdbError> {
+        Self::open_with_options(dir_path, Options::default())
+    }
+    /// Open an existing store for read only, no file is created or written
+    /// get_writer and compact return ReadOnly
+    pub fn open_read_only<P: AsRef<Path>>(dir_path: P) -> Result<Self, TdbError> {
+        Options::default().open_read_only(dir_path)
+    }
+    /// # Errors
+    /// Return OptionsMismatch if node size differs from the one persisted when store is created
+    pub(crate) fn open_with_options<P: AsRef<Path>>(
+        dir_path: P,
+        options: Options,
+    ) -> Result<Self, TdbError> {
         info!("open database at {:?}", dir_path.as_ref());
-        let dev = Dev::open(dir_path)?;
+        let mut dev = Dev::with_options(dir_path, options)?;
 
         let mut meta_log_reader = dev.get_meta_reader()?;
         let checkpoints = meta_log_reader.read_cps()?;
-        if checkpoints.is_empty() {
+        // node size is persisted when store is created, older stores use the default one
+        let node_size = match dev.read_persisted_options()? {
+            Some(persisted) => persisted.node_size as usize,
+            None => {
+                let node_size = if checkpoints.is_empty() {
+                    dev.options.get_node_size()
+                } else {
+                    DEFAULT_NODE_SIZE
+                };
+                if !dev.options.read_only {
+                    let persisted = PersistedOptions {
+                        node_size: node_size as u32,
+                    };
+                    dev.write_persisted_options(&persisted)?;
+                }
+                node_size
+            }
+        };
+        if dev.options.node_size.is_some_and(|size| size != node_size) {
+            warn!(
+                "node size {:?} differs from persisted {:?}",
+                dev.options.node_size, node_size
+            );
+            return Err(TdbError::OptionsMismatch);
+        }
+        dev.options.node_size = Some(node_size);
+        if dev.options.read_only {
+            return Self::open_read_only_with_cps(dev, checkpoints);
+        }
+        // discard everything after the last valid checkpoint
+        let valid_size = checkpoints.iter().map(|cp| cp.size as u64).sum();
+        let bytes_truncated = dev.truncate_meta_log(valid_size)?;
+        if bytes_truncated > 0 {
+            warn!(
+                "truncate {:?} bytes after {:?} valid checkpoints in meta log",
+                bytes_truncated,
+                checkpoints.len()
+            );
+        }
+        let mut recovery_report = RecoveryReport {
+            checkpoints_replayed: checkpoints.len(),
+            bytes_truncated,
+            ..RecoveryReport::default()
+        };
+        let (mut mut_ctx, table, immut_cache) = if checkpoints.is_empty() {
             debug!("checkpoint is empty, create empty database");
-            let (mut_ctx, table, immut_cache) = MutContext::new_empty(dev.clone())?;
-            Ok(Self {
-                dev,
-                immut_cache,
-                table,
-                global_ctx: RwLock::new(Arc::new(Context::default())),
-                mut_ctx: Mutex::new(mut_ctx),
-            })
+            // segments may be left by a crash during the first commit
+            dev.remove_stale_data_segments(0..0)?;
+            MutContext::new_empty(dev.clone())?
         } else {
             debug!("find prev checkpoint, open prev database");
             let cp = CheckPoint::merge(checkpoints);
-            let (mut_ctx, table, immut_cache) = MutContext::new(dev.clone(), cp)?;
-            Ok(Self {
-                dev,
-                immut_cache,
-                table,
-                global_ctx: RwLock::new(Arc::new(Context::default())),
-                mut_ctx: Mutex::new(mut_ctx),
-            })
-        }
+            recovery_report.root_oid = cp.root_oid;
+            info!("recovery complete {:?}", recovery_report);
+            // remove segments left by compaction or crash
+            let last_segment_id = (cp.data_size / SEGMENT_SIZE) as u32;
+            dev.remove_stale_data_segments(cp.first_segment_id..last_segment_id + 1)?;
+            MutContext::new(dev.clone(), cp)?
+        };
+        // readers before first commit see last committed root
+        let ctx = mut_ctx.init_ctx();
+        let sync_mode = dev.options.sync_mode;
+        let kv = Self {
+            dev,
+            immut_cache,
+            table,
+            global_ctx: GlobalCtx::new(ctx),
+            mut_ctx: Some(Arc::new(Mutex::new(mut_ctx))),
+            flusher: Mutex::new(None),
+            write_queue: Mutex::new(VecDeque::default()),
+            write_cond: Condvar::new(),
+            recovery_report,
+        };
+        kv.restart_flusher(sync_mode);
+        Ok(kv)
+    }
+    /// Only readers are constructed, meta log is neither truncated nor written
+    fn open_read_only_with_cps(dev: Dev, checkpoints: Vec<CheckPoint>) -> Result<Self, TdbError> {
+        let mut recovery_report = RecoveryReport {
+            checkpoints_replayed: checkpoints.len(),
+            ..RecoveryReport::default()
+        };
+        let immut_cache = ImMutCache::with_capacity(dev.options.cache_size);
+        let (table, ctx) = if checkpoints.is_empty() {
+            debug!("checkpoint is empty, open empty database");
+            (InnerTable::with_capacity(0), Context::default())
+        } else {
+            let cp = CheckPoint::merge(checkpoints);
+            recovery_report.root_oid = cp.root_oid;
+            let (table, _) = dev.get_table_reader()?.read_table(&cp)?;
+            let ctx = Context {
+                ts: cp.ts,
+                root_oid: cp.root_oid,
+            };
+            (table, ctx)
+        };
+        info!("open read only database {:?}", recovery_report);
+        Ok(Self {
+            dev,
+            immut_cache,
+            table: Arc::new(table),
+            global_ctx: GlobalCtx::new(Arc::new(ctx)),
+            mut_ctx: None,
+            flusher: Mutex::new(None),
+            write_queue: Mutex::new(VecDeque::default()),
+            write_cond: Condvar::new(),
+            recovery_report,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::object::{Entry, DATA_ALIGN, MAX_KEY_SIZE};
+    use crate::storage::ObjectPos;
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Seek, SeekFrom, Write};
+    use std::sync::Barrier;
     use tempfile::tempdir;
     fn init() {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -141,10 +1006,10 @@ mod tests {
         let dir = tempdir().unwrap();
         let kv = KVStore::open(dir.path()).unwrap();
 
-        let mut writer = kv.get_writer();
+        let mut writer = kv.get_writer().unwrap();
         assert_eq!(writer.insert(vec![1, 2, 3], vec![3, 2, 1]), Ok(()));
         assert_eq!(writer.get(&vec![1, 2, 3]), Ok(Some(vec![3, 2, 1])));
-        assert_eq!(writer.commit(), Ok(()));
+        assert!(writer.commit().is_ok());
 
         let mut reader = kv.get_reader().unwrap();
         assert_eq!(reader.get(&vec![1, 2, 3]), Ok(Some(vec![3, 2, 1])));
@@ -156,14 +1021,14 @@ mod tests {
         let dir = tempdir().unwrap();
         let kv = KVStore::open(dir.path()).unwrap();
 
-        let mut writer = kv.get_writer();
+        let mut writer = kv.get_writer().unwrap();
         for i in 0..=255 {
             assert_eq!(writer.insert(vec![i, 1, 1], vec![i, 1, 1]), Ok(()));
         }
         for i in 0..=255 {
             assert_eq!(writer.insert(vec![i, 2, 2], vec![i, 2, 2]), Ok(()));
         }
-        assert_eq!(writer.commit(), Ok(()));
+        assert!(writer.commit().is_ok());
 
         let mut reader = kv.get_reader().unwrap();
         for i in 0..=255 {
@@ -178,13 +1043,13 @@ mod tests {
             Ok(Some((vec![255, 2, 2], vec![255, 2, 2])))
         );
 
-        let mut writer = kv.get_writer();
+        let mut writer = kv.get_writer().unwrap();
         assert_eq!(
             writer.remove(&vec![255, 2, 2]),
             Ok(Some((vec![255, 2, 2], vec![255, 2, 2])))
         );
         assert_eq!(writer.get(&vec![255, 2, 2]), Ok(None));
-        assert_eq!(writer.commit(), Ok(()));
+        assert!(writer.commit().is_ok());
         let mut reader1 = kv.get_reader().unwrap();
         assert_eq!(reader.get(&vec![255, 2, 2]), Ok(Some(vec![255, 2, 2])));
         assert_eq!(reader1.get(&vec![255, 2, 2]), Ok(None));
@@ -195,4 +1060,1479 @@ mod tests {
         assert_eq!(reader0.get(&vec![255, 2, 2]), Ok(None));
     }
 
+    #[test]
+    fn test_kv_compact() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..=255 {
+            assert_eq!(writer.insert(vec![i, 1, 1], vec![i, 1, 1]), Ok(()));
+        }
+        assert!(writer.commit().is_ok());
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..128 {
+            assert!(writer.remove(&vec![i, 1, 1]).unwrap().is_some());
+        }
+        assert!(writer.commit().is_ok());
+
+        let mut reader0 = kv.get_reader().unwrap();
+        assert_eq!(kv.compact(), Ok(()));
+        // old reader still read old segment
+        assert_eq!(kv.dev.list_data_segments(), Ok(vec![0, 1]));
+        assert_eq!(reader0.get(&vec![0, 1, 1]), Ok(None));
+        assert_eq!(reader0.get(&vec![255, 1, 1]), Ok(Some(vec![255, 1, 1])));
+        let mut reader1 = kv.get_reader().unwrap();
+        for i in 0..128 {
+            assert_eq!(reader1.get(&vec![i, 1, 1]), Ok(None));
+        }
+        for i in 128..=255 {
+            assert_eq!(reader1.get(&vec![i, 1, 1]), Ok(Some(vec![i, 1, 1])));
+        }
+        // old segment is removed after old reader dropped
+        drop(reader0);
+        assert!(kv.get_writer().unwrap().commit().is_ok());
+        assert_eq!(kv.dev.list_data_segments(), Ok(vec![1]));
+        assert_eq!(reader1.get(&vec![255, 1, 1]), Ok(Some(vec![255, 1, 1])));
+        // close and re-open
+        drop(reader1);
+        drop(kv);
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        for i in 128..=255 {
+            assert_eq!(writer.get(&vec![i, 1, 1]), Ok(Some(vec![i, 1, 1])));
+        }
+        assert_eq!(writer.insert(vec![0, 1, 1], vec![0, 1, 1]), Ok(()));
+        assert!(writer.commit().is_ok());
+        let mut reader = kv.get_reader().unwrap();
+        assert_eq!(reader.get(&vec![0, 1, 1]), Ok(Some(vec![0, 1, 1])));
+        assert_eq!(reader.get(&vec![200, 1, 1]), Ok(Some(vec![200, 1, 1])));
+    }
+
+    #[test]
+    fn test_kv_compact_failed() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..=255 {
+            assert_eq!(writer.insert(vec![i, 1, 1], vec![i, 1, 1]), Ok(()));
+        }
+        assert!(writer.commit().is_ok());
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.insert(vec![0, 2], vec![2; 10000]), Ok(()));
+        assert!(writer.commit().is_ok());
+        let segment_path = kv.dev.data_segment_path(0);
+        drop(kv);
+        // val is written after nodes, so compaction fails after nodes are copied
+        let data = fs::read(&segment_path).unwrap();
+        fs::write(&segment_path, &data[..data.len() - 8000]).unwrap();
+
+        let kv = KVStore::open(dir.path()).unwrap();
+        assert!(matches!(kv.compact(), Err(TdbError::IoError(_))));
+        // nothing is changed by failed compaction
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.get(&vec![200, 1, 1]), Ok(Some(vec![200, 1, 1])));
+        assert_eq!(writer.insert(vec![1, 2], vec![1, 2]), Ok(()));
+        assert!(writer.commit().is_ok());
+        assert_eq!(kv.dev.list_data_segments(), Ok(vec![0, 1]));
+        drop(kv);
+
+        fs::write(&segment_path, &data).unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        assert_eq!(kv.compact(), Ok(()));
+        assert_eq!(kv.dev.list_data_segments(), Ok(vec![2]));
+        let mut reader = kv.get_reader().unwrap();
+        assert_eq!(reader.get(&vec![0, 2]), Ok(Some(vec![2; 10000])));
+        assert_eq!(reader.get(&vec![1, 2]), Ok(Some(vec![1, 2])));
+        for i in 0..=255 {
+            assert_eq!(reader.get(&vec![i, 1, 1]), Ok(Some(vec![i, 1, 1])));
+        }
+    }
+
+    #[test]
+    fn test_kv_auto_compact() {
+        init();
+        let fill = |kv: &KVStore| {
+            let mut writer = kv.get_writer().unwrap();
+            for i in 0..=255u8 {
+                assert_eq!(writer.insert(vec![i], vec![i; 1 << 15]), Ok(()));
+            }
+            assert!(writer.commit().is_ok());
+            let mut writer = kv.get_writer().unwrap();
+            for i in 0..192u8 {
+                assert!(writer.remove(&vec![i]).unwrap().is_some());
+            }
+            assert!(writer.commit().is_ok());
+        };
+        // disabled by default
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        fill(&kv);
+        assert_eq!(kv.dev.list_data_segments(), Ok(vec![0]));
+
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        kv.set_compact_ratio(Some(0.5));
+        fill(&kv);
+        assert_eq!(kv.dev.list_data_segments(), Ok(vec![1]));
+        let mut reader = kv.get_reader().unwrap();
+        assert_eq!(reader.get(&vec![0]), Ok(None));
+        assert_eq!(reader.get(&vec![255]), Ok(Some(vec![255; 1 << 15])));
+    }
+
+    #[test]
+    fn test_kv_sync_mode() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let modes = [
+            SyncMode::None,
+            SyncMode::PerCommit,
+            SyncMode::Periodic(Duration::from_secs(60)),
+            SyncMode::GroupCommit,
+        ];
+        for (i, mode) in modes.iter().enumerate() {
+            kv.set_sync_mode(*mode);
+            let mut writer = kv.get_writer().unwrap();
+            assert_eq!(writer.insert(vec![i as u8], vec![i as u8]), Ok(()));
+            assert!(writer.commit().is_ok());
+        }
+        assert_eq!(kv.sync(), Ok(()));
+        // commit is synced in background even if no commit follows
+        kv.set_sync_mode(SyncMode::Periodic(Duration::from_millis(200)));
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.insert(vec![10], vec![10]), Ok(()));
+        assert!(writer.commit().is_ok());
+        let mut_ctx = kv.mut_ctx.clone().unwrap();
+        assert!(mut_ctx.lock().has_unsynced());
+        thread::sleep(Duration::from_millis(600));
+        assert!(!mut_ctx.lock().has_unsynced());
+        kv.set_sync_mode(SyncMode::None);
+        assert!(kv.flusher.lock().is_none());
+        // close and re-open
+        drop(kv);
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..modes.len() {
+            assert_eq!(writer.get(&vec![i as u8]), Ok(Some(vec![i as u8])));
+        }
+    }
+
+    #[test]
+    fn test_kv_recovery() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        assert_eq!(kv.recovery_report(), &RecoveryReport::default());
+        for i in 0..3 {
+            let mut writer = kv.get_writer().unwrap();
+            assert_eq!(writer.insert(vec![i], vec![i]), Ok(()));
+            assert!(writer.commit().is_ok());
+        }
+        let meta_log_file_path = kv.dev.meta_log_file_path.clone();
+        drop(kv);
+        // torn tail of last checkpoint
+        let meta_size = fs::metadata(&meta_log_file_path).unwrap().len();
+        let file = OpenOptions::new()
+            .write(true)
+            .open(&meta_log_file_path)
+            .unwrap();
+        file.set_len(meta_size - 3).unwrap();
+        drop(file);
+        let kv = KVStore::open(dir.path()).unwrap();
+        let report = kv.recovery_report().clone();
+        assert_eq!(report.checkpoints_replayed, 2);
+        assert!(report.bytes_truncated > 0);
+        assert_ne!(report.root_oid, UNUSED_OID);
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.get(&vec![1]), Ok(Some(vec![1])));
+        assert_eq!(writer.get(&vec![2]), Ok(None));
+        assert_eq!(writer.insert(vec![3], vec![3]), Ok(()));
+        assert!(writer.commit().is_ok());
+        drop(kv);
+        // garbage after valid checkpoints
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&meta_log_file_path)
+            .unwrap();
+        file.write_all(&[1; 100]).unwrap();
+        drop(file);
+        let kv = KVStore::open(dir.path()).unwrap();
+        assert_eq!(kv.recovery_report().checkpoints_replayed, 3);
+        assert_eq!(kv.recovery_report().bytes_truncated, 100);
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.get(&vec![3]), Ok(Some(vec![3])));
+        drop(writer);
+        drop(kv);
+        let kv = KVStore::open(dir.path()).unwrap();
+        assert_eq!(kv.recovery_report().bytes_truncated, 0);
+    }
+
+    #[test]
+    fn test_kv_corrupted_meta_log() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        for i in 0..3 {
+            let mut writer = kv.get_writer().unwrap();
+            assert_eq!(writer.insert(vec![i], vec![i]), Ok(()));
+            assert!(writer.commit().is_ok());
+        }
+        let meta_log_file_path = kv.dev.meta_log_file_path.clone();
+        drop(kv);
+        let meta_log = fs::read(&meta_log_file_path).unwrap();
+        let mut first_size = [0; 4];
+        first_size.copy_from_slice(&meta_log[..4]);
+        let first_size = u32::from_le_bytes(first_size) as usize;
+        let corruption = Some(TdbError::Corruption {
+            oid: UNUSED_OID,
+            pos: ObjectPos::default(),
+        });
+        // corrupted first checkpoint and checkpoint in the middle aren't truncated
+        for offset in [10, first_size + 10] {
+            let mut corrupted = meta_log.clone();
+            corrupted[offset] ^= 1;
+            fs::write(&meta_log_file_path, &corrupted).unwrap();
+            assert_eq!(KVStore::open(dir.path()).err(), corruption);
+            assert_eq!(KVStore::open_read_only(dir.path()).err(), corruption);
+            assert_eq!(fs::read(&meta_log_file_path).unwrap(), corrupted);
+        }
+        // checkpoint of the first format, without first_segment_id, ts and checksum
+        let baseline: Vec<u8> = [40u32, 0xFAFA_FAFA]
+            .iter()
+            .flat_map(|n| n.to_le_bytes())
+            .chain(0u64.to_le_bytes())
+            .chain(0u64.to_le_bytes())
+            .chain([UNUSED_OID, 40, 0, 0].iter().flat_map(|n| n.to_le_bytes()))
+            .collect();
+        fs::write(&meta_log_file_path, &baseline).unwrap();
+        assert_eq!(
+            KVStore::open(dir.path()).err(),
+            Some(TdbError::UnsupportedFormat)
+        );
+        assert_eq!(fs::read(&meta_log_file_path).unwrap(), baseline);
+        fs::write(&meta_log_file_path, &meta_log).unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        assert_eq!(kv.recovery_report().bytes_truncated, 0);
+        assert_eq!(kv.get_reader().unwrap().get(&vec![2]), Ok(Some(vec![2])));
+    }
+
+    #[test]
+    fn test_kv_torn_first_checkpoint() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.insert(vec![1], vec![1; 100]), Ok(()));
+        assert!(writer.commit().is_ok());
+        let meta_log_file_path = kv.dev.meta_log_file_path.clone();
+        drop(kv);
+        let meta_log = fs::read(&meta_log_file_path).unwrap();
+        // crash during the first commit leaves an empty store
+        for len in [2, 4, meta_log.len() / 2, meta_log.len() - 1] {
+            fs::write(&meta_log_file_path, &meta_log[..len]).unwrap();
+            let kv = KVStore::open_read_only(dir.path()).unwrap();
+            assert_eq!(kv.get_reader().unwrap().get(&vec![1]), Ok(None));
+            drop(kv);
+            let kv = KVStore::open(dir.path()).unwrap();
+            assert_eq!(
+                kv.recovery_report(),
+                &RecoveryReport {
+                    bytes_truncated: len as u64,
+                    ..RecoveryReport::default()
+                }
+            );
+            assert_eq!(fs::metadata(&meta_log_file_path).unwrap().len(), 0);
+            assert_eq!(kv.get_reader().unwrap().get(&vec![1]), Ok(None));
+            let mut writer = kv.get_writer().unwrap();
+            assert_eq!(writer.insert(vec![2], vec![2; 100]), Ok(()));
+            assert!(writer.commit().is_ok());
+            drop(kv);
+            let kv = KVStore::open(dir.path()).unwrap();
+            assert_eq!(kv.recovery_report().checkpoints_replayed, 1);
+            let mut reader = kv.get_reader().unwrap();
+            assert_eq!(reader.get(&vec![1]), Ok(None));
+            assert_eq!(reader.get(&vec![2]), Ok(Some(vec![2; 100])));
+        }
+    }
+
+    #[test]
+    fn test_kv_range() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut reader = kv.get_reader().unwrap();
+        assert_eq!(reader.iter().unwrap().count(), 0);
+
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..2000u16 {
+            let key = i.to_be_bytes().to_vec();
+            assert_eq!(writer.insert(key.clone(), key), Ok(()));
+        }
+        assert!(writer.commit().is_ok());
+
+        let mut reader = kv.get_reader().unwrap();
+        let keys: Vec<Key> = reader.iter().unwrap().map(|kv| kv.unwrap().0).collect();
+        assert_eq!(keys.len(), 2000);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        let start = 100u16.to_be_bytes();
+        let end = 1500u16.to_be_bytes();
+        let range: Vec<(Key, Val)> = reader
+            .range(&start[..]..&end[..])
+            .unwrap()
+            .map(|kv| kv.unwrap())
+            .collect();
+        assert_eq!(range.len(), 1400);
+        assert_eq!(range[0], (start.to_vec(), start.to_vec()));
+        assert_eq!(range[1399].0, 1499u16.to_be_bytes().to_vec());
+        assert_eq!(reader.range(&start[..]..=&end[..]).unwrap().count(), 1401);
+        assert_eq!(reader.range(&end[..]..).unwrap().count(), 500);
+        assert_eq!(reader.range(..&start[..]).unwrap().count(), 100);
+        // key between keys
+        assert_eq!(reader.range(vec![0, 0, 0]..vec![0, 2]).unwrap().count(), 1);
+        // reverse
+        let rev_keys: Vec<Key> = reader
+            .range_rev::<Key, _>(..)
+            .unwrap()
+            .map(|kv| kv.unwrap().0)
+            .collect();
+        assert_eq!(rev_keys, keys.iter().rev().cloned().collect::<Vec<_>>());
+        let latest: Vec<Key> = reader
+            .range_rev(..&end[..])
+            .unwrap()
+            .take(3)
+            .map(|kv| kv.unwrap().0)
+            .collect();
+        assert_eq!(
+            latest,
+            vec![
+                1499u16.to_be_bytes().to_vec(),
+                1498u16.to_be_bytes().to_vec(),
+                1497u16.to_be_bytes().to_vec()
+            ]
+        );
+        assert_eq!(
+            reader.range_rev(&start[..]..=&end[..]).unwrap().count(),
+            1401
+        );
+        // both ends meet
+        let mut iter = reader.range(&start[..]..&end[..]).unwrap();
+        let mut count = 0;
+        loop {
+            let front = iter.next();
+            let back = iter.next_back();
+            if let Some(front) = front {
+                count += 1;
+                if let Some(back) = back {
+                    count += 1;
+                    assert!(front.unwrap().0 < back.unwrap().0);
+                }
+            } else {
+                assert!(back.is_none());
+                break;
+            }
+        }
+        assert_eq!(count, 1400);
+    }
+
+    #[test]
+    fn test_kv_scan_prefix() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.scan_prefix(&[]).unwrap().count(), 0);
+        for i in 0..2000u16 {
+            let key = i.to_be_bytes().to_vec();
+            assert_eq!(writer.insert(key.clone(), key), Ok(()));
+        }
+        for key in [
+            vec![0x05, 0xFF, 0],
+            vec![0xFF],
+            vec![0xFF, 0xFF],
+            vec![0xFF, 0xFF, 1],
+        ] {
+            assert_eq!(writer.insert(key.clone(), key), Ok(()));
+        }
+        assert_eq!(writer.scan_prefix(&[3]).unwrap().count(), 256);
+        assert!(writer.commit().is_ok());
+
+        let mut reader = kv.get_reader().unwrap();
+        let keys: Vec<Key> = reader
+            .scan_prefix(&[3])
+            .unwrap()
+            .map(|kv| kv.unwrap().0)
+            .collect();
+        assert_eq!(keys.len(), 256);
+        assert!(keys.iter().all(|key| key[0] == 3));
+        let keys: Vec<Key> = reader
+            .scan_prefix(&[0x05, 0xFF])
+            .unwrap()
+            .map(|kv| kv.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![vec![0x05, 0xFF], vec![0x05, 0xFF, 0]]);
+        assert_eq!(reader.scan_prefix(&[0xFF]).unwrap().count(), 3);
+        assert_eq!(reader.scan_prefix(&[0xFF, 0xFF]).unwrap().count(), 2);
+        assert_eq!(reader.scan_prefix(&[0x08]).unwrap().count(), 0);
+        assert_eq!(reader.scan_prefix(&[]).unwrap().count(), 2004);
+
+        // changes not committed are visible to writer only
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.insert(vec![0xFF, 0xFF, 0xFF], vec![]), Ok(()));
+        assert!(writer.remove(&vec![0xFF]).unwrap().is_some());
+        let keys: Vec<Key> = writer
+            .scan_prefix(&[0xFF])
+            .unwrap()
+            .map(|kv| kv.unwrap().0)
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                vec![0xFF, 0xFF],
+                vec![0xFF, 0xFF, 1],
+                vec![0xFF, 0xFF, 0xFF]
+            ]
+        );
+        assert_eq!(reader.scan_prefix(&[0xFF]).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_kv_writer_range() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.iter().unwrap().count(), 0);
+        for i in 0..1000u16 {
+            let key = i.to_be_bytes().to_vec();
+            assert_eq!(writer.insert(key, vec![0]), Ok(()));
+        }
+        assert_eq!(writer.iter().unwrap().count(), 1000);
+        assert!(writer.commit().is_ok());
+
+        // read-modify-write over a range
+        let mut writer = kv.get_writer().unwrap();
+        let start = 100u16.to_be_bytes();
+        let end = 200u16.to_be_bytes();
+        let range: Vec<(Key, Val)> = writer
+            .range(&start[..]..&end[..])
+            .unwrap()
+            .map(|kv| kv.unwrap())
+            .collect();
+        assert_eq!(range.len(), 100);
+        for (key, val) in range {
+            assert_eq!(val, vec![0]);
+            assert_eq!(writer.insert(key, vec![1]), Ok(()));
+        }
+        for i in 150..250u16 {
+            assert!(writer.remove(&i.to_be_bytes()).unwrap().is_some());
+        }
+        for i in 1000..1100u16 {
+            assert_eq!(writer.insert(i.to_be_bytes().to_vec(), vec![2]), Ok(()));
+        }
+        let vals: Vec<Val> = writer
+            .range(&start[..]..)
+            .unwrap()
+            .map(|kv| kv.unwrap().1)
+            .collect();
+        assert_eq!(vals.len(), 50 + 750 + 100);
+        assert!(vals[..50].iter().all(|val| val == &vec![1]));
+        assert!(vals[50..800].iter().all(|val| val == &vec![0]));
+        assert!(vals[800..].iter().all(|val| val == &vec![2]));
+        let keys: Vec<Key> = writer.iter().unwrap().map(|kv| kv.unwrap().0).collect();
+        assert_eq!(keys.len(), 1000);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+        // reader does not see changes not committed
+        let mut reader = kv.get_reader().unwrap();
+        assert_eq!(reader.iter().unwrap().count(), 1000);
+        assert!(reader.iter().unwrap().all(|kv| kv.unwrap().1 == vec![0]));
+        assert!(writer.commit().is_ok());
+    }
+
+    #[test]
+    fn test_kv_rollback() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        // rollback on empty tree
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.insert(vec![1], vec![1]), Ok(()));
+        writer.rollback();
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.get(&vec![1]), Ok(None));
+        assert_eq!(writer.iter().unwrap().count(), 0);
+
+        for i in 0..1000u16 {
+            let key = i.to_be_bytes().to_vec();
+            assert_eq!(writer.insert(key, vec![0]), Ok(()));
+        }
+        assert!(writer.commit().is_ok());
+
+        // abort on drop
+        {
+            let mut writer = kv.get_writer().unwrap();
+            for i in 0..500u16 {
+                assert!(writer.remove(&i.to_be_bytes()).unwrap().is_some());
+            }
+            for i in 500..1000u16 {
+                assert_eq!(writer.insert(i.to_be_bytes().to_vec(), vec![1]), Ok(()));
+            }
+            for i in 1000..2000u16 {
+                assert_eq!(writer.insert(i.to_be_bytes().to_vec(), vec![2]), Ok(()));
+            }
+        }
+        let mut writer = kv.get_writer().unwrap();
+        let vals: Vec<Val> = writer.iter().unwrap().map(|kv| kv.unwrap().1).collect();
+        assert_eq!(vals, vec![vec![0]; 1000]);
+        for i in 1000..1500u16 {
+            assert_eq!(writer.insert(i.to_be_bytes().to_vec(), vec![3]), Ok(()));
+        }
+        assert!(writer.commit().is_ok());
+        drop(kv);
+
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        let vals: Vec<Val> = writer.iter().unwrap().map(|kv| kv.unwrap().1).collect();
+        assert_eq!(vals.len(), 1500);
+        assert!(vals[..1000].iter().all(|val| val == &vec![0]));
+        assert!(vals[1000..].iter().all(|val| val == &vec![3]));
+    }
+
+    #[test]
+    fn test_kv_large_val() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let large_val = |i: u8, len: usize| (0..len).map(|j| (j as u8) ^ i).collect::<Val>();
+        let sizes = [0, 65000, 65535, 65536, 200_000, 500_000];
+        let mut writer = kv.get_writer().unwrap();
+        for (i, size) in sizes.iter().enumerate() {
+            let i = i as u8;
+            assert_eq!(writer.insert(vec![i], large_val(i, *size)), Ok(()));
+        }
+        for (i, size) in sizes.iter().enumerate() {
+            let i = i as u8;
+            assert_eq!(writer.get(&vec![i]), Ok(Some(large_val(i, *size))));
+        }
+        assert!(writer.commit().is_ok());
+
+        let mut reader = kv.get_reader().unwrap();
+        for (i, size) in sizes.iter().enumerate() {
+            let i = i as u8;
+            assert_eq!(reader.get(&vec![i]), Ok(Some(large_val(i, *size))));
+        }
+        let vals: Vec<Val> = reader.iter().unwrap().map(|kv| kv.unwrap().1).collect();
+        assert_eq!(vals.iter().map(|val| val.len()).collect::<Vec<_>>(), sizes);
+
+        // shrink, grow and remove
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.insert(vec![5], vec![5]), Ok(()));
+        assert_eq!(writer.insert(vec![0], large_val(0, 300_000)), Ok(()));
+        assert_eq!(
+            writer.remove(&vec![4]),
+            Ok(Some((vec![4], large_val(4, 200_000))))
+        );
+        assert!(writer.commit().is_ok());
+        // old reader still see old vals
+        assert_eq!(reader.get(&vec![4]), Ok(Some(large_val(4, 200_000))));
+        assert_eq!(reader.get(&vec![5]), Ok(Some(large_val(5, 500_000))));
+        drop(reader);
+
+        assert_eq!(kv.compact(), Ok(()));
+        drop(kv);
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.get(&vec![0]), Ok(Some(large_val(0, 300_000))));
+        assert_eq!(writer.get(&vec![3]), Ok(Some(large_val(3, 65536))));
+        assert_eq!(writer.get(&vec![4]), Ok(None));
+        assert_eq!(writer.get(&vec![5]), Ok(Some(vec![5])));
+    }
+
+    #[test]
+    fn test_kv_stream_val() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let large_val = |i: u8, len: usize| (0..len).map(|j| (j as u8) ^ i).collect::<Val>();
+        let mut writer = kv.get_writer().unwrap();
+        let val = large_val(1, 1_000_000);
+        assert_eq!(
+            writer.insert_from(vec![1], val.as_slice(), val.len() as u64),
+            Ok(())
+        );
+        assert_eq!(writer.insert_from(vec![2], &[2u8][..], 1), Ok(()));
+        assert_eq!(writer.insert(vec![3], large_val(3, 100_000)), Ok(()));
+        // written objects are visible before commit
+        assert_eq!(writer.get(&vec![1]), Ok(Some(val.clone())));
+        // reader is shorter than len
+        assert!(matches!(
+            writer.insert_from(vec![4], val.as_slice(), 2_000_000),
+            Err(TdbError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+        ));
+        assert_eq!(writer.get(&vec![4]), Ok(None));
+        assert!(writer.commit().is_ok());
+
+        let mut reader = kv.get_reader().unwrap();
+        assert!(reader.get_reader(&vec![4]).unwrap().is_none());
+        let mut val_reader = reader.get_reader(&vec![1]).unwrap().unwrap();
+        let mut buf = vec![];
+        assert_eq!(val_reader.read_to_end(&mut buf).unwrap(), val.len());
+        assert_eq!(buf, val);
+        assert_eq!(val_reader.seek(SeekFrom::End(-10)).unwrap(), 999_990);
+        buf.clear();
+        assert_eq!(val_reader.read_to_end(&mut buf).unwrap(), 10);
+        assert_eq!(buf, &val[999_990..]);
+        assert_eq!(val_reader.seek(SeekFrom::Start(70000)).unwrap(), 70000);
+        let mut buf = vec![0; 200_000];
+        val_reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, &val[70000..270_000]);
+        assert_eq!(val_reader.seek(SeekFrom::Current(-1)).unwrap(), 269_999);
+        assert!(val_reader.seek(SeekFrom::Current(-300_000)).is_err());
+        let mut buf = vec![];
+        let mut val_reader = reader.get_reader(&vec![2]).unwrap().unwrap();
+        assert_eq!(val_reader.get_len(), Ok(1));
+        assert_eq!(val_reader.read_to_end(&mut buf).unwrap(), 1);
+        let mut val_reader = reader.get_reader(&vec![3]).unwrap().unwrap();
+        assert_eq!(val_reader.get_len(), Ok(100_000));
+        drop(reader);
+
+        // replace streamed val and reopen
+        let mut writer = kv.get_writer().unwrap();
+        let val = large_val(5, 300_000);
+        assert_eq!(
+            writer.insert_from(vec![1], val.as_slice(), val.len() as u64),
+            Ok(())
+        );
+        assert!(writer.commit().is_ok());
+        assert_eq!(kv.compact(), Ok(()));
+        // streamed vals are aligned once by commit, not one by one
+        let data_size = || -> u64 {
+            kv.dev
+                .list_data_segments()
+                .unwrap()
+                .into_iter()
+                .map(|id| fs::metadata(kv.dev.data_segment_path(id)).unwrap().len())
+                .sum()
+        };
+        let size = data_size();
+        // entry is full and the rest of val is in an overflow of 1000 bytes
+        let len = Entry::max_val_size(1) + 1000;
+        let mut writer = kv.get_writer().unwrap();
+        for i in 10..30 {
+            let val = large_val(i, len);
+            assert_eq!(
+                writer.insert_from(vec![i], val.as_slice(), len as u64),
+                Ok(())
+            );
+        }
+        assert!(writer.commit().is_ok());
+        assert!(data_size() - size < 20 * (len + 100) as u64 + 4 * DATA_ALIGN as u64);
+        drop(kv);
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.get(&vec![1]), Ok(Some(val)));
+        assert_eq!(writer.get(&vec![2]), Ok(Some(vec![2])));
+        assert_eq!(writer.get(&vec![29]), Ok(Some(large_val(29, len))));
+    }
+
+    #[test]
+    fn test_kv_long_key() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        // key len is between 2 and 4096, keys are sorted by i
+        let long_key = |i: u16| {
+            let mut key = i.to_be_bytes().to_vec();
+            key.resize((i as usize * 997) % 4095 + 2, i as u8);
+            key
+        };
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..500 {
+            assert_eq!(writer.insert(long_key(i), vec![i as u8]), Ok(()));
+        }
+        assert_eq!(
+            writer.insert(vec![0; MAX_KEY_SIZE as usize + 1], vec![1]),
+            Err(TdbError::ObjectTooBig)
+        );
+        assert!(writer.commit().is_ok());
+
+        let mut reader = kv.get_reader().unwrap();
+        for i in 0..500 {
+            assert_eq!(reader.get(&long_key(i)), Ok(Some(vec![i as u8])));
+        }
+        let keys: Vec<Key> = reader.iter().unwrap().map(|kv| kv.unwrap().0).collect();
+        assert_eq!(keys, (0..500).map(long_key).collect::<Vec<_>>());
+        drop(reader);
+
+        let mut writer = kv.get_writer().unwrap();
+        for i in (0..500).step_by(2) {
+            assert_eq!(
+                writer.remove(&long_key(i)),
+                Ok(Some((long_key(i), vec![i as u8])))
+            );
+        }
+        assert!(writer.commit().is_ok());
+        assert_eq!(kv.compact(), Ok(()));
+        drop(kv);
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..500 {
+            let val = if i % 2 == 0 {
+                None
+            } else {
+                Some(vec![i as u8])
+            };
+            assert_eq!(writer.get(&long_key(i)), Ok(val));
+        }
+        for i in (1..500).step_by(2) {
+            assert!(writer.remove(&long_key(i)).unwrap().is_some());
+        }
+        assert_eq!(writer.iter().unwrap().count(), 0);
+        assert!(writer.commit().is_ok());
+    }
+
+    #[test]
+    fn test_kv_shared_prefix_key() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let prefix_key = |i: u32| {
+            let mut key = vec![1; 1000];
+            key.extend_from_slice(&i.to_be_bytes());
+            key
+        };
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..1000 {
+            assert_eq!(writer.insert(prefix_key(i), i.to_le_bytes()), Ok(()));
+        }
+        assert!(writer.commit().is_ok());
+        drop(kv);
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        let keys: Vec<Key> = writer.iter().unwrap().map(|kv| kv.unwrap().0).collect();
+        assert_eq!(keys, (0..1000).map(prefix_key).collect::<Vec<_>>());
+        for i in (0..1000).step_by(2) {
+            assert_eq!(
+                writer.remove(&prefix_key(i)),
+                Ok(Some((prefix_key(i), i.to_le_bytes().to_vec())))
+            );
+        }
+        assert!(writer.commit().is_ok());
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..1000u32 {
+            let val = if i % 2 == 0 {
+                None
+            } else {
+                Some(i.to_le_bytes().to_vec())
+            };
+            assert_eq!(writer.get(&prefix_key(i)), Ok(val));
+        }
+    }
+
+    #[test]
+    fn test_kv_inline_val() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        kv.set_inline_val_size(16);
+        // vals of odd keys are inlined
+        let val = |i: u32, len: u32| vec![i as u8; len as usize];
+        let len = |i: u32| {
+            if i.is_multiple_of(2) {
+                17 + i % 100
+            } else {
+                i % 17
+            }
+        };
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..2000u32 {
+            assert_eq!(writer.insert(i.to_be_bytes(), val(i, len(i))), Ok(()));
+        }
+        assert_eq!(writer.get(&3u32.to_be_bytes()), Ok(Some(val(3, 3))));
+        assert!(writer.commit().is_ok());
+
+        let mut reader = kv.get_reader().unwrap();
+        for i in 0..2000u32 {
+            assert_eq!(reader.get(&i.to_be_bytes()), Ok(Some(val(i, len(i)))));
+        }
+        let vals: Vec<Val> = reader.iter().unwrap().map(|kv| kv.unwrap().1).collect();
+        assert_eq!(vals, (0..2000).map(|i| val(i, len(i))).collect::<Vec<_>>());
+        let vals: Vec<Val> = reader
+            .range_rev::<[u8; 4], _>(..)
+            .unwrap()
+            .map(|kv| kv.unwrap().1)
+            .collect();
+        assert_eq!(vals.len(), 2000);
+        assert_eq!(vals[0], val(1999, len(1999)));
+        assert_eq!(
+            reader.get_min(),
+            Ok(Some((0u32.to_be_bytes().to_vec(), val(0, 17))))
+        );
+        let mut val_reader = reader.get_reader(&9u32.to_be_bytes()).unwrap().unwrap();
+        assert_eq!(val_reader.get_len(), Ok(9));
+        assert_eq!(val_reader.seek(SeekFrom::Start(4)).unwrap(), 4);
+        let mut buf = vec![];
+        assert_eq!(val_reader.read_to_end(&mut buf).unwrap(), 5);
+        assert_eq!(buf, val(9, 5));
+        drop(reader);
+
+        // move vals between leaf and entry, and remove some of them
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..2000u32 {
+            if i % 3 == 0 {
+                assert_eq!(
+                    writer.remove(&i.to_be_bytes()),
+                    Ok(Some((i.to_be_bytes().to_vec(), val(i, len(i)))))
+                );
+            } else {
+                assert_eq!(writer.insert(i.to_be_bytes(), val(i, len(i + 1))), Ok(()));
+            }
+        }
+        assert_eq!(writer.insert_from(vec![0], &val(0, 16)[..], 16), Ok(()));
+        assert!(writer.commit().is_ok());
+        assert_eq!(kv.compact(), Ok(()));
+        drop(kv);
+
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..2000u32 {
+            let expected = if i % 3 == 0 {
+                None
+            } else {
+                Some(val(i, len(i + 1)))
+            };
+            assert_eq!(writer.get(&i.to_be_bytes()), Ok(expected));
+        }
+        assert_eq!(writer.get(&vec![0]), Ok(Some(val(0, 16))));
+        assert_eq!(writer.iter().unwrap().count(), 1334);
+    }
+
+    #[test]
+    fn test_kv_options() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::builder()
+            .cache_size(16)
+            .meta_log_max_size(4096)
+            .data_buffer_size(512)
+            .meta_buffer_size(512)
+            .table_buffer_size(512)
+            .node_size(16384)
+            .sync_mode(SyncMode::None)
+            .compact_ratio(0.2)
+            .inline_val_size(0)
+            .open(dir.path())
+            .unwrap();
+        for i in 0..20u32 {
+            let mut writer = kv.get_writer().unwrap();
+            for j in 0..500u32 {
+                let key = (i * 500 + j).to_be_bytes();
+                assert_eq!(writer.insert(key, key), Ok(()));
+            }
+            assert!(writer.commit().is_ok());
+        }
+        let mut reader = kv.get_reader().unwrap();
+        assert_eq!(reader.iter().unwrap().count(), 10000);
+        drop(reader);
+        drop(kv);
+
+        // node size is persisted
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        for i in (0..10000u32).step_by(2) {
+            let key = i.to_be_bytes();
+            assert_eq!(writer.remove(&key), Ok(Some((key.to_vec(), key.to_vec()))));
+        }
+        assert!(writer.commit().is_ok());
+        drop(kv);
+        assert!(matches!(
+            KVStore::builder().node_size(4096).open(dir.path()),
+            Err(TdbError::OptionsMismatch)
+        ));
+        let kv = KVStore::builder()
+            .node_size(16384)
+            .open(dir.path())
+            .unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..10000u32 {
+            let key = i.to_be_bytes();
+            let val = if i % 2 == 0 { None } else { Some(key.to_vec()) };
+            assert_eq!(writer.get(&key), Ok(val));
+        }
+        drop(writer);
+        drop(kv);
+
+        // store created before options are persisted uses default node size
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.insert(vec![1], vec![1]), Ok(()));
+        assert!(writer.commit().is_ok());
+        drop(kv);
+        fs::remove_file(dir.path().join("options.db")).unwrap();
+        assert!(matches!(
+            KVStore::builder().node_size(8192).open(dir.path()),
+            Err(TdbError::OptionsMismatch)
+        ));
+        let kv = KVStore::builder().node_size(4096).open(dir.path()).unwrap();
+        assert_eq!(kv.get_writer().unwrap().get(&vec![1]), Ok(Some(vec![1])));
+        drop(kv);
+
+        // periodic sync mode starts background flusher on open
+        let kv = KVStore::builder()
+            .sync_mode(SyncMode::Periodic(Duration::from_millis(100)))
+            .open(dir.path())
+            .unwrap();
+        assert!(kv.flusher.lock().is_some());
+    }
+
+    #[test]
+    fn test_kv_read_only() {
+        init();
+        let dir = tempdir().unwrap();
+        assert!(KVStore::open_read_only(dir.path().join("not_exist")).is_err());
+        // empty store
+        let kv = KVStore::open(dir.path()).unwrap();
+        drop(kv);
+        let kv = KVStore::open_read_only(dir.path()).unwrap();
+        assert!(kv.is_read_only());
+        assert_eq!(kv.get_reader().unwrap().get_min(), Ok(None));
+        drop(kv);
+
+        let kv = KVStore::open(dir.path()).unwrap();
+        assert!(matches!(
+            KVStore::open_read_only(dir.path()),
+            Err(TdbError::Locked)
+        ));
+        for i in 0..10u32 {
+            let mut writer = kv.get_writer().unwrap();
+            for j in 0..100u32 {
+                let key = (i * 100 + j).to_be_bytes();
+                assert_eq!(writer.insert(key, key), Ok(()));
+            }
+            assert!(writer.commit().is_ok());
+        }
+        drop(kv);
+        // torn tail is kept, options file is not created
+        let meta_log_path = dir.path().join("meta_log_file.db");
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&meta_log_path)
+            .unwrap();
+        file.write_all(&[1, 2, 3]).unwrap();
+        drop(file);
+        fs::remove_file(dir.path().join("options.db")).unwrap();
+        let meta_log = fs::read(&meta_log_path).unwrap();
+
+        let kv = KVStore::open_read_only(dir.path()).unwrap();
+        assert_eq!(kv.recovery_report().checkpoints_replayed, 10);
+        // shared with other read only stores, exclusive with writable one
+        assert!(KVStore::open_read_only(dir.path()).is_ok());
+        assert!(matches!(KVStore::open(dir.path()), Err(TdbError::Locked)));
+        assert!(matches!(kv.get_writer(), Err(TdbError::ReadOnly)));
+        assert_eq!(kv.compact(), Err(TdbError::ReadOnly));
+        assert_eq!(kv.sync(), Ok(()));
+        let mut reader = kv.get_reader().unwrap();
+        for i in 0..1000u32 {
+            let key = i.to_be_bytes();
+            assert_eq!(reader.get(&key), Ok(Some(key.to_vec())));
+        }
+        assert_eq!(reader.iter().unwrap().count(), 1000);
+        drop(reader);
+        drop(kv);
+        assert_eq!(fs::read(&meta_log_path).unwrap(), meta_log);
+        assert!(!dir.path().join("options.db").exists());
+    }
+
+    #[test]
+    fn test_kv_write_batch() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut batch = WriteBatch::new();
+        for i in 0..100u32 {
+            batch.insert(i.to_be_bytes(), i.to_be_bytes());
+        }
+        batch
+            .remove(5u32.to_be_bytes())
+            .remove_range(10u32.to_be_bytes()..20u32.to_be_bytes())
+            .insert(15u32.to_be_bytes(), vec![1]);
+        assert_eq!(batch.len(), 103);
+        assert!(kv.write(batch.clone()).is_ok());
+        let mut reader = kv.get_reader().unwrap();
+        assert_eq!(reader.iter().unwrap().count(), 100 - 1 - 10 + 1);
+        assert_eq!(reader.get(&5u32.to_be_bytes()), Ok(None));
+        assert_eq!(reader.get(&12u32.to_be_bytes()), Ok(None));
+        assert_eq!(reader.get(&15u32.to_be_bytes()), Ok(Some(vec![1])));
+        assert_eq!(
+            reader.get(&20u32.to_be_bytes()),
+            Ok(Some(20u32.to_be_bytes().to_vec()))
+        );
+
+        // nothing is applied if any change fails
+        batch.clear();
+        assert!(batch.is_empty());
+        batch
+            .remove_range::<Key, _>(..)
+            .insert(vec![0; MAX_KEY_SIZE as usize + 1], vec![1]);
+        assert_eq!(kv.write(batch), Err(TdbError::ObjectTooBig));
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.iter().unwrap().count(), 90);
+        drop(writer);
+        assert!(kv.write(WriteBatch::new()).is_ok());
+        drop(reader);
+        drop(kv);
+
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.remove_range(..10u32.to_be_bytes()), Ok(9));
+        assert_eq!(writer.iter().unwrap().count(), 81);
+    }
+
+    #[test]
+    fn test_kv_group_commit() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = Arc::new(
+            KVStore::builder()
+                .sync_mode(SyncMode::GroupCommit)
+                .open(dir.path())
+                .unwrap(),
+        );
+        // writes are queued while writer lock is held, then committed as one group
+        let writer = kv.get_writer().unwrap();
+        let ts = kv.global_ctx.get().ts;
+        let handles: Vec<_> = (0..8u32)
+            .map(|i| {
+                let kv = kv.clone();
+                std::thread::spawn(move || {
+                    let mut batch = WriteBatch::new();
+                    for j in 0..100u32 {
+                        let key = (i * 100 + j).to_be_bytes();
+                        batch.insert(key, key);
+                    }
+                    kv.write(batch)
+                })
+            })
+            .collect();
+        while kv.write_queue.lock().len() < 8 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(writer);
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Ok(ts + 1));
+        }
+        // ts of dropped writer is reused
+        assert_eq!(kv.global_ctx.get().ts, ts + 1);
+        assert_eq!(kv.get_reader().unwrap().iter().unwrap().count(), 800);
+
+        // concurrent writers without holding writer lock
+        let handles: Vec<_> = (0..8u32)
+            .map(|i| {
+                let kv = kv.clone();
+                std::thread::spawn(move || {
+                    for j in 0..50u32 {
+                        let mut batch = WriteBatch::new();
+                        let key = (i * 100 + j).to_be_bytes();
+                        batch.remove(key).insert(vec![1, i as u8, j as u8], key);
+                        assert!(kv.write(batch).is_ok());
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(kv.write_queue.lock().is_empty());
+        drop(kv);
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.iter().unwrap().count(), 800);
+        assert_eq!(writer.scan_prefix(&vec![1]).unwrap().count(), 400);
+    }
+
+    #[test]
+    fn test_kv_group_commit_failed_batch() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = Arc::new(
+            KVStore::builder()
+                .sync_mode(SyncMode::GroupCommit)
+                .open(dir.path())
+                .unwrap(),
+        );
+        let writer = kv.get_writer().unwrap();
+        let ts = kv.global_ctx.get().ts;
+        // the 4th batch has a too big key after valid changes
+        let handles: Vec<_> = (0..8u32)
+            .map(|i| {
+                let kv = kv.clone();
+                std::thread::spawn(move || {
+                    let mut batch = WriteBatch::new();
+                    for j in 0..100u32 {
+                        let key = (i * 100 + j).to_be_bytes();
+                        batch.insert(key, key);
+                    }
+                    if i == 3 {
+                        batch.insert(vec![0; MAX_KEY_SIZE as usize + 1], vec![]);
+                    }
+                    (i, kv.write(batch))
+                })
+            })
+            .collect();
+        while kv.write_queue.lock().len() < 8 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(writer);
+        for handle in handles {
+            let (i, result) = handle.join().unwrap();
+            if i == 3 {
+                assert_eq!(result, Err(TdbError::ObjectTooBig));
+            } else {
+                assert_eq!(result, Ok(ts + 1));
+            }
+        }
+        assert!(kv.write_queue.lock().is_empty());
+        let mut reader = kv.get_reader().unwrap();
+        assert_eq!(reader.iter().unwrap().count(), 700);
+        for j in 0..100u32 {
+            assert!(reader.get(&(300 + j).to_be_bytes()).unwrap().is_none());
+        }
+        drop(reader);
+        drop(kv);
+        let kv = KVStore::open(dir.path()).unwrap();
+        assert_eq!(kv.get_reader().unwrap().iter().unwrap().count(), 700);
+    }
+
+    #[test]
+    fn test_kv_ts_persisted() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        for i in 0..3u8 {
+            let mut writer = kv.get_writer().unwrap();
+            assert_eq!(writer.insert(vec![i], vec![i]), Ok(()));
+            assert!(writer.commit().is_ok());
+        }
+        let ts = kv.global_ctx.get().ts;
+        assert_eq!(ts, 3);
+        drop(kv);
+
+        let kv = KVStore::open(dir.path()).unwrap();
+        assert_eq!(kv.global_ctx.get().ts, ts);
+        // reader before first commit sees last committed root
+        let mut reader = kv.get_reader().unwrap();
+        assert_eq!(reader.get(&vec![1]), Ok(Some(vec![1])));
+        for i in 0..3u8 {
+            let mut writer = kv.get_writer().unwrap();
+            assert_eq!(writer.insert(vec![1], vec![i + 10]), Ok(()));
+            assert!(writer.commit().is_ok());
+        }
+        assert_eq!(kv.global_ctx.get().ts, ts + 3);
+        // old version is kept for reader
+        assert_eq!(reader.get(&vec![1]), Ok(Some(vec![1])));
+        assert_eq!(reader.iter().unwrap().count(), 3);
+        drop(reader);
+        assert_eq!(kv.get_reader().unwrap().get(&vec![1]), Ok(Some(vec![12])));
+        drop(kv);
+
+        let kv = KVStore::open_read_only(dir.path()).unwrap();
+        assert_eq!(kv.global_ctx.get().ts, ts + 3);
+        assert_eq!(kv.get_reader().unwrap().get(&vec![1]), Ok(Some(vec![12])));
+    }
+
+    #[test]
+    fn test_kv_timestamp() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = Arc::new(KVStore::open(dir.path()).unwrap());
+        assert_eq!(kv.get_reader().unwrap().timestamp(), 0);
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.insert(vec![1], vec![1]), Ok(()));
+        let ts = writer.commit().unwrap();
+        let mut batch = WriteBatch::new();
+        batch.insert(vec![2], vec![2]);
+        assert_eq!(kv.write(batch), Ok(ts + 1));
+        let mut reader = kv.get_reader().unwrap();
+        assert_eq!(reader.timestamp(), ts + 1);
+        assert_eq!(reader.get(&vec![2]), Ok(Some(vec![2])));
+
+        // visible commit is returned at once
+        let reader = kv
+            .get_reader_at_least(ts, Duration::from_millis(0))
+            .unwrap();
+        assert_eq!(reader.timestamp(), ts + 1);
+        assert!(matches!(
+            kv.get_reader_at_least(ts + 2, Duration::from_millis(10)),
+            Err(TdbError::Timeout)
+        ));
+        // wait until commit of other thread is visible
+        let kv_clone = kv.clone();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            let mut writer = kv_clone.get_writer().unwrap();
+            assert_eq!(writer.insert(vec![3], vec![3]), Ok(()));
+            writer.commit()
+        });
+        let mut reader = kv
+            .get_reader_at_least(ts + 2, Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(handle.join().unwrap(), Ok(ts + 2));
+        assert_eq!(reader.timestamp(), ts + 2);
+        assert_eq!(reader.get(&vec![3]), Ok(Some(vec![3])));
+    }
+
+    #[test]
+    fn test_kv_transaction() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = Arc::new(KVStore::open(dir.path()).unwrap());
+        let mut txn0 = kv.begin_transaction().unwrap();
+        let mut txn1 = kv.begin_transaction().unwrap();
+        assert_eq!(txn0.insert(vec![1], vec![1]), Ok(()));
+        assert_eq!(txn0.get(&vec![1]), Ok(Some(vec![1])));
+        assert_eq!(txn1.get(&vec![1]), Ok(None));
+        assert_eq!(txn1.insert(vec![1], vec![2]), Ok(()));
+        assert_eq!(txn1.insert(vec![2], vec![2]), Ok(()));
+        // first committer wins
+        let ts = txn0.commit().unwrap();
+        assert_eq!(txn1.commit(), Err(TdbError::Conflict));
+        assert_eq!(kv.get_reader().unwrap().get(&vec![2]), Ok(None));
+
+        // snapshot isolation, disjoint keys don't conflict
+        let mut txn0 = kv.begin_transaction().unwrap();
+        let mut txn1 = kv.begin_transaction().unwrap();
+        assert_eq!(txn0.timestamp(), ts);
+        assert_eq!(txn0.remove(&vec![1]), Ok(Some(vec![1])));
+        assert_eq!(txn0.get(&vec![1]), Ok(None));
+        let ts0 = txn0.commit().unwrap();
+        // ts isn't increased by the conflicting commit
+        assert_eq!(ts0, ts + 1);
+        assert_eq!(txn1.get(&vec![1]), Ok(Some(vec![1])));
+        assert_eq!(txn1.insert(vec![2], vec![2]), Ok(()));
+        assert!(txn1.commit().unwrap() > ts0);
+
+        // changes of writer conflict with transaction
+        let mut txn = kv.begin_transaction().unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.insert(vec![2], vec![3]), Ok(()));
+        assert!(writer.commit().is_ok());
+        assert_eq!(txn.insert(vec![2], vec![4]), Ok(()));
+        assert_eq!(txn.commit(), Err(TdbError::Conflict));
+        // removing absent key conflicts with concurrent insert of it
+        let mut txn0 = kv.begin_transaction().unwrap();
+        let mut txn1 = kv.begin_transaction().unwrap();
+        assert_eq!(txn0.remove(&vec![5]), Ok(None));
+        assert_eq!(txn1.insert(vec![5], vec![5]), Ok(()));
+        assert!(txn1.commit().is_ok());
+        assert_eq!(txn0.commit(), Err(TdbError::Conflict));
+        assert_eq!(kv.get_reader().unwrap().get(&vec![5]), Ok(Some(vec![5])));
+        // rollback discards changes
+        let mut txn = kv.begin_transaction().unwrap();
+        assert_eq!(txn.insert(vec![3], vec![3]), Ok(()));
+        txn.rollback();
+        assert_eq!(kv.get_reader().unwrap().get(&vec![3]), Ok(None));
+
+        // counter increased by concurrent transactions with retry
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let kv = kv.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        loop {
+                            let mut txn = kv.begin_transaction().unwrap();
+                            let count = match txn.get(&vec![0]).unwrap() {
+                                Some(val) => u32::from_be_bytes([val[0], val[1], val[2], val[3]]),
+                                None => 0,
+                            };
+                            txn.insert(vec![0], (count + 1).to_be_bytes()).unwrap();
+                            match txn.commit() {
+                                Ok(_) => break,
+                                Err(err) => assert_eq!(err, TdbError::Conflict),
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(
+            kv.get_reader().unwrap().get(&vec![0]),
+            Ok(Some(100u32.to_be_bytes().to_vec()))
+        );
+        // commits are not logged without active transaction
+        assert!(kv.global_ctx.commit_log.lock().commits.is_empty());
+        assert!(kv.global_ctx.commit_log.lock().active_txns.is_empty());
+    }
+
+    #[test]
+    fn test_kv_serializable() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = KVStore::open(dir.path()).unwrap();
+        let mut writer = kv.get_writer().unwrap();
+        for i in 0..10u8 {
+            assert_eq!(writer.insert(vec![1, i], vec![i]), Ok(()));
+        }
+        assert!(writer.commit().is_ok());
+
+        // write skew is possible in snapshot level, but not in serializable level
+        for isolation in [IsolationLevel::Snapshot, IsolationLevel::Serializable] {
+            let mut txn0 = kv.begin_transaction_with(isolation).unwrap();
+            let mut txn1 = kv.begin_transaction_with(isolation).unwrap();
+            for txn in [&mut txn0, &mut txn1] {
+                assert_eq!(txn.get(&vec![1, 0]), Ok(Some(vec![0])));
+                assert_eq!(txn.get(&vec![1, 1]), Ok(Some(vec![1])));
+            }
+            assert_eq!(txn0.insert(vec![1, 0], vec![10]), Ok(()));
+            assert_eq!(txn1.insert(vec![1, 1], vec![11]), Ok(()));
+            assert!(txn0.commit().is_ok());
+            let result = txn1.commit();
+            if isolation == IsolationLevel::Snapshot {
+                assert!(result.is_ok());
+            } else {
+                assert_eq!(result, Err(TdbError::Conflict));
+            }
+            let mut writer = kv.get_writer().unwrap();
+            assert_eq!(writer.insert(vec![1, 0], vec![0]), Ok(()));
+            assert_eq!(writer.insert(vec![1, 1], vec![1]), Ok(()));
+            assert!(writer.commit().is_ok());
+        }
+
+        // range sees changes of transaction
+        let mut txn = kv
+            .begin_transaction_with(IsolationLevel::Serializable)
+            .unwrap();
+        assert_eq!(txn.insert(vec![1, 20], vec![20]), Ok(()));
+        assert_eq!(txn.insert(vec![1, 2], vec![12]), Ok(()));
+        assert_eq!(txn.remove(&vec![1, 3]), Ok(Some(vec![3])));
+        assert_eq!(txn.remove(&vec![1, 30]), Ok(None));
+        let vals: Vec<u8> = txn
+            .scan_prefix(&vec![1])
+            .unwrap()
+            .map(|item| item.unwrap().1[0])
+            .collect();
+        assert_eq!(vals, vec![0, 1, 12, 4, 5, 6, 7, 8, 9, 20]);
+        assert_eq!(txn.range(vec![1, 2]..=vec![1, 4]).unwrap().count(), 2);
+        assert_eq!(txn.range(vec![1, 4]..vec![1, 2]).unwrap().count(), 0);
+        // read only transaction doesn't conflict
+        let mut reader_txn = kv
+            .begin_transaction_with(IsolationLevel::Serializable)
+            .unwrap();
+        assert_eq!(reader_txn.range(vec![1, 5]..vec![1, 7]).unwrap().count(), 2);
+        assert!(txn.commit().is_ok());
+        assert!(reader_txn.commit().is_ok());
+
+        // phantom in read range
+        let mut txn0 = kv
+            .begin_transaction_with(IsolationLevel::Serializable)
+            .unwrap();
+        let mut txn1 = kv
+            .begin_transaction_with(IsolationLevel::Serializable)
+            .unwrap();
+        assert_eq!(txn0.range(vec![1, 5]..vec![1, 7]).unwrap().count(), 2);
+        assert_eq!(txn0.insert(vec![2], vec![2]), Ok(()));
+        assert_eq!(txn1.range(vec![1, 7]..).unwrap().count(), 4);
+        assert_eq!(txn1.insert(vec![1, 6, 1], vec![6]), Ok(()));
+        assert!(txn1.commit().is_ok());
+        assert_eq!(txn0.commit(), Err(TdbError::Conflict));
+
+        // reads conflict with commits of writer and batch
+        let mut txn = kv
+            .begin_transaction_with(IsolationLevel::Serializable)
+            .unwrap();
+        assert_eq!(txn.get(&vec![1, 5]), Ok(Some(vec![5])));
+        assert_eq!(txn.insert(vec![3], vec![3]), Ok(()));
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.insert(vec![1, 5], vec![15]), Ok(()));
+        assert!(writer.commit().is_ok());
+        assert_eq!(txn.commit(), Err(TdbError::Conflict));
+        let mut txn = kv
+            .begin_transaction_with(IsolationLevel::Serializable)
+            .unwrap();
+        assert_eq!(txn.range(vec![1, 8]..).unwrap().count(), 3);
+        assert_eq!(txn.insert(vec![3], vec![3]), Ok(()));
+        let mut batch = WriteBatch::new();
+        batch.remove(vec![1, 9]);
+        assert!(kv.write(batch).is_ok());
+        assert_eq!(txn.commit(), Err(TdbError::Conflict));
+        assert_eq!(kv.get_reader().unwrap().get(&vec![3]), Ok(None));
+    }
+
+    #[test]
+    fn test_kv_conditional_write() {
+        init();
+        let dir = tempdir().unwrap();
+        let kv = Arc::new(KVStore::open(dir.path()).unwrap());
+        let mut writer = kv.get_writer().unwrap();
+        assert_eq!(writer.update(vec![1], vec![1]), Ok(false));
+        assert_eq!(writer.insert_if_absent(vec![1], vec![1]), Ok(true));
+        assert_eq!(writer.insert_if_absent(vec![1], vec![2]), Ok(false));
+        assert_eq!(writer.get(&vec![1]), Ok(Some(vec![1])));
+        assert_eq!(writer.update(vec![1], vec![2]), Ok(true));
+        assert_eq!(writer.get(&vec![1]), Ok(Some(vec![2])));
+        // large val in overflow is compared as a whole
+        let large_val = vec![3; 10000];
+        assert_eq!(
+            writer.compare_and_swap(vec![1], Some(vec![1]), Some(large_val.clone())),
+            Ok(false)
+        );
+        assert_eq!(
+            writer.compare_and_swap(vec![1], Some(vec![2]), Some(large_val.clone())),
+            Ok(true)
+        );
+        let mut other_val = large_val.clone();
+        other_val[9999] = 4;
+        assert_eq!(
+            writer.compare_and_swap(vec![1], Some(other_val), None),
+            Ok(false)
+        );
+        assert_eq!(
+            writer.compare_and_swap(vec![1], Some(large_val), None),
+            Ok(true)
+        );
+        assert_eq!(writer.get(&vec![1]), Ok(None));
+        assert_eq!(writer.compare_and_swap(vec![1], None, None), Ok(true));
+        assert_eq!(writer.update(vec![1], vec![1]), Ok(false));
+        assert_eq!(
+            writer.insert_if_absent(vec![0; MAX_KEY_SIZE as usize + 1], vec![1]),
+            Err(TdbError::ObjectTooBig)
+        );
+        assert!(writer.commit().is_ok());
+
+        // writers racing on the same expected val, only one swaps
+        for round in 0..10u8 {
+            let barrier = Arc::new(Barrier::new(4));
+            let handles: Vec<_> = (0..4u8)
+                .map(|i| {
+                    let kv = kv.clone();
+                    let barrier = barrier.clone();
+                    std::thread::spawn(move || {
+                        let expected = kv.get_reader().unwrap().get(&vec![0]).unwrap();
+                        barrier.wait();
+                        let mut writer = kv.get_writer().unwrap();
+                        let swapped = writer
+                            .compare_and_swap(vec![0], expected, Some(vec![round, i]))
+                            .unwrap();
+                        assert!(writer.commit().is_ok());
+                        swapped.then_some(i)
+                    })
+                })
+                .collect();
+            let winners: Vec<u8> = handles
+                .into_iter()
+                .filter_map(|handle| handle.join().unwrap())
+                .collect();
+            assert_eq!(winners.len(), 1);
+            assert_eq!(
+                kv.get_reader().unwrap().get(&vec![0]),
+                Ok(Some(vec![round, winners[0]]))
+            );
+        }
+    }
 }
@@ -1,12 +1,16 @@
-#![feature(core_intrinsics)]
-#![feature(weak_counts)]
+mod batch;
 mod cache;
 mod error;
 mod kv;
 mod meta;
 mod object;
+mod options;
 mod storage;
 mod transaction;
 mod utils;
 
-pub use kv::{KVReader, KVStore, KVWriter};
+pub use batch::WriteBatch;
+pub use kv::{IsolationLevel, KVReader, KVStore, KVWriter, RecoveryReport, Transaction, TxnIter};
+pub use options::Options;
+pub use storage::SyncMode;
+pub use transaction::TimeStamp;
@@ -2,12 +2,16 @@ use crate::error::TdbError;
 use crate::meta::{InnerTable, PageId};
 use crate::object::{ObjectId, UNUSED_OID};
 use crate::storage::{Deserialize, ObjectPos, Serialize};
+use crate::transaction::TimeStamp;
+use crate::utils::{CrcReader, CrcWriter, CHECKSUM_SIZE};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::collections::{HashMap, HashSet};
 use std::io::{Read, Write};
 use std::mem;
 
-const MAGIC_NUM: u32 = 0xfAfAfAfA;
+// Format version of checkpoint, and of objects it refers to, which have no version of their own
+// Change it whenever checkpoint or object layout changes, checkpoint of other magic is rejected
+const MAGIC_NUM: u32 = 0xFAFB_FAFB;
 
 /// Meta data redo log
 /// Write to Meta file every write transcation
@@ -20,7 +24,11 @@ pub struct CheckPoint {
     // for gc
     pub data_removed_size: u64,
     pub data_size: u64,
+    // segments before it are removed, changed by compaction
+    pub first_segment_id: u32,
     pub root_oid: ObjectId,
+    // timestamp of last commit
+    pub ts: TimeStamp,
     // meta log area used size
     pub meta_size: u32,
     // meta file len = tablepage_nums  * 4096
@@ -29,10 +37,13 @@ pub struct CheckPoint {
 }
 
 impl CheckPoint {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         data_removed_size: u64,
         data_size: u64,
+        first_segment_id: u32,
         root_oid: ObjectId,
+        ts: TimeStamp,
         meta_size: u32,
         tablepage_nums: u32,
         obj_changes: Vec<(ObjectId, ObjectPos)>,
@@ -45,7 +56,9 @@ impl CheckPoint {
             // for gc
             data_removed_size,
             data_size,
+            first_segment_id,
             root_oid,
+            ts,
             meta_size,
             tablepage_nums,
             obj_changes,
@@ -57,7 +70,7 @@ impl CheckPoint {
     }
 
     pub fn merge(mut cps: Vec<CheckPoint>) -> CheckPoint {
-        assert!(cps.len() > 0);
+        assert!(!cps.is_empty());
         let mut changes: HashMap<ObjectId, ObjectPos> = HashMap::default();
 
         for cp in cps.iter() {
@@ -66,7 +79,7 @@ impl CheckPoint {
             }
         }
         let mut changes: Vec<(ObjectId, ObjectPos)> = changes.drain().collect();
-        changes.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        changes.sort_unstable_by_key(|a| a.0);
         let mut last_cp = cps.pop().unwrap();
         last_cp.obj_changes = changes;
         last_cp
@@ -82,6 +95,14 @@ impl CheckPoint {
 
     #[inline]
     pub fn len(&self) -> usize {
+        Self::fixed_len()
+            // obj_changes
+            + self.obj_changes.len() * (mem::size_of::<ObjectId>() + mem::size_of::<u64>())
+    }
+
+    /// Return len without obj_changes
+    #[inline]
+    fn fixed_len() -> usize {
         // size
         mem::size_of::<u32>()
         // crcmagic 32
@@ -90,16 +111,20 @@ impl CheckPoint {
             + mem::size_of::<u64>()
             // datasizen
             + mem::size_of::<u64>()
+            // first_segment_id
+            + mem::size_of::<u32>()
             // root_oid
             + mem::size_of::<u32>()
             // meta_size
             + mem::size_of::<u32>()
             // tablepage_nums 
             + mem::size_of::<u32>()
+            // ts
+            + mem::size_of::<TimeStamp>()
             // obj_changes len
             + mem::size_of::<u32>()
-            // obj_changes
-            + self.obj_changes.len() * (mem::size_of::<ObjectId>() + mem::size_of::<u64>())
+            // checksum
+            + CHECKSUM_SIZE
     }
 }
 
@@ -113,7 +138,9 @@ impl Default for CheckPoint {
             // for gc
             data_removed_size: 0,
             data_size: 0,
+            first_segment_id: 0,
             root_oid: UNUSED_OID,
+            ts: 0,
             // meta log area used size
             meta_size: 0,
             // meta file len = tablepage_nums * 4096
@@ -129,50 +156,67 @@ impl Default for CheckPoint {
 
 impl Serialize for CheckPoint {
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<usize, TdbError> {
+        let mut writer = CrcWriter::new(writer);
         writer.write_u32::<LittleEndian>(self.size)?;
         writer.write_u32::<LittleEndian>(self.magic)?;
         writer.write_u64::<LittleEndian>(self.data_removed_size)?;
         writer.write_u64::<LittleEndian>(self.data_size)?;
+        writer.write_u32::<LittleEndian>(self.first_segment_id)?;
         writer.write_u32::<LittleEndian>(self.root_oid)?;
         writer.write_u32::<LittleEndian>(self.meta_size)?;
         writer.write_u32::<LittleEndian>(self.tablepage_nums)?;
+        writer.write_u64::<LittleEndian>(self.ts)?;
         writer.write_u32::<LittleEndian>(self.obj_changes.len() as u32)?;
         for i in 0..self.obj_changes.len() {
             writer.write_u32::<LittleEndian>(self.obj_changes[i].0)?;
             writer.write_u64::<LittleEndian>((self.obj_changes[i].1).0)?
         }
+        writer.write_checksum()?;
         Ok(self.len())
     }
 }
 
 impl Deserialize for CheckPoint {
     fn deserialize<R: Read>(reader: &mut R) -> Result<Self, TdbError> {
+        let mut reader = CrcReader::new(reader);
         let size = reader.read_u32::<LittleEndian>()?;
         if size == 0 {
             return Err(TdbError::SerializeError);
         }
         let magic = reader.read_u32::<LittleEndian>()?;
         if magic != MAGIC_NUM {
-            return Err(TdbError::SerializeError);
+            return Err(TdbError::UnsupportedFormat);
         }
         let data_removed_size = reader.read_u64::<LittleEndian>()?;
         let data_size = reader.read_u64::<LittleEndian>()?;
+        let first_segment_id = reader.read_u32::<LittleEndian>()?;
         let root_oid = reader.read_u32::<LittleEndian>()?;
         let meta_size = reader.read_u32::<LittleEndian>()?;
         let tablepage_nums = reader.read_u32::<LittleEndian>()?;
+        let ts = reader.read_u64::<LittleEndian>()?;
         let obj_change_len = reader.read_u32::<LittleEndian>()? as usize;
+        // don't trust len before checksum is verified
+        if size as usize
+            != CheckPoint::fixed_len()
+                + obj_change_len * (mem::size_of::<ObjectId>() + mem::size_of::<u64>())
+        {
+            return Err(TdbError::SerializeError);
+        }
         let mut obj_changes = Vec::with_capacity(obj_change_len);
         for _ in 0..obj_change_len {
             let oid = reader.read_u32::<LittleEndian>()?;
             let obj_pos = reader.read_u64::<LittleEndian>()?;
             obj_changes.push((oid, ObjectPos(obj_pos)));
         }
+        reader.check_checksum()?;
         Ok(Self {
             size,
             magic,
             data_removed_size,
             data_size,
+            first_segment_id,
             root_oid,
+            ts,
             meta_size,
             tablepage_nums,
             obj_changes,
@@ -187,9 +231,9 @@ mod tests {
     #[test]
     fn test_cp_size() {
         let mut cp = CheckPoint::default();
-        assert_eq!(cp.len(), 4 + 4 + 8 + 8 + 4 + 4 + 4 + 4);
+        assert_eq!(cp.len(), 4 + 4 + 8 + 8 + 4 + 4 + 4 + 4 + 8 + 4 + 4);
         cp.obj_changes.push((1, ObjectPos::default()));
-        assert_eq!(cp.len(), 4 + 4 + 8 + 8 + 4 + 4 + 4 + 4 + 4 + 8);
+        assert_eq!(cp.len(), 4 + 4 + 8 + 8 + 4 + 4 + 4 + 4 + 8 + 4 + 4 + 4 + 8);
     }
 
     #[test]
@@ -202,9 +246,45 @@ mod tests {
         assert!(CheckPoint::deserialize(&mut &buf[..8]).is_err());
         // println!("{:?}", CheckPoint::deserialize(&mut &buf[..8]));
         cp0.obj_changes.push((1, ObjectPos::default()));
+        cp0.size = cp0.len() as u32;
         assert!(cp0.serialize(&mut &mut buf[..]).is_ok());
         let cp1 = CheckPoint::deserialize(&mut &buf[..]).unwrap();
         assert_eq!(cp0, cp1);
+        let cp0 = CheckPoint::new(1, 4096, 2, 3, 42, 0, 1, vec![(1, ObjectPos::default())]);
+        assert!(cp0.serialize(&mut &mut buf[..]).is_ok());
+        let cp1 = CheckPoint::deserialize(&mut &buf[..]).unwrap();
+        assert_eq!(cp1.ts, 42);
+        assert_eq!(cp0, cp1);
+        // flip a bit
+        buf[20] ^= 1;
+        assert!(CheckPoint::deserialize(&mut &buf[..]).is_err());
+        // unknown magic
+        buf[4..8].copy_from_slice(&0xFAFC_FAFCu32.to_le_bytes());
+        assert_eq!(
+            CheckPoint::deserialize(&mut &buf[..]),
+            Err(TdbError::UnsupportedFormat)
+        );
+    }
+
+    #[test]
+    fn test_cp_baseline() {
+        // checkpoint of the first format, without first_segment_id, ts and checksum
+        let mut buf = vec![];
+        buf.write_u32::<LittleEndian>(4 + 4 + 8 + 8 + 4 + 4 + 4 + 4 + 4 + 8)
+            .unwrap();
+        buf.write_u32::<LittleEndian>(0xFAFA_FAFA).unwrap();
+        buf.write_u64::<LittleEndian>(0).unwrap();
+        buf.write_u64::<LittleEndian>(4096).unwrap();
+        buf.write_u32::<LittleEndian>(1).unwrap();
+        buf.write_u32::<LittleEndian>(52).unwrap();
+        buf.write_u32::<LittleEndian>(1).unwrap();
+        buf.write_u32::<LittleEndian>(1).unwrap();
+        buf.write_u32::<LittleEndian>(1).unwrap();
+        buf.write_u64::<LittleEndian>(0).unwrap();
+        assert_eq!(
+            CheckPoint::deserialize(&mut &buf[..]),
+            Err(TdbError::UnsupportedFormat)
+        );
     }
 
     #[test]
@@ -212,7 +292,9 @@ mod tests {
         let cp0 = CheckPoint::new(
             0,
             4096,
+            0,
             1,
+            5,
             123,
             10,
             vec![(0, ObjectPos::default()), (2, ObjectPos::default())],
@@ -220,7 +302,9 @@ mod tests {
         let mut cp1 = CheckPoint::new(
             1,
             4096,
+            1,
             2,
+            6,
             234,
             9,
             vec![
@@ -230,7 +314,7 @@ mod tests {
         );
         let cp2 = CheckPoint::merge(vec![cp0.clone(), cp1.clone()]);
         cp1.obj_changes.push((2, ObjectPos::default()));
+        assert_eq!(cp2.ts, 6);
         assert_eq!(cp1, cp2);
     }
-
 }
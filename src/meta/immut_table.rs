@@ -33,11 +33,13 @@ mod tests {
     use crate::cache::ImMutCache;
     use crate::object::{Entry, ObjectRef};
     use crate::storage::{Dev, ObjectPos};
-    use std::env;
     use std::sync::Arc;
+    use tempfile::tempdir;
     #[test]
     fn test_immut_table() {
-        let dev = Dev::open(env::current_dir().unwrap()).unwrap();
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
+        dev.get_data_writer(0, 0, 0).unwrap();
         let data_file = dev.get_data_reader().unwrap();
         let cache = ImMutCache::default();
         let table = InnerTable::with_capacity(1);
@@ -52,5 +54,4 @@ mod tests {
         assert_eq!(immut_table.get_obj(0, 1), Ok(arc_obj0.clone()));
         assert_eq!(immut_table.get_obj(0, 2), Ok(arc_obj1.clone()));
     }
-
 }
@@ -1,14 +1,17 @@
 use crate::cache::{ImMutCache, MutCache};
 use crate::error::TdbError;
 use crate::meta::{InnerTable, PageId, TablePage, MAX_PAGE_NUM, OBJ_PRE_PAGE};
-use crate::object::{Entry, Object, ObjectId, ObjectRef, ObjectState};
-use crate::storage::{DataFileReader, ObjectPos};
+use crate::object::{Object, ObjectId, ObjectRef, ObjectState, ObjectTag};
+use crate::storage::{DataFileReader, DataFilwWriter, ObjectPos};
 use crate::transaction::TimeStamp;
 use crate::utils::BitMap;
 use log::debug;
-use std::collections::{HashSet,HashMap};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+/// Objects position changed by commit or compaction
+pub type ObjChanges = Vec<(ObjectId, ObjectPos)>;
+
 pub struct MutTable {
     dirty_cache: MutCache,
     data_reader: DataFileReader,
@@ -20,12 +23,13 @@ pub struct MutTable {
 }
 
 impl MutTable {
-    pub fn new_empty(data_reader: DataFileReader) -> Self {
+    pub fn new_empty(data_reader: DataFileReader, cache: ImMutCache) -> Self {
         Self::new(
             data_reader,
             InnerTable::default(),
             BitMap::default(),
             HashSet::default(),
+            cache,
         )
     }
 
@@ -34,8 +38,8 @@ impl MutTable {
         table: InnerTable,
         bitmap: BitMap,
         dirty_pages: HashSet<PageId>,
+        cache: ImMutCache,
     ) -> Self {
-        let cache = ImMutCache::default();
         let dirty_cache = MutCache::default();
         let table = Arc::new(table);
         Self {
@@ -63,7 +67,7 @@ impl MutTable {
             );
             self.dirty_cache
                 .insert(oid, ObjectState::Readonly(obj.clone()));
-            if !obj.is::<Entry>() {
+            if obj.is_node() {
                 self.cache.insert(pos, obj);
             }
         }
@@ -88,7 +92,7 @@ impl MutTable {
             );
             self.dirty_cache
                 .insert(oid, ObjectState::Readonly(obj.clone()));
-            if !obj.is::<Entry>() {
+            if obj.is_node() {
                 self.cache.insert(pos, obj);
             }
         }
@@ -103,7 +107,7 @@ impl MutTable {
     /// Panics if oid has been released
     #[inline]
     fn free_oid(&mut self, oid: ObjectId) {
-        assert_eq!(self.bitmap.get_bit(oid as usize), true);
+        assert!(self.bitmap.get_bit(oid as usize));
         if oid < self.min_unused_oid {
             self.min_unused_oid = oid;
         }
@@ -124,6 +128,25 @@ impl MutTable {
         }
     }
 
+    /// Allocate unused oid, extend table if there is no unused oid
+    /// # Panics
+    /// Panics if table is full
+    fn allocate(&mut self) -> ObjectId {
+        match self.allocate_oid() {
+            Some(oid) => oid,
+            None => {
+                let used_page_num = self.table.get_page_num();
+                if used_page_num == MAX_PAGE_NUM {
+                    panic!("allocated page overflow");
+                }
+                self.table.extend_to(used_page_num as PageId);
+                self.bitmap.extend_to((used_page_num + 1) * OBJ_PRE_PAGE);
+                debug!("obj num extend to {:?}", (used_page_num + 1) * OBJ_PRE_PAGE);
+                self.allocate_oid().expect("no enough oid for object")
+            }
+        }
+    }
+
     /// Remove object if object in dirty_cache and insert Del tag if object is ondisk
     /// Return old object
     /// # Notes
@@ -141,7 +164,7 @@ impl MutTable {
             );
             self.dirty_cache
                 .insert(oid, ObjectState::Readonly(obj.clone()));
-            if !obj.is::<Entry>() {
+            if obj.is_node() {
                 self.cache.insert(pos, obj);
             }
         }
@@ -153,12 +176,14 @@ impl MutTable {
                         .insert(oid, ObjectState::Del(arc_obj.clone()));
                 }
                 // object is new allcated, just remove it and free oid
-                ObjectState::New(_) => {
+                ObjectState::New(_) | ObjectState::Written(_, None) => {
                     // reuse oid
                     self.free_oid(oid);
                 }
                 // object is on disk, insert remove tag and free oid
-                ObjectState::Readonly(arc_obj) | ObjectState::Dirty(_, arc_obj) => {
+                ObjectState::Readonly(arc_obj)
+                | ObjectState::Dirty(_, arc_obj)
+                | ObjectState::Written(_, Some(arc_obj)) => {
                     self.dirty_cache
                         .insert(oid, ObjectState::Del(arc_obj.clone()));
                     // reuse oid
@@ -176,38 +201,74 @@ impl MutTable {
     /// # Panics
     /// Panics if there is no unused oid
     pub fn insert(&mut self, obj: Object) -> ObjectId {
-        let oid = match self.allocate_oid() {
-            Some(oid) => oid,
-            None => {
-                let used_page_num = self.table.get_page_num();
-                if used_page_num == MAX_PAGE_NUM {
-                    panic!("allocated page overflow");
-                }
-                self.table.extend_to(used_page_num as PageId);
-                self.bitmap.extend_to((used_page_num + 1) * OBJ_PRE_PAGE);
-                debug!("obj num extend to {:?}", (used_page_num + 1) * OBJ_PRE_PAGE);
-                self.allocate_oid().expect("no enough oid for object")
+        let oid = self.allocate();
+        match self.dirty_cache.remove(oid) {
+            // object is on disk
+            Some(ObjectState::Del(arc_obj))
+            | Some(ObjectState::Dirty(_, arc_obj))
+            | Some(ObjectState::Readonly(arc_obj)) => {
+                self.dirty_cache
+                    .insert(oid, ObjectState::Dirty(obj, arc_obj));
             }
-        };
-        if let Some(mut_obj) = self.dirty_cache.remove(oid) {
-            match mut_obj {
-                // object is on disk
-                ObjectState::Del(arc_obj)
-                | ObjectState::Dirty(_, arc_obj)
-                | ObjectState::Readonly(arc_obj) => {
-                    self.dirty_cache
-                        .insert(oid, ObjectState::Dirty(obj, arc_obj));
-                }
-                _ => {
-                    self.dirty_cache.insert(oid, ObjectState::New(obj));
-                }
+            _ => {
+                self.dirty_cache.insert(oid, ObjectState::New(obj));
             }
-        } else {
-            self.dirty_cache.insert(oid, ObjectState::New(obj));
         }
         oid
     }
 
+    /// Allocate oid for object written to data file before commit, pos is set by set_written_pos
+    pub fn reserve(&mut self) -> ObjectId {
+        let oid = self.allocate();
+        let old_obj = match self.dirty_cache.remove(oid) {
+            // object is on disk
+            Some(ObjectState::Del(arc_obj))
+            | Some(ObjectState::Dirty(_, arc_obj))
+            | Some(ObjectState::Readonly(arc_obj)) => Some(arc_obj),
+            _ => None,
+        };
+        self.dirty_cache
+            .insert(oid, ObjectState::Written(ObjectPos::default(), old_obj));
+        oid
+    }
+
+    /// Set pos of object reserved before
+    /// # Panics
+    /// Panics if oid is not reserved
+    pub fn set_written_pos(&mut self, oid: ObjectId, pos: ObjectPos) {
+        match self.dirty_cache.get_inner_mut().get_mut(&oid) {
+            Some(ObjectState::Written(written_pos, _)) => *written_pos = pos,
+            _ => panic!("object isn't reserved"),
+        }
+    }
+
+    /// Return object without keeping it in dirty cache, object written before commit is read from data file
+    pub fn get_arc(&mut self, oid: ObjectId, ts: TimeStamp) -> Result<Arc<Object>, TdbError> {
+        match self.dirty_cache.get_inner_mut().get(&oid) {
+            Some(ObjectState::Readonly(arc_obj)) => Ok(arc_obj.clone()),
+            Some(ObjectState::Dirty(obj, _)) | Some(ObjectState::New(obj)) => {
+                Ok(Arc::new(obj.clone()))
+            }
+            Some(ObjectState::Written(pos, _)) => Ok(Arc::new(self.data_reader.read_obj(pos)?)),
+            Some(ObjectState::Del(_)) => Err(TdbError::NotFindObject),
+            None => Ok(self.table.get(oid, ts, &mut self.data_reader)?.1),
+        }
+    }
+
+    /// Discard objects in dirty cache
+    /// Oids allocated by new objects are freed and oids freed by removed objects are restored
+    pub fn rollback(&mut self) {
+        for (oid, obj) in self.dirty_cache.drain() {
+            match obj {
+                ObjectState::New(_) | ObjectState::Written(_, None) => self.free_oid(oid),
+                ObjectState::Del(_) => self.bitmap.set_bit(oid as usize, true),
+                ObjectState::Readonly(_)
+                | ObjectState::Dirty(_, _)
+                | ObjectState::Written(_, Some(_)) => {}
+            }
+        }
+    }
+
     /// Apply object change to inner table
     /// Return (oids need to gc next time,chaneged objs)
     pub fn apply(
@@ -224,9 +285,18 @@ impl MutTable {
             match obj {
                 ObjectState::Dirty(obj, _) | ObjectState::New(obj) => {
                     let arc_obj = Arc::new(obj);
-                    let version = ObjectRef::new(&arc_obj, arc_obj.get_pos().clone(), ts);
-                    obj_changes.push((oid, arc_obj.get_pos().clone()));
-                    self.cache.insert(arc_obj.get_pos().clone(), arc_obj);
+                    let version = ObjectRef::new(&arc_obj, *arc_obj.get_pos(), ts);
+                    obj_changes.push((oid, *arc_obj.get_pos()));
+                    self.cache.insert(*arc_obj.get_pos(), arc_obj);
+                    match self.table.insert(oid, version, min_ts) {
+                        Ok(()) => {}
+                        Err(oid) => gc_ctx.push(oid),
+                    };
+                }
+                ObjectState::Written(pos, _) => {
+                    // don't keep written object in memory
+                    let version = ObjectRef::on_disk(pos, ts);
+                    obj_changes.push((oid, pos));
                     match self.table.insert(oid, version, min_ts) {
                         Ok(()) => {}
                         Err(oid) => gc_ctx.push(oid),
@@ -245,10 +315,71 @@ impl MutTable {
         (gc_ctx, obj_changes)
     }
 
+    /// Copy all live objects to data writer and add new version to inner table
+    /// Branch and leaf are copied before entry to keep them align to 4K
+    /// Return (oids need to gc next time,chaneged objs)
+    /// # Errors
+    /// Versions added before error are removed
+    /// # Panics
+    /// Panics if there is uncommitted object
+    pub fn compact(
+        &mut self,
+        ts: TimeStamp,
+        min_ts: TimeStamp,
+        data_writer: &mut DataFilwWriter,
+    ) -> Result<(Vec<ObjectId>, ObjChanges), TdbError> {
+        assert!(
+            self.dirty_cache.is_empty(),
+            "compact with uncommitted objects"
+        );
+        let mut node_oids = vec![];
+        let mut entry_oids = vec![];
+        for index in 0..self.bitmap.get_cap() {
+            if self.bitmap.get_bit(index) {
+                let oid = index as ObjectId;
+                match self.table.get_newest_pos(oid).get_tag() {
+                    ObjectTag::Leaf | ObjectTag::Branch => node_oids.push(oid),
+                    ObjectTag::Entry | ObjectTag::Overflow => entry_oids.push(oid),
+                }
+            }
+        }
+        let mut gc_ctx = vec![];
+        let mut obj_changes = Vec::with_capacity(node_oids.len() + entry_oids.len());
+        for oid in node_oids.into_iter().chain(entry_oids) {
+            let pos = match self
+                .table
+                .get(oid, ts, &mut self.data_reader)
+                .and_then(|(_, arc_obj)| data_writer.write_obj(&mut (*arc_obj).clone()))
+            {
+                Ok(pos) => pos,
+                Err(err) => {
+                    self.rollback_compact(ts, &obj_changes);
+                    return Err(err);
+                }
+            };
+            self.dirty_pages.insert(InnerTable::get_page_id(oid));
+            obj_changes.push((oid, pos));
+            // don't keep copied object in memory, read it from new data file if need
+            let version = ObjectRef::on_disk(pos, ts);
+            if let Err(oid) = self.table.insert(oid, version, min_ts) {
+                gc_ctx.push(oid);
+            }
+        }
+        debug!("compact {:?} objects", obj_changes.len());
+        Ok((gc_ctx, obj_changes))
+    }
+
+    /// Remove versions added by compaction at ts, objects are read from old pos again
+    pub fn rollback_compact(&mut self, ts: TimeStamp, obj_changes: &ObjChanges) {
+        for (oid, _) in obj_changes.iter() {
+            self.table.rollback(*oid, ts);
+        }
+    }
+
     /// Free object if no immut context will see it  
     pub fn gc(&mut self, oids: HashSet<ObjectId>, min_ts: TimeStamp) {
         for oid in oids.iter() {
-            self.table.try_gc(*oid, min_ts);
+            let _ = self.table.try_gc(*oid, min_ts);
         }
     }
 
@@ -281,17 +412,25 @@ impl MutTable {
 mod tests {
     use super::*;
     use crate::meta::ImMutTable;
-    use crate::object::{Leaf, ObjectRef};
-    use crate::storage::{Dev, ObjectPos};
+    use crate::object::Leaf;
+    use crate::storage::Dev;
     use crate::utils::BitMap;
-    use std::env;
+    use tempfile::tempdir;
     #[test]
     fn test_mut_table() {
-        let dev = Dev::open(env::current_dir().unwrap()).unwrap();
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
+        dev.get_data_writer(0, 0, 0).unwrap();
         let data_reader = dev.get_data_reader().unwrap();
         let table = InnerTable::with_capacity(0);
         let bitamp: BitMap<u32> = BitMap::with_capacity(0);
-        let mut mut_table = MutTable::new(data_reader, table, bitamp, HashSet::default());
+        let mut mut_table = MutTable::new(
+            data_reader,
+            table,
+            bitamp,
+            HashSet::default(),
+            ImMutCache::default(),
+        );
         let mut immut_table = ImMutTable::new(
             mut_table.table.clone(),
             dev.get_data_reader().unwrap(),
@@ -304,7 +443,7 @@ mod tests {
         assert_eq!(immut_table.get_obj(oid0, 0), Err(TdbError::NotFindObject));
         let (oids, oidpos) = mut_table.apply(2, 1);
         assert_eq!(oids, vec![]);
-        assert_eq!(oidpos, vec![(0, obj0.get_pos().clone())]);
+        assert_eq!(oidpos, vec![(0, *obj0.get_pos())]);
         assert_eq!(immut_table.get_obj(oid0, 1), Err(TdbError::NotFindObject));
         assert_eq!(immut_table.get_obj(oid0, 2), Ok(Arc::new(obj0.clone())));
         assert_eq!(
@@ -317,4 +456,33 @@ mod tests {
         assert_eq!(immut_table.get_obj(oid0, 3), Err(TdbError::NotFindObject));
     }
 
+    #[test]
+    fn test_mut_table_rollback() {
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
+        dev.get_data_writer(0, 0, 0).unwrap();
+        let mut mut_table =
+            MutTable::new_empty(dev.get_data_reader().unwrap(), ImMutCache::default());
+
+        let obj0 = Object::L(Leaf::default());
+        let oid0 = mut_table.insert(obj0.clone());
+        mut_table.apply(1, 0);
+        // remove committed object and reuse its oid, then allocate a new one
+        assert!(mut_table.remove(oid0, 2).is_ok());
+        let oid1 = mut_table.insert(obj0.clone());
+        assert_eq!(oid1, oid0);
+        let oid2 = mut_table.insert(obj0.clone());
+        assert_ne!(oid2, oid0);
+        mut_table.rollback();
+        assert_eq!(mut_table.get_ref(oid0, 2), Ok(&obj0));
+        assert_eq!(mut_table.get_ref(oid2, 2), Err(TdbError::NotFindObject));
+        assert!(mut_table.bitmap.get_bit(oid0 as usize));
+        assert!(!mut_table.bitmap.get_bit(oid2 as usize));
+
+        // removed object is restored
+        assert!(mut_table.remove(oid0, 2).is_ok());
+        mut_table.rollback();
+        assert!(mut_table.bitmap.get_bit(oid0 as usize));
+        assert_eq!(mut_table.insert(obj0), oid2);
+    }
 }
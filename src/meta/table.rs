@@ -10,7 +10,6 @@ use std::sync::{
     atomic::{AtomicPtr, AtomicU32, Ordering},
     Arc,
 };
-use std::u32;
 
 // 4K
 pub const TABLE_PAGE_SIZE: usize = 1 << 12;
@@ -152,7 +151,7 @@ impl InnerTable {
     /// Locks object by oid with shared read access
     /// # Panics
     /// Panics if the oid overflows max allocated oid
-    fn get_readlock(&self, oid: ObjectId) -> RwLockReadGuard<Versions> {
+    fn get_readlock(&self, oid: ObjectId) -> RwLockReadGuard<'_, Versions> {
         let pid = Self::get_page_id(oid);
         let page_ptr = self.get_page_ptr(pid).load(Ordering::SeqCst);
         assert!(!page_ptr.is_null());
@@ -164,7 +163,7 @@ impl InnerTable {
     /// Locks object by oid with exclusive write access
     /// # Panics
     /// Panics if the oid overflows max allocated oid
-    fn get_writelock(&self, oid: u32) -> RwLockWriteGuard<Versions> {
+    fn get_writelock(&self, oid: u32) -> RwLockWriteGuard<'_, Versions> {
         let page_id = Self::get_page_id(oid);
         let page_ptr = self.get_page_ptr(page_id).load(Ordering::SeqCst);
         assert!(!page_ptr.is_null());
@@ -180,7 +179,7 @@ impl InnerTable {
     pub fn extend_to(&self, pid: PageId) -> u32 {
         let used_page_num = self.used_page_num.load(Ordering::Relaxed);
         let new_page_num = pid + 1;
-        assert!(pid as usize + 1 <= MAX_PAGE_NUM && pid >= used_page_num);
+        assert!((pid as usize) < MAX_PAGE_NUM && pid >= used_page_num);
         for pid in used_page_num..new_page_num {
             let page_ptr = self.get_page_ptr(pid).load(Ordering::SeqCst);
             assert!(page_ptr.is_null());
@@ -206,7 +205,7 @@ impl InnerTable {
     ) -> Result<(ObjectPos, Arc<Object>), TdbError> {
         let read_versions = self.get_readlock(oid);
         if let Some(obj_ref) = read_versions.find_obj_ref(ts) {
-            let pos = obj_ref.obj_pos.clone();
+            let pos = obj_ref.obj_pos;
             if let Some(arc_obj) = obj_ref.obj_ref.upgrade() {
                 return Ok((pos, arc_obj));
             } else {
@@ -216,7 +215,12 @@ impl InnerTable {
                 if let Some(arc_obj) = obj_mut.obj_ref.upgrade() {
                     return Ok((pos, arc_obj));
                 } else {
-                    let obj = file.read_obj(&pos)?;
+                    let obj = match file.read_obj(&pos) {
+                        Err(TdbError::Corruption { pos, .. }) => {
+                            return Err(TdbError::Corruption { oid, pos })
+                        }
+                        obj => obj?,
+                    };
                     let arc_obj = Arc::new(obj);
                     obj_mut.obj_ref = Arc::downgrade(&arc_obj);
                     return Ok((pos, arc_obj));
@@ -226,6 +230,11 @@ impl InnerTable {
         Err(TdbError::NotFindObject)
     }
 
+    /// Return pos of newest object, empty pos if object is removed
+    pub fn get_newest_pos(&self, oid: ObjectId) -> ObjectPos {
+        self.get_readlock(oid).get_newest_objpos()
+    }
+
     /// Insert object and try to free old version
     /// # Errors
     /// Return Err(oid) if object version must be clear next time
@@ -247,6 +256,11 @@ impl InnerTable {
         }
     }
 
+    /// Remove version inserted at ts, the version before it becomes the newest again
+    pub fn rollback(&self, oid: ObjectId, ts: TimeStamp) {
+        self.get_writelock(oid).rollback(ts);
+    }
+
     /// Remove object from table
     /// # Errors
     /// Return Err(oid) if object version must be clear next time
@@ -280,10 +294,12 @@ mod tests {
     use super::*;
     use crate::object::Entry;
     use crate::storage::Dev;
-    use std::env;
+    use tempfile::tempdir;
     #[test]
     fn test_table() {
-        let dev = Dev::open(env::current_dir().unwrap()).unwrap();
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
+        dev.get_data_writer(0, 0, 0).unwrap();
         let mut data_file = dev.get_data_reader().unwrap();
         let table = InnerTable::with_capacity(1);
         assert!(table.get(0, 0, &mut data_file).is_err());
@@ -298,5 +314,4 @@ mod tests {
         assert_eq!(table.try_gc(0, 2), Ok(()));
         assert!(table.get(0, 0, &mut data_file).is_err());
     }
-
 }
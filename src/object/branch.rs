@@ -1,20 +1,36 @@
-use super::{Key, MAX_KEY_SIZE};
+use super::{
+    node_disk_size, read_varint, shared_len, varint_size, write_varint, Key, MAX_KEY_SIZE,
+    MAX_SHORT_KEY_SIZE, UNUSED_OID,
+};
 use crate::error::TdbError;
-use crate::object::{AsObject, Object, ObjectId, ObjectTag, DATA_ALIGN};
+use crate::object::{AsObject, Object, ObjectId, ObjectTag};
 use crate::storage::{Deserialize, ObjectPos, Serialize};
+use crate::utils::{CrcReader, CrcWriter, CHECKSUM_SIZE};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::borrow::Borrow;
 use std::io::{Read, Write};
 use std::mem;
 
-const MAX_BRANCH_SIZE: u16 = DATA_ALIGN as u16;
-// key + key len + nodeid
-const MAX_NONSPLIT_BRANCH_SIZE: u16 = MAX_BRANCH_SIZE
-    - MAX_KEY_SIZE
-    - mem::size_of::<ObjectId>() as u16
-    - mem::size_of::<u8>() as u16;
+// non-root branch keeps it even if keys are long, so that it is not empty after merging children
+const MIN_BRANCH_KEYS: usize = 2;
 
-const REBALANCE_BRANCH_SIZE: u16 = MAX_BRANCH_SIZE / 4;
+// short key + shared len + key len + nodeid, longer keys make branch bigger than node size
+#[inline]
+fn max_nonsplit_size(node_size: usize) -> usize {
+    node_size
+        - MAX_SHORT_KEY_SIZE as usize
+        - mem::size_of::<ObjectId>()
+        - 2 * varint_size(MAX_SHORT_KEY_SIZE as usize)
+}
+#[inline]
+fn rebalance_size(node_size: usize) -> usize {
+    node_size / 4
+}
+// split key is chosen from keys whose left half size is within it around the middle
+#[inline]
+fn split_window_size(node_size: usize) -> usize {
+    node_size / 8
+}
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct Branch {
@@ -35,15 +51,13 @@ impl Default for Branch {
 impl Branch {
     pub fn new(key: Key, oid0: ObjectId, oid1: ObjectId) -> Self {
         assert!(key.len() <= MAX_KEY_SIZE as usize);
-        let size = Branch::get_header_size()
-            + key.len()
-            + mem::size_of::<u8>()
-            + 2 * mem::size_of::<ObjectId>();
-        Self {
+        let mut branch = Self {
             keys: vec![key],
             children: vec![oid0, oid1],
-            pos: ObjectPos::new(0, size as u16, ObjectTag::Branch),
-        }
+            ..Self::default()
+        };
+        branch.update_len();
+        branch
     }
     // Return (object,object index) greater or equal to key
     pub fn search<K: Borrow<[u8]>>(&self, key: &K) -> (ObjectId, usize) {
@@ -60,145 +74,177 @@ impl Branch {
     pub fn remove_index(&mut self, index: usize) -> (Key, ObjectId) {
         let key = self.keys.remove(index);
         let oid = self.children.remove(index + 1);
-        self.pos
-            .sub_len((key.len() + mem::size_of::<u8>() + mem::size_of::<ObjectId>()) as u16);
+        self.update_len();
         (key, oid)
     }
     pub fn update_key(&mut self, index: usize, key: Key) {
-        self.pos.sub_len(self.keys[index].len() as u16);
-        self.pos.add_len(key.len() as u16);
         self.keys[index] = key;
+        self.update_len();
     }
     // Insert object to non-full branch, branch must be dirty before insert
     pub fn insert_non_full(&mut self, index: usize, key: Key, oid: ObjectId) {
         assert!(key.len() <= MAX_KEY_SIZE as usize);
         // don't use this function for root insert
         assert!(!self.children.is_empty());
-        self.pos
-            .add_len((key.len() + mem::size_of::<u8>() + mem::size_of::<ObjectId>()) as u16);
         self.keys.insert(index, key);
         self.children.insert(index + 1, oid);
+        self.update_len();
     }
-    // Split branch whuch size biggher than MAX_NONSPLIT_BRANCH_SIZE
+    // Split branch whuch size biggher than max nonsplit size of node_size
     // Branch must be dirty befor split
     // Return split key and split Branch, solit key is used to insert split Branch in parent
-    pub fn split(&mut self) -> (Key, Self) {
-        let mut split_index = 0;
-        let mut left_size = Self::get_header_size();
-        for i in 0..self.keys.len() {
-            left_size += self.keys[i].len() + mem::size_of::<u8>() + mem::size_of::<ObjectId>();
-            split_index = i;
-            if left_size as u16 > MAX_BRANCH_SIZE / 2 {
-                // mid key will be remove and insert to parent branch
-                break;
-            }
-        }
-        let right_keys = self.keys.split_off(split_index + 1);
-        let right_children = self.children.split_off(split_index + 1);
+    // Split key isn't truncated here, keys of the child left to it are unknown to branch,
+    // it is already the shortest separator made by leaf split
+    pub fn split(&mut self, node_size: usize) -> (Key, Self) {
+        assert!(self.should_split(node_size));
+        let split_index = self.split_index(node_size);
+        let mut right_branch = Branch {
+            keys: self.keys.split_off(split_index + 1),
+            children: self.children.split_off(split_index + 1),
+            ..Branch::default()
+        };
         let split_key = self.keys.pop().unwrap();
-        let right_size = self.pos.get_len() - left_size as u16 + Self::get_header_size() as u16;
-        // children num is keys + 1
-        left_size -= split_key.len() + mem::size_of::<u8>();
-        self.pos.set_len(left_size as u16);
-        let mut right_branch = Branch::default();
-        right_branch.children = right_children;
-        right_branch.keys = right_keys;
-        right_branch.pos.set_len(right_size);
+        self.update_len();
+        right_branch.update_len();
         (split_key, right_branch)
     }
-    // Merge right branch if left < REBALANCE_BRANCH_SIZE and total size <=  MAX_NONSPLIT_BRANCH_SIZE
+    // Merge right branch if left < rebalance size and total size <= max nonsplit size
     // right_branch should be marked del after merge
     // merge_key is the key of right_branch's first child
     pub fn merge(&mut self, right_branch: &mut Branch, merge_key: Key) {
-        self.pos
-            .add_len((merge_key.len() + mem::size_of::<u8>()) as u16);
         self.keys.push(merge_key);
         self.keys.append(&mut right_branch.keys);
         self.children.append(&mut right_branch.children);
-        self.pos.add_len(right_branch.pos.get_len());
-        self.pos.sub_len(Branch::get_header_size() as u16);
+        self.update_len();
     }
-    // Rebalance left and right branch if left < REBALANCE_BRANCH_SIZE and total size > MAX_NONSPLIT_BRANCH_SIZE
+    // Rebalance left and right branch if left < rebalance size and total size > max nonsplit size
     // All two branch must be dirty
     // rebalance_key is the key of right_branch's first child
     // return remove key as new key in parrent branch
-    pub fn rebalance(&mut self, right_branch: &mut Branch, rebalance_key: Key) -> Key {
-        self.pos
-            .add_len((rebalance_key.len() + mem::size_of::<u8>()) as u16);
+    pub fn rebalance(
+        &mut self,
+        right_branch: &mut Branch,
+        rebalance_key: Key,
+        node_size: usize,
+    ) -> Key {
         self.keys.push(rebalance_key);
         self.keys.append(&mut right_branch.keys);
         self.children.append(&mut right_branch.children);
-        self.pos.add_len(right_branch.pos.get_len());
-        self.pos.sub_len(Branch::get_header_size() as u16);
-        let mut split_index = 0;
-        let mut left_size = Self::get_header_size();
-        for i in 0..self.keys.len() {
-            left_size += self.keys[i].len() + mem::size_of::<u8>() + mem::size_of::<ObjectId>();
-            split_index = i;
-            if left_size as u16 > MAX_BRANCH_SIZE / 2 {
-                break;
-            }
-        }
+        let split_index = self.split_index(node_size);
         right_branch.keys = self.keys.split_off(split_index + 1);
         right_branch.children = self.children.split_off(split_index + 1);
-        right_branch
-            .pos
-            .set_len(self.pos.get_len() - left_size as u16 + Self::get_header_size() as u16);
         let remove_key = self.keys.pop().unwrap();
-        self.pos
-            .set_len((left_size - remove_key.len() - mem::size_of::<u8>()) as u16);
+        self.update_len();
+        right_branch.update_len();
         remove_key
     }
     #[inline]
-    pub fn should_split(&self) -> bool {
-        self.pos.get_len() > MAX_NONSPLIT_BRANCH_SIZE
+    pub fn should_split(&self, node_size: usize) -> bool {
+        self.pos.get_len() as usize > max_nonsplit_size(node_size)
+            && self.keys.len() > 2 * MIN_BRANCH_KEYS
     }
     #[inline]
-    pub fn should_rebalance_merge(&self) -> bool {
-        self.pos.get_len() < REBALANCE_BRANCH_SIZE
+    pub fn should_rebalance_merge(&self, node_size: usize) -> bool {
+        (self.pos.get_len() as usize) < rebalance_size(node_size)
+            || self.keys.len() < MIN_BRANCH_KEYS
     }
+    // Branches with few long keys are merged even if merged branch is big
     #[inline]
-    pub fn should_merge(left_branch: &Branch, right_branch: &Branch) -> bool {
-        left_branch.pos.get_len() + right_branch.pos.get_len() - Branch::get_header_size() as u16
-            <= MAX_NONSPLIT_BRANCH_SIZE
+    pub fn should_merge(left_branch: &Branch, right_branch: &Branch, node_size: usize) -> bool {
+        left_branch.pos.get_len() as usize + right_branch.pos.get_len() as usize
+            - Branch::get_header_size()
+            <= max_nonsplit_size(node_size)
+            || left_branch.keys.len() + right_branch.keys.len() < 2 * MIN_BRANCH_KEYS
     }
+    #[cfg(test)]
     #[inline]
-    pub fn should_rebalance(left_branch: &Branch, right_branch: &Branch) -> bool {
-        left_branch.pos.get_len() + right_branch.pos.get_len() - Branch::get_header_size() as u16
-            > MAX_NONSPLIT_BRANCH_SIZE
+    pub fn should_rebalance(left_branch: &Branch, right_branch: &Branch, node_size: usize) -> bool {
+        !Self::should_merge(left_branch, right_branch, node_size)
     }
+    // Return size of key at index, it is stored without prefix shared with previous key
     #[inline]
-    pub fn get_key(&self) -> &Key {
-        &self.keys[0]
+    fn key_size(&self, index: usize) -> usize {
+        let key = &self.keys[index];
+        let shared = if index == 0 {
+            0
+        } else {
+            shared_len(&self.keys[index - 1], key)
+        };
+        varint_size(shared) + varint_size(key.len() - shared) + key.len() - shared
+    }
+    // Recompute size
+    fn update_len(&mut self) {
+        let size = Self::get_header_size()
+            + (0..self.keys.len())
+                .map(|i| self.key_size(i))
+                .sum::<usize>()
+            + self.children.len() * mem::size_of::<ObjectId>();
+        self.pos.set_len(size as u16);
+    }
+    // Return index of the key moved to parent
+    // both halves keep MIN_BRANCH_KEYS at least
+    // the shortest key near the middle is chosen to keep parent small
+    fn split_index(&self, node_size: usize) -> usize {
+        // size of left half with key i
+        let mut left_size = Self::get_header_size();
+        let left_sizes: Vec<usize> = (0..self.keys.len())
+            .map(|i| {
+                left_size += self.key_size(i) + mem::size_of::<ObjectId>();
+                left_size
+            })
+            .collect();
+        let (min_index, max_index) = (MIN_BRANCH_KEYS, self.keys.len() - MIN_BRANCH_KEYS - 1);
+        let mid_index = (min_index..=max_index)
+            .find(|i| left_sizes[*i] > node_size / 2)
+            .unwrap_or(max_index);
+        (min_index..=max_index)
+            .filter(|i| {
+                left_sizes[*i].abs_diff(left_sizes[mid_index]) <= split_window_size(node_size)
+            })
+            .min_by_key(|i| (self.keys[*i].len(), i.abs_diff(mid_index)))
+            .unwrap()
     }
 }
 
 impl Serialize for Branch {
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<usize, TdbError> {
+        let mut writer = CrcWriter::new(writer);
         let mut size = 0;
         // object pos
         writer.write_u64::<LittleEndian>(self.pos.0)?;
         size += mem::size_of::<u64>();
         // keys num
-        writer.write_u8(self.keys.len() as u8)?;
-        size += mem::size_of::<u8>();
+        writer.write_u16::<LittleEndian>(self.keys.len() as u16)?;
+        size += mem::size_of::<u16>();
         // keys
-        for key in self.keys.iter() {
-            writer.write_u8(key.len() as u8)?;
-            size += mem::size_of::<u8>();
-            writer.write(&key)?;
-            size += key.len();
+        for (i, key) in self.keys.iter().enumerate() {
+            // len of prefix shared with previous key
+            let shared = if i == 0 {
+                0
+            } else {
+                shared_len(&self.keys[i - 1], key)
+            };
+            write_varint(&mut writer, shared)?;
+            size += varint_size(shared);
+            // key suffix len
+            write_varint(&mut writer, key.len() - shared)?;
+            size += varint_size(key.len() - shared);
+            // key suffix
+            writer.write_all(&key[shared..])?;
+            size += key.len() - shared;
         }
         // children num
-        writer.write_u8(self.children.len() as u8)?;
-        size += mem::size_of::<u8>();
+        writer.write_u16::<LittleEndian>(self.children.len() as u16)?;
+        size += mem::size_of::<u16>();
         // children
         for child in self.children.iter() {
             writer.write_u32::<LittleEndian>(*child)?;
             size += mem::size_of::<u32>();
         }
+        // checksum, padding is not included
+        size += writer.write_checksum()?;
         // align to 4K
-        for _ in size..MAX_BRANCH_SIZE as usize {
+        while size < node_disk_size(self.pos.get_len()) {
             writer.write_u8(0)?;
             size += 1;
         }
@@ -208,26 +254,40 @@ impl Serialize for Branch {
 
 impl Deserialize for Branch {
     fn deserialize<R: Read>(reader: &mut R) -> Result<Self, TdbError> {
+        let mut reader = CrcReader::new(reader);
         // object pos
         let pos = ObjectPos(reader.read_u64::<LittleEndian>()?);
         // keys num
-        let keys_len: usize = reader.read_u8()? as usize;
-        let mut keys = Vec::with_capacity(keys_len);
+        let keys_len: usize = reader.read_u16::<LittleEndian>()?.into();
+        let mut keys: Vec<Key> = Vec::with_capacity(keys_len);
         // keys
         for _ in 0..keys_len {
-            let key_len: usize = reader.read_u8()? as usize;
-            let mut key = vec![0; key_len];
-            reader.read_exact(&mut key)?;
+            let shared = read_varint(&mut reader)?;
+            let suffix_len = read_varint(&mut reader)?;
+            let mut key = match keys.last() {
+                Some(prev_key) if shared <= prev_key.len() => prev_key[..shared].to_vec(),
+                _ if shared == 0 => Vec::with_capacity(suffix_len),
+                _ => {
+                    return Err(TdbError::Corruption {
+                        oid: UNUSED_OID,
+                        pos,
+                    })
+                }
+            };
+            key.resize(shared + suffix_len, 0);
+            reader.read_exact(&mut key[shared..])?;
             keys.push(key);
         }
         // children num
-        let children_len: usize = reader.read_u8()? as usize;
+        let children_len: usize = reader.read_u16::<LittleEndian>()?.into();
         let mut children = Vec::with_capacity(children_len);
         // children
         for _ in 0..children_len {
             let oid = reader.read_u32::<LittleEndian>()? as ObjectId;
             children.push(oid);
         }
+        // checksum
+        reader.check_checksum()?;
         Ok(Branch {
             keys,
             children,
@@ -237,14 +297,6 @@ impl Deserialize for Branch {
 }
 
 impl AsObject for Branch {
-    #[inline]
-    fn get_tag(&self) -> ObjectTag {
-        ObjectTag::Branch
-    }
-    #[inline]
-    fn get_key(&self) -> &[u8] {
-        self.keys[0].as_slice()
-    }
     #[inline]
     fn get_ref(obejct_ref: &Object) -> &Self {
         match obejct_ref {
@@ -260,18 +312,8 @@ impl AsObject for Branch {
         }
     }
     #[inline]
-    fn unwrap(obj: Object) -> Self {
-        match obj {
-            Object::B(branch) => branch,
-            _ => panic!("object isn't branch"),
-        }
-    }
-    #[inline]
     fn is(obejct_ref: &Object) -> bool {
-        match obejct_ref {
-            Object::B(_) => true,
-            _ => false,
-        }
+        matches!(obejct_ref, Object::B(_))
     }
     #[inline]
     fn get_pos(&self) -> &ObjectPos {
@@ -283,14 +325,15 @@ impl AsObject for Branch {
     }
     #[inline]
     fn get_header_size() -> usize {
-        // object_pos + key num + child num
-        mem::size_of::<u64>() + mem::size_of::<u8>() + mem::size_of::<u8>()
+        // object_pos + key num + child num + checksum
+        mem::size_of::<u64>() + mem::size_of::<u16>() + mem::size_of::<u16>() + CHECKSUM_SIZE
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::object::DATA_ALIGN;
     #[test]
     fn test_branch_serialize_deserialize() {
         // test empty
@@ -304,7 +347,7 @@ mod tests {
         branch1.keys.push(vec![1, 2, 3]);
         branch1.children.push(2);
         branch1.children.push(3);
-        branch1.pos.add_len(3 + 1 + 4 + 4);
+        branch1.pos.add_len(3 + 2 + 4 + 4);
         assert!(branch1.serialize(&mut buf.as_mut_slice()).is_ok());
         let branch11 = Branch::deserialize(&mut buf.as_slice()).unwrap();
         assert_eq!(branch1, branch11);
@@ -328,7 +371,9 @@ mod tests {
         for i in 1..3 {
             let key = vec![i];
             branch.pos.add_len(
-                key.len() as u16 + mem::size_of::<u8>() as u16 + mem::size_of::<ObjectId>() as u16,
+                key.len() as u16
+                    + 2 * mem::size_of::<u8>() as u16
+                    + mem::size_of::<ObjectId>() as u16,
             );
             branch.keys.push(key);
             branch.children.push(i as u32);
@@ -337,12 +382,12 @@ mod tests {
         branch.children.insert(0, 0);
         assert_eq!(
             branch.get_pos().get_len(),
-            Branch::get_header_size() as u16 + 2 + 2 + 4 * 3
+            Branch::get_header_size() as u16 + 3 + 3 + 4 * 3
         );
         branch.insert_non_full(2, vec![4], 4);
         assert_eq!(
             branch.get_pos().get_len(),
-            Branch::get_header_size() as u16 + 2 + 2 + 4 * 3 + 1 + 1 + 4
+            Branch::get_header_size() as u16 + 3 + 3 + 4 * 3 + 3 + 4
         );
         assert_eq!(branch.search(&vec![4]), (4, 3));
     }
@@ -353,7 +398,9 @@ mod tests {
         for i in 1..3 {
             let key = vec![i; 40];
             branch.pos.add_len(
-                key.len() as u16 + mem::size_of::<u8>() as u16 + mem::size_of::<ObjectId>() as u16,
+                key.len() as u16
+                    + 2 * mem::size_of::<u8>() as u16
+                    + mem::size_of::<ObjectId>() as u16,
             );
             branch.keys.push(key);
             branch.children.push(i as u32);
@@ -365,15 +412,15 @@ mod tests {
         }
         assert_eq!(
             branch.get_pos().get_len(),
-            Branch::get_header_size() as u16 + 40 * 100 + 1 * 100 + 4 * 101
+            Branch::get_header_size() as u16 + 40 * 100 + 2 * 100 + 4 * 101
         );
         let branch0 = branch.clone();
-        let (key, mut other) = branch.split();
-        assert_eq!(key, vec![46; 40]);
-        assert_eq!(branch.children.last().unwrap(), &45);
-        assert_eq!(other.keys[0], vec![47; 40]);
-        assert_eq!(other.children[0], 46);
-        branch.merge(&mut other, vec![46; 40]);
+        let (key, mut other) = branch.split(DATA_ALIGN);
+        assert_eq!(key, vec![45; 40]);
+        assert_eq!(branch.children.last().unwrap(), &44);
+        assert_eq!(other.keys[0], vec![46; 40]);
+        assert_eq!(other.children[0], 45);
+        branch.merge(&mut other, vec![45; 40]);
         assert_eq!(branch0, branch);
     }
 
@@ -383,7 +430,9 @@ mod tests {
         for i in 1..3 {
             let key = vec![i; 40];
             branch0.pos.add_len(
-                key.len() as u16 + mem::size_of::<u8>() as u16 + mem::size_of::<ObjectId>() as u16,
+                key.len() as u16
+                    + 2 * mem::size_of::<u8>() as u16
+                    + mem::size_of::<ObjectId>() as u16,
             );
             branch0.keys.push(key);
             branch0.children.push(i as u32);
@@ -394,7 +443,9 @@ mod tests {
         for i in 4..6 {
             let key = vec![i; 40];
             branch1.pos.add_len(
-                key.len() as u16 + mem::size_of::<u8>() as u16 + mem::size_of::<ObjectId>() as u16,
+                key.len() as u16
+                    + 2 * mem::size_of::<u8>() as u16
+                    + mem::size_of::<ObjectId>() as u16,
             );
             branch1.keys.push(key);
             branch1.children.push(i as u32);
@@ -405,7 +456,9 @@ mod tests {
         for i in 1..6 {
             let key = vec![i; 40];
             branch3.pos.add_len(
-                key.len() as u16 + mem::size_of::<u8>() as u16 + mem::size_of::<ObjectId>() as u16,
+                key.len() as u16
+                    + 2 * mem::size_of::<u8>() as u16
+                    + mem::size_of::<ObjectId>() as u16,
             );
             branch3.keys.push(key);
             branch3.children.push(i as u32);
@@ -422,7 +475,9 @@ mod tests {
         for i in 1..3 {
             let key = vec![i; 40];
             branch0.pos.add_len(
-                key.len() as u16 + mem::size_of::<u8>() as u16 + mem::size_of::<ObjectId>() as u16,
+                key.len() as u16
+                    + 2 * mem::size_of::<u8>() as u16
+                    + mem::size_of::<ObjectId>() as u16,
             );
             branch0.keys.push(key);
             branch0.children.push(i as u32);
@@ -436,7 +491,9 @@ mod tests {
         for i in 11..13 {
             let key = vec![i; 40];
             branch1.pos.add_len(
-                key.len() as u16 + mem::size_of::<u8>() as u16 + mem::size_of::<ObjectId>() as u16,
+                key.len() as u16
+                    + 2 * mem::size_of::<u8>() as u16
+                    + mem::size_of::<ObjectId>() as u16,
             );
             branch1.keys.push(key);
             branch1.children.push(i as u32);
@@ -446,21 +503,23 @@ mod tests {
         for i in 2..90 {
             branch1.insert_non_full(i, vec![i as u8 + 11; 40], i as u32 + 11);
         }
-        assert!(Branch::should_rebalance(&branch0, &branch1));
-        let key = branch0.rebalance(&mut branch1, vec![10; 40]);
-        assert_eq!(key, vec![46; 40]);
+        assert!(Branch::should_rebalance(&branch0, &branch1, DATA_ALIGN));
+        let key = branch0.rebalance(&mut branch1, vec![10; 40], DATA_ALIGN);
+        assert_eq!(key, vec![45; 40]);
         let mut new_branch0 = branch0.clone();
         let mut new_branch1 = branch1.clone();
-        new_branch0.merge(&mut new_branch1, vec![46; 40]);
-        let (key, new_branch1) = new_branch0.split();
-        assert_eq!(key, vec![46; 40]);
+        new_branch0.merge(&mut new_branch1, vec![45; 40]);
+        let (key, new_branch1) = new_branch0.split(DATA_ALIGN);
+        assert_eq!(key, vec![45; 40]);
         assert_eq!(branch0, new_branch0);
         assert_eq!(branch1, new_branch1);
         let mut branch = Branch::default();
         for i in 1..3 {
             let key = vec![i; 40];
             branch.pos.add_len(
-                key.len() as u16 + mem::size_of::<u8>() as u16 + mem::size_of::<ObjectId>() as u16,
+                key.len() as u16
+                    + 2 * mem::size_of::<u8>() as u16
+                    + mem::size_of::<ObjectId>() as u16,
             );
             branch.keys.push(key);
             branch.children.push(i as u32);
@@ -470,9 +529,98 @@ mod tests {
         for i in 2..100 {
             branch.insert_non_full(i, vec![i as u8 + 1; 40], i as u32 + 1);
         }
-        let (key, mut other) = branch.split();
+        let (_key, other) = branch.split(DATA_ALIGN);
         assert_eq!(branch, branch0);
         assert_eq!(other, branch1);
     }
 
+    #[test]
+    fn test_branch_long_key() {
+        let mut branch = Branch::new(vec![1; 300], 0, 1);
+        assert_eq!(
+            branch.get_pos().get_len(),
+            Branch::get_header_size() as u16 + 1 + 2 + 300 + 4 * 2
+        );
+        for i in 1..5 {
+            branch.insert_non_full(i, vec![i as u8 + 1; 4096], i as u32 + 1);
+        }
+        let mut buf = vec![0; 20480];
+        assert_eq!(branch.serialize(&mut buf.as_mut_slice()), Ok(20480));
+        assert_eq!(Branch::deserialize(&mut buf.as_slice()), Ok(branch.clone()));
+        assert!(branch.should_split(DATA_ALIGN));
+        let branch0 = branch.clone();
+        let (key, mut other) = branch.split(DATA_ALIGN);
+        assert_eq!(key, vec![3; 4096]);
+        assert_eq!(branch.keys, vec![vec![1; 300], vec![2; 4096]]);
+        assert_eq!(other.keys.len(), 2);
+        branch.merge(&mut other, key);
+        assert_eq!(branch0, branch);
+    }
+
+    #[test]
+    fn test_branch_split_shortest_key() {
+        let mut branch = Branch::new(vec![0; 40], 0, 1);
+        for i in 1..100 {
+            // key 50 is the only short one
+            let len = if i == 50 { 1 } else { 40 };
+            branch.insert_non_full(i, vec![i as u8; len], i as u32 + 1);
+        }
+        let (key, other) = branch.split(DATA_ALIGN);
+        assert_eq!(key, vec![50]);
+        assert_eq!(branch.keys.len(), 50);
+        assert_eq!(other.keys.len(), 49);
+    }
+
+    #[test]
+    fn test_branch_many_keys() {
+        let mut branch = Branch::new(vec![0, 0], 0, 1);
+        for i in 1..1000u16 {
+            branch.insert_non_full(i as usize, i.to_be_bytes().to_vec(), i as u32 + 1);
+        }
+        assert_eq!(
+            branch.get_pos().get_len(),
+            // keys 0, 256, 512 and 768 share no prefix with previous key
+            Branch::get_header_size() as u16 + 1000 * (1 + 1 + 1) + 4 + 1001 * 4
+        );
+        let mut buf = vec![0; 8192];
+        assert_eq!(branch.serialize(&mut buf.as_mut_slice()), Ok(8192));
+        assert_eq!(Branch::deserialize(&mut buf.as_slice()), Ok(branch.clone()));
+        // larger node is split later
+        assert!(branch.should_split(DATA_ALIGN));
+        assert!(!branch.should_split(2 * DATA_ALIGN));
+        let (_, other) = branch.split(DATA_ALIGN);
+        assert_eq!(branch.keys.len() + other.keys.len(), 999);
+        assert_eq!(branch.keys[256], vec![1, 0]);
+        assert_eq!(
+            branch.get_pos().get_len(),
+            Branch::get_header_size() as u16 + branch.keys.len() as u16 * 7 + 2 + 4
+        );
+    }
+
+    #[test]
+    fn test_branch_prefix_compression() {
+        let prefix = vec![7; 200];
+        let key = |i: u8| [prefix.as_slice(), &[i]].concat();
+        let mut branch = Branch::new(key(0), 0, 1);
+        for i in 1..100 {
+            branch.insert_non_full(i as usize, key(i), i as u32 + 1);
+        }
+        // only the first key is stored in full
+        assert_eq!(
+            branch.get_pos().get_len(),
+            Branch::get_header_size() as u16 + 1 + 2 + 201 + 99 * (2 + 1 + 1) + 101 * 4
+        );
+        assert!(!branch.should_split(DATA_ALIGN));
+        let mut buf = vec![0; DATA_ALIGN];
+        assert_eq!(branch.serialize(&mut buf.as_mut_slice()), Ok(DATA_ALIGN));
+        assert_eq!(Branch::deserialize(&mut buf.as_slice()), Ok(branch.clone()));
+        // shared len of the first key must be 0
+        let branch0 = Branch::new(vec![1, 2, 3], 0, 1);
+        branch0.serialize(&mut buf.as_mut_slice()).unwrap();
+        buf[8 + 2] = 1;
+        assert!(matches!(
+            Branch::deserialize(&mut buf.as_slice()),
+            Err(TdbError::Corruption { .. })
+        ));
+    }
 }
@@ -1,9 +1,9 @@
-use super::{Key, Val, MAX_KEY_SIZE};
+use super::{Key, ObjectId, Val, MAX_KEY_SIZE, MAX_OBJ_SIZE, UNUSED_OID};
 use crate::error::TdbError;
 use crate::object::{AsObject, Object, ObjectTag};
 use crate::storage::{Deserialize, ObjectPos, Serialize};
+use crate::utils::{CrcReader, CrcWriter, CHECKSUM_SIZE};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::convert::TryInto;
 use std::io::{Read, Write};
 use std::mem;
 
@@ -11,23 +11,36 @@ use std::mem;
 pub struct Entry {
     pub key: Key,
     pub val: Val,
+    // first overflow object of val, UNUSED_OID if val is not overflowed
+    pub overflow_oid: ObjectId,
     pos: ObjectPos,
 }
 
 impl Entry {
     pub fn new(key: Key, val: Val) -> Self {
+        Self::with_overflow(key, val, UNUSED_OID)
+    }
+    /// Entry whose val is continued by overflow chain
+    pub fn with_overflow(key: Key, val: Val, overflow_oid: ObjectId) -> Self {
         assert!(key.len() <= MAX_KEY_SIZE as usize);
         let size = Self::get_header_size() + key.len() + val.len();
         Self {
             key,
             val,
+            overflow_oid,
             pos: ObjectPos::new(0, size as u16, ObjectTag::Entry),
         }
     }
-    pub fn update(&mut self, val: Val) {
+    pub fn update(&mut self, val: Val, overflow_oid: ObjectId) {
         self.pos.sub_len(self.val.len() as u16);
         self.pos.add_len(val.len() as u16);
         self.val = val;
+        self.overflow_oid = overflow_oid;
+    }
+    /// Max size of val in entry, the rest is put in overflow objects
+    #[inline]
+    pub fn max_val_size(key_len: usize) -> usize {
+        MAX_OBJ_SIZE as usize - Self::get_header_size() - key_len
     }
     pub fn get_key_val(&self) -> (Key, Val) {
         (self.key.clone(), self.val.clone())
@@ -39,6 +52,7 @@ impl Default for Entry {
         Self {
             key: Vec::with_capacity(0),
             val: Vec::with_capacity(0),
+            overflow_oid: UNUSED_OID,
             pos: ObjectPos::default(),
         }
     }
@@ -46,54 +60,61 @@ impl Default for Entry {
 
 impl Serialize for Entry {
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<usize, TdbError> {
+        let mut writer = CrcWriter::new(writer);
         let mut size = 0;
         // object info
         writer.write_u64::<LittleEndian>(self.pos.0)?;
         size += mem::size_of::<u64>();
         // key len
-        writer.write_u8(self.key.len() as u8)?;
-        size += mem::size_of::<u8>();
+        writer.write_u16::<LittleEndian>(self.key.len() as u16)?;
+        size += mem::size_of::<u16>();
         // key
-        writer.write(&self.key)?;
+        writer.write_all(&self.key)?;
         size += self.key.len();
         // val len
         writer.write_u16::<LittleEndian>(self.val.len() as u16)?;
         size += mem::size_of::<u16>();
         // val
-        writer.write(&self.val)?;
+        writer.write_all(&self.val)?;
         size += self.val.len();
+        // overflow oid
+        writer.write_u32::<LittleEndian>(self.overflow_oid)?;
+        size += mem::size_of::<ObjectId>();
+        // checksum
+        size += writer.write_checksum()?;
         Ok(size)
     }
 }
 
 impl Deserialize for Entry {
     fn deserialize<R: Read>(reader: &mut R) -> Result<Self, TdbError> {
+        let mut reader = CrcReader::new(reader);
         // object pos
         let pos = ObjectPos(reader.read_u64::<LittleEndian>()?);
         // key len
-        let key_len: usize = reader.read_u8()?.try_into().unwrap();
+        let key_len: usize = reader.read_u16::<LittleEndian>()?.into();
         // key
         let mut key = vec![0; key_len];
         reader.read_exact(&mut key)?;
         // val len
-        let val_len: usize = reader.read_u16::<LittleEndian>()?.try_into().unwrap();
+        let val_len: usize = reader.read_u16::<LittleEndian>()?.into();
         // val
         let mut val = vec![0; val_len];
         reader.read_exact(&mut val)?;
-        Ok(Entry { key, val, pos })
+        // overflow oid
+        let overflow_oid = reader.read_u32::<LittleEndian>()?;
+        // checksum
+        reader.check_checksum()?;
+        Ok(Entry {
+            key,
+            val,
+            overflow_oid,
+            pos,
+        })
     }
 }
 
 impl AsObject for Entry {
-    #[inline]
-    fn get_tag(&self) -> ObjectTag {
-        ObjectTag::Entry
-    }
-
-    #[inline]
-    fn get_key(&self) -> &[u8] {
-        self.key.as_slice()
-    }
     #[inline]
     fn get_ref(obejct_ref: &Object) -> &Self {
         match obejct_ref {
@@ -109,18 +130,8 @@ impl AsObject for Entry {
         }
     }
     #[inline]
-    fn unwrap(obj: Object) -> Self {
-        match obj {
-            Object::E(entry) => entry,
-            _ => panic!("object isn't entry"),
-        }
-    }
-    #[inline]
     fn is(obejct_ref: &Object) -> bool {
-        match obejct_ref {
-            Object::E(_) => true,
-            _ => false,
-        }
+        matches!(obejct_ref, Object::E(_))
     }
     #[inline]
     fn get_pos(&self) -> &ObjectPos {
@@ -132,8 +143,12 @@ impl AsObject for Entry {
     }
     #[inline]
     fn get_header_size() -> usize {
-        // obj pos + key len + val len
-        mem::size_of::<u64>() + mem::size_of::<u8>() + mem::size_of::<u16>()
+        // obj pos + key len + val len + overflow oid + checksum
+        mem::size_of::<u64>()
+            + mem::size_of::<u16>()
+            + mem::size_of::<u16>()
+            + mem::size_of::<ObjectId>()
+            + CHECKSUM_SIZE
     }
 }
 
@@ -153,6 +168,26 @@ mod tests {
         assert!(entry1.serialize(&mut buf.as_mut_slice()).is_ok());
         let entry11 = Entry::deserialize(&mut buf.as_slice()).unwrap();
         assert_eq!(entry1, entry11);
-        assert_eq!(entry1.pos.get_len(), 8 + 1 + 2 + 3 + 3);
+        assert_eq!(entry1.pos.get_len(), 8 + 2 + 2 + 3 + 3 + 4 + 4);
+        // flip a bit
+        buf[12] ^= 1;
+        assert_eq!(
+            Entry::deserialize(&mut buf.as_slice()),
+            Err(TdbError::Corruption {
+                oid: UNUSED_OID,
+                pos: ObjectPos::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_entry_long_key() {
+        let entry0 = Entry::new(vec![1; MAX_KEY_SIZE as usize], vec![2, 2, 2]);
+        let mut buf = vec![0; 8192];
+        assert_eq!(
+            entry0.serialize(&mut buf.as_mut_slice()),
+            Ok(entry0.pos.get_len() as usize)
+        );
+        assert_eq!(Entry::deserialize(&mut buf.as_slice()), Ok(entry0));
     }
 }
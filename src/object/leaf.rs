@@ -1,23 +1,35 @@
-use super::{Key, MAX_KEY_SIZE};
+use super::{
+    node_disk_size, read_varint, shared_len, varint_size, write_varint, Key, Val,
+    MAX_SHORT_KEY_SIZE, UNUSED_OID,
+};
 use crate::error::TdbError;
-use crate::object::{AsObject, Object, ObjectId, ObjectTag, DATA_ALIGN};
+use crate::object::{AsObject, Object, ObjectId, ObjectTag};
 use crate::storage::{Deserialize, ObjectPos, Serialize};
+use crate::utils::{CrcReader, CrcWriter, CHECKSUM_SIZE};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::borrow::Borrow;
 use std::io::{Read, Write};
 use std::mem;
 
-const MAX_LEAF_SIZE: u16 = DATA_ALIGN as u16;
-// key + key len + nodeid
-const MAX_NONSPLIT_LEAF_SIZE: u16 =
-    MAX_LEAF_SIZE - MAX_KEY_SIZE - mem::size_of::<ObjectId>() as u16 - mem::size_of::<u8>() as u16;
-
-const REBALANCE_LEAF_SIZE: u16 = MAX_LEAF_SIZE / 4;
+// short key + shared len + key len + nodeid, longer keys make leaf bigger than node size
+#[inline]
+fn max_nonsplit_size(node_size: usize) -> usize {
+    node_size
+        - MAX_SHORT_KEY_SIZE as usize
+        - mem::size_of::<ObjectId>()
+        - 2 * varint_size(MAX_SHORT_KEY_SIZE as usize)
+}
+#[inline]
+fn rebalance_size(node_size: usize) -> usize {
+    node_size / 4
+}
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct Leaf {
-    pub entrys: Vec<(Key, ObjectId)>,
+    pub entrys: Vec<(Key, LeafVal)>,
     pub pos: ObjectPos,
+    // size of compressed keys with their lens and vals
+    entrys_size: usize,
 }
 
 impl Default for Leaf {
@@ -25,19 +37,38 @@ impl Default for Leaf {
         Self {
             entrys: Vec::with_capacity(0),
             pos: ObjectPos::new(0, Self::get_header_size() as u16, ObjectTag::Leaf),
+            entrys_size: 0,
+        }
+    }
+}
+
+/// Val of leaf entry, small val is put in leaf instead of entry object
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum LeafVal {
+    Oid(ObjectId),
+    Inline(Val),
+}
+
+impl LeafVal {
+    // Inline val is written after UNUSED_OID
+    #[inline]
+    fn get_size(&self) -> usize {
+        match self {
+            LeafVal::Oid(_) => mem::size_of::<ObjectId>(),
+            LeafVal::Inline(val) => mem::size_of::<ObjectId>() + mem::size_of::<u16>() + val.len(),
         }
     }
 }
 
 impl Leaf {
-    // Search oid corresponding to key
-    // Return oid if find else index for insert
-    pub fn search<K: Borrow<[u8]>>(&self, key: &K) -> Result<ObjectId, usize> {
+    // Search val corresponding to key
+    // Return val if find else index for insert
+    pub fn search<K: Borrow<[u8]>>(&self, key: &K) -> Result<&LeafVal, usize> {
         match self
             .entrys
             .binary_search_by(|_key| _key.0.as_slice().cmp(key.borrow()))
         {
-            Ok(index) => Ok(self.entrys[index].1),
+            Ok(index) => Ok(&self.entrys[index].1),
             Err(index) => Err(index),
         }
     }
@@ -53,114 +84,166 @@ impl Leaf {
         }
     }
     // Insert object to non-full leaf, leaf must be dirty before insert
-    pub fn insert_non_full(&mut self, index: usize, key: Key, oid: ObjectId) {
-        self.pos
-            .add_len((key.len() + mem::size_of::<u8>() + mem::size_of::<ObjectId>()) as u16);
-        self.entrys.insert(index, (key, oid));
+    pub fn insert_non_full(&mut self, index: usize, key: Key, val: LeafVal) {
+        // next key shares prefix with the inserted one instead of previous one
+        self.sub_entry_size(index);
+        self.entrys.insert(index, (key, val));
+        self.add_entry_size(index);
+        self.add_entry_size(index + 1);
+        self.update_len();
+    }
+    // Replace val at index, return old val
+    pub fn update_val(&mut self, index: usize, val: LeafVal) -> LeafVal {
+        self.sub_entry_size(index);
+        let old_val = mem::replace(&mut self.entrys[index].1, val);
+        self.add_entry_size(index);
+        self.update_len();
+        old_val
     }
 
     // Remove obj corresponding to key
-    pub fn remove<K: Borrow<[u8]>>(&mut self, key: &K) -> Option<(Key, ObjectId)> {
+    pub fn remove<K: Borrow<[u8]>>(&mut self, key: &K) -> Option<(Key, LeafVal)> {
         match self
             .entrys
             .binary_search_by(|_key| _key.0.as_slice().cmp(key.borrow()))
         {
             Ok(index) => {
-                self.pos.sub_len(
-                    (key.borrow().len() + mem::size_of::<u8>() + mem::size_of::<ObjectId>()) as u16,
-                );
-                Some(self.entrys.remove(index))
+                self.sub_entry_size(index);
+                self.sub_entry_size(index + 1);
+                let entry = self.entrys.remove(index);
+                self.add_entry_size(index);
+                self.update_len();
+                Some(entry)
             }
             Err(_) => None,
         }
     }
 
-    // Split leaf which size bigger than MAX_NONSPLIT_LEAF_SIZE
+    // Split leaf which size bigger than max nonsplit size of node_size
     // Leaf must be dirty befor split
     // Return split key and split Leaf, solit key is used to insert split Leaf in parent
-    pub fn split(&mut self) -> (Key, Self) {
-        assert!(self.pos.get_len() > MAX_NONSPLIT_LEAF_SIZE);
-        let mut split_index = 0;
-        let mut left_size = Self::get_header_size();
-        for i in 0..self.entrys.len() {
-            left_size += self.entrys[i].0.len() + mem::size_of::<u8>() + mem::size_of::<ObjectId>();
-            split_index = i;
-            if left_size as u16 > MAX_LEAF_SIZE / 2 {
-                left_size -=
-                    self.entrys[i].0.len() + mem::size_of::<u8>() + mem::size_of::<ObjectId>();
-                break;
-            }
-        }
-        let right_entrys = self.entrys.split_off(split_index);
-        let right_size = self.pos.get_len() - left_size as u16 + Self::get_header_size() as u16;
-        let split_key = right_entrys[0].0.clone();
-        self.pos.set_len(left_size as u16);
-        let mut right_leaf = Leaf::default();
-        right_leaf.entrys = right_entrys;
-        right_leaf.pos.set_len(right_size);
-        (split_key, right_leaf)
-    }
-    // Merge right leaf if left < REBALANCE_LEAF_SIZE and total size <= MAX_NONSPLIT_LEAF_SIZE
+    pub fn split(&mut self, node_size: usize) -> (Key, Self) {
+        assert!(self.should_split(node_size));
+        let split_index = self.split_index(node_size);
+        let mut right_leaf = Leaf {
+            entrys: self.entrys.split_off(split_index),
+            ..Leaf::default()
+        };
+        self.reset_len();
+        right_leaf.reset_len();
+        (self.separator(&right_leaf), right_leaf)
+    }
+    // Merge right leaf if left < rebalance size and total size <= max nonsplit size
     // right leaf should be marked del after merge
     pub fn merge(&mut self, right_leaf: &mut Leaf) {
-        for entry in right_leaf.entrys.iter() {
-            self.entrys.push(entry.clone());
-        }
-        self.pos.sub_len(Self::get_header_size() as u16);
-        self.pos.add_len(right_leaf.pos.get_len());
+        self.entrys_size = Self::merged_entrys_size(self, right_leaf);
+        self.entrys.extend(right_leaf.entrys.iter().cloned());
+        self.update_len();
     }
-    // Rebalance left and right leaf if left < REBALANCE_LEAF_SIZE and total size > MAX_NONSPLIT_LEAF_SIZE
+    // Rebalance left and right leaf if left < rebalance size and total size > max nonsplit size
     // All two left must be dirty
     // return mid key as new key in parrent branch
-    pub fn rebalance(&mut self, right_leaf: &mut Leaf) -> Key {
+    pub fn rebalance(&mut self, right_leaf: &mut Leaf, node_size: usize) -> Key {
         self.entrys.append(&mut right_leaf.entrys);
-        self.pos.sub_len(Self::get_header_size() as u16);
-        self.pos.add_len(right_leaf.pos.get_len());
-        let mut split_index = 0;
-        let mut left_size = Self::get_header_size();
-        for i in 0..self.entrys.len() {
-            left_size += self.entrys[i].0.len() + mem::size_of::<u8>() + mem::size_of::<ObjectId>();
-            split_index = i;
-            if left_size as u16 > MAX_LEAF_SIZE / 2 {
-                left_size -=
-                    self.entrys[i].0.len() + mem::size_of::<u8>() + mem::size_of::<ObjectId>();;
-                break;
-            }
-        }
+        let split_index = self.split_index(node_size);
         right_leaf.entrys = self.entrys.split_off(split_index);
-        right_leaf
-            .pos
-            .add_len(self.pos.get_len() + Self::get_header_size() as u16);
-        right_leaf.pos.sub_len(left_size as u16);
-        self.pos.set_len(left_size as u16);
-        right_leaf.entrys[0].0.clone()
+        self.reset_len();
+        right_leaf.reset_len();
+        self.separator(right_leaf)
+    }
+    #[inline]
+    pub fn should_split(&self, node_size: usize) -> bool {
+        self.pos.get_len() as usize > max_nonsplit_size(node_size) && self.entrys.len() > 1
+    }
+    #[inline]
+    pub fn should_rebalance_merge(&self, node_size: usize) -> bool {
+        (self.pos.get_len() as usize) < rebalance_size(node_size)
+    }
+    // Leaves with a single long key are merged even if merged leaf is big
+    pub fn should_merge(left_branch: &Leaf, right_branch: &Leaf, node_size: usize) -> bool {
+        let entrys_len = left_branch.entrys.len() + right_branch.entrys.len();
+        let size = Self::get_header_size() + Self::merged_entrys_size(left_branch, right_branch);
+        size <= max_nonsplit_size(node_size) || entrys_len < 2
+    }
+    #[inline]
+    pub fn should_rebalance(left_branch: &Leaf, right_branch: &Leaf, node_size: usize) -> bool {
+        !Self::should_merge(left_branch, right_branch, node_size)
+    }
+    // Shortest key greater than last key of left leaf and not greater than first key of right leaf
+    fn separator(&self, right_leaf: &Leaf) -> Key {
+        let right_key = &right_leaf.entrys[0].0;
+        let len = shared_len(&self.entrys.last().unwrap().0, right_key) + 1;
+        right_key[..len].to_vec()
     }
+    // Return size of entry whose key is stored without prefix of shared len
     #[inline]
-    pub fn should_split(&self) -> bool {
-        self.pos.get_len() as u16 > MAX_NONSPLIT_LEAF_SIZE
+    fn compressed_size(shared: usize, key: &[u8], val: &LeafVal) -> usize {
+        varint_size(shared) + varint_size(key.len() - shared) + key.len() - shared + val.get_size()
     }
+    // Return size of entry at index, its key is stored without prefix shared with previous key
     #[inline]
-    pub fn should_rebalance_merge(&self) -> bool {
-        self.pos.get_len() < REBALANCE_LEAF_SIZE
+    fn entry_size(&self, index: usize) -> usize {
+        let (key, val) = &self.entrys[index];
+        let shared = if index == 0 {
+            0
+        } else {
+            shared_len(&self.entrys[index - 1].0, key)
+        };
+        Self::compressed_size(shared, key, val)
+    }
+    // Return entrys size after merge, first key of right leaf shares prefix with last key of left leaf
+    fn merged_entrys_size(left_leaf: &Leaf, right_leaf: &Leaf) -> usize {
+        let size = left_leaf.entrys_size + right_leaf.entrys_size;
+        match (left_leaf.entrys.last(), right_leaf.entrys.first()) {
+            (Some((left_key, _)), Some((right_key, val))) => {
+                size - Self::compressed_size(0, right_key, val)
+                    + Self::compressed_size(shared_len(left_key, right_key), right_key, val)
+            }
+            _ => size,
+        }
     }
+    // Entry sizes change with neighbour keys, so they are removed before update and added back after it
     #[inline]
-    pub fn should_merge(left_branch: &Leaf, right_branch: &Leaf) -> bool {
-        left_branch.pos.get_len() + right_branch.pos.get_len() - Self::get_header_size() as u16
-            <= MAX_NONSPLIT_LEAF_SIZE
+    fn sub_entry_size(&mut self, index: usize) {
+        if index < self.entrys.len() {
+            self.entrys_size -= self.entry_size(index);
+        }
     }
     #[inline]
-    pub fn should_rebalance(left_branch: &Leaf, right_branch: &Leaf) -> bool {
-        left_branch.pos.get_len() + right_branch.pos.get_len() - Self::get_header_size() as u16
-            > MAX_NONSPLIT_LEAF_SIZE
+    fn add_entry_size(&mut self, index: usize) {
+        if index < self.entrys.len() {
+            self.entrys_size += self.entry_size(index);
+        }
     }
+    // Set len by entrys size
     #[inline]
-    pub fn get_key(&self) -> &Key {
-        &self.entrys[0].0
+    fn update_len(&mut self) {
+        let size = Self::get_header_size() + self.entrys_size;
+        self.pos.set_len(size as u16);
+    }
+    // Recompute entrys size, called after entrys are moved
+    fn reset_len(&mut self) {
+        self.entrys_size = (0..self.entrys.len()).map(|i| self.entry_size(i)).sum();
+        self.update_len();
+    }
+    // Return index of the first entry of right half, both halves keep one entry at least
+    fn split_index(&self, node_size: usize) -> usize {
+        let mut split_index = 0;
+        let mut left_size = Self::get_header_size();
+        while split_index < self.entrys.len() {
+            left_size += self.entry_size(split_index);
+            if left_size > node_size / 2 {
+                break;
+            }
+            split_index += 1;
+        }
+        split_index.clamp(1, self.entrys.len() - 1)
     }
 }
 
 impl Serialize for Leaf {
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<usize, TdbError> {
+        let mut writer = CrcWriter::new(writer);
         let mut size = 0;
         // object pos
         writer.write_u64::<LittleEndian>(self.pos.0)?;
@@ -169,18 +252,42 @@ impl Serialize for Leaf {
         writer.write_u16::<LittleEndian>(self.entrys.len() as u16)?;
         size += mem::size_of::<u16>();
         // entrys
-        for (key, oid) in self.entrys.iter() {
-            // key len
-            writer.write_u8(key.len() as u8)?;
-            size += mem::size_of::<u8>();
-            // key
-            writer.write(&key)?;
-            size += key.len();
-            // oid
-            writer.write_u32::<LittleEndian>(*oid)?;
-            size += mem::size_of::<u32>();
+        for (i, (key, val)) in self.entrys.iter().enumerate() {
+            // len of prefix shared with previous key
+            let shared = if i == 0 {
+                0
+            } else {
+                shared_len(&self.entrys[i - 1].0, key)
+            };
+            write_varint(&mut writer, shared)?;
+            size += varint_size(shared);
+            // key suffix len
+            write_varint(&mut writer, key.len() - shared)?;
+            size += varint_size(key.len() - shared);
+            // key suffix
+            writer.write_all(&key[shared..])?;
+            size += key.len() - shared;
+            match val {
+                // oid
+                LeafVal::Oid(oid) => {
+                    writer.write_u32::<LittleEndian>(*oid)?;
+                    size += mem::size_of::<u32>();
+                }
+                // UNUSED_OID + val len + val
+                LeafVal::Inline(val) => {
+                    writer.write_u32::<LittleEndian>(UNUSED_OID)?;
+                    size += mem::size_of::<u32>();
+                    writer.write_u16::<LittleEndian>(val.len() as u16)?;
+                    size += mem::size_of::<u16>();
+                    writer.write_all(val)?;
+                    size += val.len();
+                }
+            }
         }
-        for _ in size..MAX_LEAF_SIZE as usize {
+        // checksum, padding is not included
+        size += writer.write_checksum()?;
+        // align to 4K
+        while size < node_disk_size(self.pos.get_len()) {
             writer.write_u8(0)?;
             size += 1
         }
@@ -190,32 +297,53 @@ impl Serialize for Leaf {
 
 impl Deserialize for Leaf {
     fn deserialize<R: Read>(reader: &mut R) -> Result<Self, TdbError> {
+        let mut reader = CrcReader::new(reader);
         // object pos
         let pos = ObjectPos(reader.read_u64::<LittleEndian>()?);
         // entrys num
-        let entrys_len: usize = reader.read_u16::<LittleEndian>()? as usize;
-        let mut entrys = Vec::with_capacity(entrys_len);
+        let entrys_len: usize = reader.read_u16::<LittleEndian>()?.into();
+        let mut entrys: Vec<(Key, LeafVal)> = Vec::with_capacity(entrys_len);
         // entrys
         for _ in 0..entrys_len {
-            let key_len: usize = reader.read_u8()? as usize;
-            let mut key = vec![0; key_len];
-            reader.read_exact(&mut key)?;
+            let shared = read_varint(&mut reader)?;
+            let suffix_len = read_varint(&mut reader)?;
+            let mut key = match entrys.last() {
+                Some((prev_key, _)) if shared <= prev_key.len() => prev_key[..shared].to_vec(),
+                _ if shared == 0 => Vec::with_capacity(suffix_len),
+                _ => {
+                    return Err(TdbError::Corruption {
+                        oid: UNUSED_OID,
+                        pos,
+                    })
+                }
+            };
+            key.resize(shared + suffix_len, 0);
+            reader.read_exact(&mut key[shared..])?;
             let oid = reader.read_u32::<LittleEndian>()? as ObjectId;
-            entrys.push((key, oid));
+            let val = if oid == UNUSED_OID {
+                let val_len: usize = reader.read_u16::<LittleEndian>()?.into();
+                let mut val = vec![0; val_len];
+                reader.read_exact(&mut val)?;
+                LeafVal::Inline(val)
+            } else {
+                LeafVal::Oid(oid)
+            };
+            entrys.push((key, val));
         }
-        Ok(Leaf { entrys, pos })
+        // checksum
+        reader.check_checksum()?;
+        let mut leaf = Leaf {
+            entrys,
+            pos,
+            entrys_size: 0,
+        };
+        // len is kept as written
+        leaf.reset_len();
+        leaf.pos = pos;
+        Ok(leaf)
     }
 }
-
 impl AsObject for Leaf {
-    #[inline]
-    fn get_tag(&self) -> ObjectTag {
-        ObjectTag::Leaf
-    }
-    #[inline]
-    fn get_key(&self) -> &[u8] {
-        self.entrys[0].0.as_slice()
-    }
     #[inline]
     fn get_ref(obejct_ref: &Object) -> &Self {
         match obejct_ref {
@@ -231,18 +359,8 @@ impl AsObject for Leaf {
         }
     }
     #[inline]
-    fn unwrap(obj: Object) -> Self {
-        match obj {
-            Object::L(leaf) => leaf,
-            _ => panic!("object isn't leaf"),
-        }
-    }
-    #[inline]
     fn is(obejct_ref: &Object) -> bool {
-        match obejct_ref {
-            Object::L(_) => true,
-            _ => false,
-        }
+        matches!(obejct_ref, Object::L(_))
     }
     #[inline]
     fn get_pos(&self) -> &ObjectPos {
@@ -254,14 +372,15 @@ impl AsObject for Leaf {
     }
     #[inline]
     fn get_header_size() -> usize {
-        // obj pos + entry num
-        mem::size_of::<u64>() + mem::size_of::<u16>()
+        // obj pos + entry num + checksum
+        mem::size_of::<u64>() + mem::size_of::<u16>() + CHECKSUM_SIZE
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::object::DATA_ALIGN;
     #[test]
     fn test_leaf_serialize_deserialize() {
         // test empty serialize
@@ -271,17 +390,17 @@ mod tests {
         assert_eq!(leaf, Leaf::deserialize(&mut buf.as_slice()).unwrap());
         // test one
         let mut leaf = Leaf::default();
-        leaf.insert_non_full(0, vec![0; 40], 0);
+        leaf.insert_non_full(0, vec![0; 40], LeafVal::Oid(0));
         assert!(leaf.serialize(&mut buf.as_mut_slice()).is_ok());
         assert_eq!(leaf, Leaf::deserialize(&mut buf.as_slice()).unwrap());
-        assert_eq!(leaf.pos.get_len(), 8 + 2 + 1 + 40 + 4);
+        assert_eq!(leaf.pos.get_len(), 8 + 2 + 4 + 1 + 1 + 40 + 4);
     }
 
     #[test]
     fn test_leaf_search() {
         let mut leaf = Leaf::default();
-        leaf.insert_non_full(0, vec![1; 40], 0);
-        assert_eq!(leaf.search(&vec![1; 40]), Ok(0));
+        leaf.insert_non_full(0, vec![1; 40], LeafVal::Oid(0));
+        assert_eq!(leaf.search(&vec![1; 40]), Ok(&LeafVal::Oid(0)));
         assert_eq!(leaf.search(&vec![2; 40]), Err(1));
         assert_eq!(leaf.search(&vec![0; 40]), Err(0));
     }
@@ -290,14 +409,15 @@ mod tests {
     fn test_leaf_split_merge() {
         let mut leaf = Leaf::default();
         for i in 0..100 {
-            leaf.insert_non_full(i, vec![i as u8; 40], i as u32);
+            leaf.insert_non_full(i, vec![i as u8; 40], LeafVal::Oid(i as u32));
         }
-        assert_eq!(leaf.pos.get_len(), 8 + 2 + 100 * 1 + 100 * 40 + 100 * 4);
-        assert!(leaf.should_split());
+        assert_eq!(leaf.pos.get_len(), 8 + 2 + 4 + 2 * 100 + 100 * 40 + 100 * 4);
+        assert!(leaf.should_split(DATA_ALIGN));
         let mut leaf1 = leaf.clone();
-        let (key, mut leaf11) = leaf1.split();
-        assert_eq!(key, vec![45; 40]);
-        assert_eq!(leaf1.pos.get_len(), 8 + 2 + 45 * 45);
+        let (key, mut leaf11) = leaf1.split(DATA_ALIGN);
+        // separator is truncated
+        assert_eq!(key, vec![44]);
+        assert_eq!(leaf1.pos.get_len(), 8 + 2 + 4 + 44 * 46);
         leaf1.merge(&mut leaf11);
         assert_eq!(leaf, leaf1);
     }
@@ -306,16 +426,143 @@ mod tests {
     fn test_leaf_rebalance() {
         let mut leaf0 = Leaf::default();
         for i in 0..10 {
-            leaf0.insert_non_full(i, vec![i as u8; 40], i as u32);
+            leaf0.insert_non_full(i, vec![i as u8; 40], LeafVal::Oid(i as u32));
         }
         let mut leaf1 = Leaf::default();
         for i in 0..90 {
-            leaf1.insert_non_full(i, vec![(i + 10) as u8; 40], (i + 10) as u32);
+            leaf1.insert_non_full(i, vec![(i + 10) as u8; 40], LeafVal::Oid((i + 10) as u32));
+        }
+        assert!(Leaf::should_rebalance(&leaf0, &leaf1, DATA_ALIGN));
+        let key = leaf0.rebalance(&mut leaf1, DATA_ALIGN);
+        assert_eq!(key, vec![44]);
+        assert_eq!(leaf0.pos.get_len(), 8 + 2 + 4 + 44 * 46);
+    }
+
+    #[test]
+    fn test_leaf_long_key() {
+        let mut leaf = Leaf::default();
+        leaf.insert_non_full(0, vec![1; 40], LeafVal::Oid(1));
+        assert_eq!(leaf.pos.get_len(), 8 + 2 + 4 + 1 + 1 + 40 + 4);
+        // len of long key takes two bytes
+        leaf.insert_non_full(1, vec![2; 4096], LeafVal::Oid(2));
+        assert_eq!(
+            leaf.pos.get_len(),
+            8 + 2 + 4 + 1 + 1 + 40 + 4 + 1 + 2 + 4096 + 4
+        );
+        assert!(leaf.should_split(DATA_ALIGN));
+        let mut buf = vec![0; 8192];
+        assert_eq!(leaf.serialize(&mut buf.as_mut_slice()), Ok(8192));
+        assert_eq!(Leaf::deserialize(&mut buf.as_slice()), Ok(leaf.clone()));
+        let (key, leaf1) = leaf.split(DATA_ALIGN);
+        assert_eq!(key, vec![2]);
+        assert_eq!(leaf.pos.get_len(), 8 + 2 + 4 + 1 + 1 + 40 + 4);
+        assert_eq!(leaf1.pos.get_len(), 8 + 2 + 4 + 1 + 2 + 4096 + 4);
+        // a single long key can't be split
+        assert!(!leaf1.should_split(DATA_ALIGN));
+        assert!(Leaf::should_merge(&Leaf::default(), &leaf1, DATA_ALIGN));
+    }
+
+    #[test]
+    fn test_leaf_prefix_compression() {
+        let mut leaf = Leaf::default();
+        for i in 0..100u8 {
+            let mut key = vec![7; 100];
+            key.push(i);
+            leaf.insert_non_full(i as usize, key, LeafVal::Oid(i as u32));
+        }
+        // only the first key is stored in full
+        assert_eq!(leaf.pos.get_len(), 8 + 2 + 4 + 2 * 100 + 101 + 99 + 100 * 4);
+        assert!(!leaf.should_split(DATA_ALIGN));
+        let mut buf = vec![0; 4096];
+        assert_eq!(leaf.serialize(&mut buf.as_mut_slice()), Ok(4096));
+        assert_eq!(Leaf::deserialize(&mut buf.as_slice()), Ok(leaf.clone()));
+    }
+
+    #[test]
+    fn test_leaf_incremental_len() {
+        let full_len = |leaf: &Leaf| {
+            let mut leaf = leaf.clone();
+            leaf.reset_len();
+            leaf.pos.get_len()
+        };
+        let mut leaf = Leaf::default();
+        let mut seed = 7u32;
+        for i in 0..2000u32 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            // keys share prefix of random len, some of them are long
+            let mut key = vec![1; (seed >> 8) as usize % 8];
+            key.push((seed >> 16) as u8);
+            if seed.is_multiple_of(31) {
+                key.resize(300, 2);
+            }
+            match (seed >> 4) % 4 {
+                0 => {
+                    leaf.remove(&key);
+                }
+                1 if !leaf.entrys.is_empty() => {
+                    let index = (seed >> 12) as usize % leaf.entrys.len();
+                    leaf.update_val(index, LeafVal::Inline(vec![0; i as usize % 10]));
+                }
+                _ => match leaf.search(&key) {
+                    Ok(_) => {}
+                    Err(index) => leaf.insert_non_full(index, key, LeafVal::Oid(i)),
+                },
+            }
+            assert_eq!(leaf.pos.get_len(), full_len(&leaf));
         }
-        assert!(Leaf::should_rebalance(&leaf0, &leaf1));
-        let key = leaf0.rebalance(&mut leaf1);
-        assert_eq!(key, vec![45; 40]);
-        assert_eq!(leaf0.pos.get_len(), 8 + 2 + 45 * 45);
+        // split and merge back
+        let whole_leaf = leaf.clone();
+        assert!(leaf.should_split(DATA_ALIGN));
+        let (_, mut right_leaf) = leaf.split(DATA_ALIGN);
+        assert_eq!(leaf.pos.get_len(), full_len(&leaf));
+        assert_eq!(right_leaf.pos.get_len(), full_len(&right_leaf));
+        assert_eq!(
+            Leaf::should_merge(&leaf, &right_leaf, 65536),
+            whole_leaf.pos.get_len() as usize <= max_nonsplit_size(65536)
+        );
+        leaf.merge(&mut right_leaf);
+        assert_eq!(leaf, whole_leaf);
     }
 
+    #[test]
+    fn test_leaf_inline_val() {
+        let mut leaf = Leaf::default();
+        leaf.insert_non_full(0, vec![1; 40], LeafVal::Inline(vec![1; 100]));
+        leaf.insert_non_full(1, vec![2; 40], LeafVal::Oid(2));
+        leaf.insert_non_full(2, vec![3; 40], LeafVal::Inline(vec![]));
+        assert_eq!(
+            leaf.pos.get_len(),
+            8 + 2 + 4 + 3 * (1 + 1 + 40 + 4) + 2 + 100 + 2
+        );
+        let mut buf = vec![0; 4096];
+        assert_eq!(leaf.serialize(&mut buf.as_mut_slice()), Ok(4096));
+        assert_eq!(Leaf::deserialize(&mut buf.as_slice()), Ok(leaf.clone()));
+        assert_eq!(
+            leaf.search(&vec![1; 40]),
+            Ok(&LeafVal::Inline(vec![1; 100]))
+        );
+        // replace inline val with oid
+        assert_eq!(
+            leaf.update_val(0, LeafVal::Oid(1)),
+            LeafVal::Inline(vec![1; 100])
+        );
+        assert_eq!(leaf.pos.get_len(), 8 + 2 + 4 + 3 * (1 + 1 + 40 + 4) + 2);
+        assert_eq!(
+            leaf.remove(&vec![3; 40]),
+            Some((vec![3; 40], LeafVal::Inline(vec![])))
+        );
+        // inline vals are moved with their keys
+        let mut leaf = Leaf::default();
+        for i in 0..40u8 {
+            leaf.insert_non_full(i as usize, vec![i; 40], LeafVal::Inline(vec![i; 60]));
+        }
+        assert!(leaf.should_split(DATA_ALIGN));
+        let (key, leaf1) = leaf.split(DATA_ALIGN);
+        let i = key[0];
+        assert_eq!(leaf1.entrys[0], (vec![i; 40], LeafVal::Inline(vec![i; 60])));
+        assert_eq!(leaf.entrys.len() + leaf1.entrys.len(), 40);
+        assert!(
+            leaf.pos.get_len() <= DATA_ALIGN as u16 && leaf1.pos.get_len() <= DATA_ALIGN as u16
+        );
+    }
 }
@@ -3,34 +3,101 @@ mod entry;
 mod leaf;
 mod object_ref;
 mod object_state;
+mod overflow;
 
 use crate::error::TdbError;
 use crate::storage::{Deserialize, ObjectPos, Serialize};
 pub use branch::Branch;
+use byteorder::{ReadBytesExt, WriteBytesExt};
 pub use entry::Entry;
-pub use leaf::Leaf;
+pub use leaf::{Leaf, LeafVal};
 pub use object_ref::{ObjectRef, Versions};
 pub use object_state::ObjectState;
-use std::io::{Read, Write};
-use std::u16;
-use std::u32;
-use std::u8;
+pub use overflow::Overflow;
+use std::io::{self, Read, Write};
 
-// 255 byte
-pub const MAX_KEY_SIZE: u16 = u8::MAX as u16;
+// 4K byte
+pub const MAX_KEY_SIZE: u16 = 4096;
+// nodes reserve space for one more key not longer than it, longer keys make node bigger than node size
+const MAX_SHORT_KEY_SIZE: u16 = u8::MAX as u16;
 pub const MAX_OBJ_SIZE: u16 = u16::MAX;
+// vals not longer than it are put in leaf instead of entry
+pub const DEFAULT_INLINE_VAL_SIZE: usize = 64;
+pub const MAX_INLINE_VAL_SIZE: usize = 1024;
 pub const UNUSED_OID: u32 = u32::MAX;
 pub const DATA_ALIGN: usize = 4096;
+// leaf and branch are split at node size
+pub const DEFAULT_NODE_SIZE: usize = DATA_ALIGN;
+pub const MAX_NODE_SIZE: usize = 8 * DATA_ALIGN;
 
 pub type Key = Vec<u8>;
 
+/// Size of branch or leaf on disk, nodes are padded to multiple of DATA_ALIGN
+#[inline]
+pub fn node_disk_size(len: u16) -> usize {
+    (len as usize).next_multiple_of(DATA_ALIGN)
+}
+
 pub type Val = Vec<u8>;
 
+// Key lens in nodes are varint, 7 bits in each byte and high bit is set if more bytes follow
+#[inline]
+const fn varint_size(n: usize) -> usize {
+    if n < 1 << 7 {
+        1
+    } else if n < 1 << 14 {
+        2
+    } else {
+        3
+    }
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut n: usize) -> io::Result<()> {
+    while n >= 1 << 7 {
+        writer.write_u8(n as u8 | 0x80)?;
+        n >>= 7;
+    }
+    writer.write_u8(n as u8)
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let mut n = 0;
+    for i in 0..3 {
+        let byte = reader.read_u8()?;
+        n |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "varint is too long",
+    ))
+}
+
+// Return len of common prefix of two keys
+#[inline]
+fn shared_len(key0: &[u8], key1: &[u8]) -> usize {
+    // skip equal chunks first, long keys often share most of their bytes
+    let len = key0.len().min(key1.len());
+    let mut shared = 0;
+    while shared + 64 <= len && key0[shared..shared + 64] == key1[shared..shared + 64] {
+        shared += 64;
+    }
+    shared
+        + key0[shared..len]
+            .iter()
+            .zip(&key1[shared..len])
+            .take_while(|(a, b)| a == b)
+            .count()
+}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum Object {
     L(Leaf),
     B(Branch),
     E(Entry),
+    O(Overflow),
 }
 
 impl Object {
@@ -44,27 +111,21 @@ impl Object {
         T::get_mut(self)
     }
     #[inline]
-    pub fn get_key(&self) -> &[u8] {
-        match self {
-            Object::L(leaf) => leaf.get_key(),
-            Object::B(branch) => branch.get_key(),
-            Object::E(entry) => entry.get_key(),
-        }
-    }
-    #[inline]
-    pub fn unwrap<T: AsObject>(self) -> T {
-        T::unwrap(self)
-    }
-    #[inline]
     pub fn is<T: AsObject>(&self) -> bool {
         T::is(self)
     }
+    /// Return true for branch and leaf, which are aligned to 4K and cached
+    #[inline]
+    pub fn is_node(&self) -> bool {
+        matches!(self, Object::L(_) | Object::B(_))
+    }
     #[inline]
     pub fn get_pos(&self) -> &ObjectPos {
         match self {
             Object::L(leaf) => leaf.get_pos(),
             Object::B(branch) => branch.get_pos(),
             Object::E(entry) => entry.get_pos(),
+            Object::O(overflow) => overflow.get_pos(),
         }
     }
     #[inline]
@@ -73,6 +134,7 @@ impl Object {
             Object::L(leaf) => leaf.get_pos_mut(),
             Object::B(branch) => branch.get_pos_mut(),
             Object::E(entry) => entry.get_pos_mut(),
+            Object::O(overflow) => overflow.get_pos_mut(),
         }
     }
     #[inline]
@@ -81,6 +143,7 @@ impl Object {
             ObjectTag::Leaf => Ok(Object::L(Leaf::deserialize(buf)?)),
             ObjectTag::Branch => Ok(Object::B(Branch::deserialize(buf)?)),
             ObjectTag::Entry => Ok(Object::E(Entry::deserialize(buf)?)),
+            ObjectTag::Overflow => Ok(Object::O(Overflow::deserialize(buf)?)),
         }
     }
     #[inline]
@@ -89,6 +152,7 @@ impl Object {
             Object::L(leaf) => leaf.serialize(buf),
             Object::B(branch) => branch.serialize(buf),
             Object::E(entry) => entry.serialize(buf),
+            Object::O(overflow) => overflow.serialize(buf),
         }
     }
 }
@@ -101,6 +165,7 @@ pub enum ObjectTag {
     Leaf = 0,
     Branch,
     Entry,
+    Overflow,
 }
 
 impl From<u8> for ObjectTag {
@@ -111,28 +176,27 @@ impl From<u8> for ObjectTag {
             ObjectTag::Branch
         } else if val == 2 {
             ObjectTag::Entry
+        } else if val == 3 {
+            ObjectTag::Overflow
         } else {
             unreachable!()
         }
     }
 }
 
-impl Into<u8> for ObjectTag {
-    fn into(self) -> u8 {
-        self as u8
+impl From<ObjectTag> for u8 {
+    fn from(val: ObjectTag) -> Self {
+        val as u8
     }
 }
 
 pub trait AsObject: Deserialize + Serialize {
-    fn get_tag(&self) -> ObjectTag;
-    fn get_key(&self) -> &[u8];
     fn get_ref(obejct_ref: &Object) -> &Self;
     fn get_mut(object_state: &mut Object) -> &mut Self;
     fn is(obejct_ref: &Object) -> bool;
     fn get_pos(&self) -> &ObjectPos;
     fn get_pos_mut(&mut self) -> &mut ObjectPos;
     fn get_header_size() -> usize;
-    fn unwrap(obj: Object) -> Self;
 }
 
 #[cfg(test)]
@@ -143,8 +207,22 @@ mod tests {
         assert_eq!(ObjectTag::Leaf as u8, 0);
         assert_eq!(ObjectTag::Branch as u8, 1);
         assert_eq!(ObjectTag::Entry as u8, 2);
+        assert_eq!(ObjectTag::Overflow as u8, 3);
         assert_eq!(ObjectTag::Leaf, ObjectTag::from(0));
         assert_eq!(ObjectTag::Branch, ObjectTag::from(1));
         assert_eq!(ObjectTag::Entry, ObjectTag::from(2));
+        assert_eq!(ObjectTag::Overflow, ObjectTag::from(3));
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut buf = vec![];
+        for n in [0, 1, 127, 128, 300, 4096, 16383, 16384] {
+            buf.clear();
+            write_varint(&mut buf, n).unwrap();
+            assert_eq!(buf.len(), varint_size(n));
+            assert_eq!(read_varint(&mut buf.as_slice()).unwrap(), n);
+        }
+        assert!(read_varint(&mut [0x80u8, 0x80, 0x80, 0].as_slice()).is_err());
     }
 }
@@ -54,27 +54,21 @@ impl Versions {
         Self { history }
     }
     pub fn find_obj_ref(&self, ts: TimeStamp) -> Option<&ObjectRef> {
-        for obj_ref in self.history.iter() {
-            if obj_ref.start_ts <= ts && obj_ref.end_ts > ts {
-                return Some(obj_ref);
-            }
-        }
-        None
+        self.history
+            .iter()
+            .find(|obj_ref| obj_ref.start_ts <= ts && obj_ref.end_ts > ts)
     }
 
     pub fn find_obj_mut(&mut self, ts: TimeStamp) -> Option<&mut ObjectRef> {
-        for obj_mut in self.history.iter_mut() {
-            if obj_mut.start_ts <= ts && obj_mut.end_ts > ts {
-                return Some(obj_mut);
-            }
-        }
-        None
+        self.history
+            .iter_mut()
+            .find(|obj_mut| obj_mut.start_ts <= ts && obj_mut.end_ts > ts)
     }
 
     pub fn get_newest_objpos(&self) -> ObjectPos {
         if let Some(_version) = self.history.front() {
             if _version.end_ts == MAX_TS {
-                return _version.obj_pos.clone();
+                return _version.obj_pos;
             }
         }
         ObjectPos::default()
@@ -99,6 +93,22 @@ impl Versions {
         self.history.push_front(obj_ref);
     }
 
+    // Remove newest version if it's added at ts, undo obsolete_newest of the one before it
+    pub fn rollback(&mut self, ts: TimeStamp) {
+        if self
+            .history
+            .front()
+            .is_some_and(|version| version.start_ts == ts)
+        {
+            self.history.pop_front();
+            if let Some(version) = self.history.front_mut() {
+                if version.end_ts == ts {
+                    version.end_ts = MAX_TS;
+                }
+            }
+        }
+    }
+
     // Set newest version's end_ts to ts, make it obsolete
     pub fn obsolete_newest(&mut self, ts: TimeStamp) {
         if let Some(_version) = self.history.front_mut() {
@@ -1,4 +1,5 @@
 use crate::object::Object;
+use crate::storage::ObjectPos;
 use std::sync::Arc;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -7,13 +8,15 @@ pub enum ObjectState {
     Dirty(Object, Arc<Object>),
     New(Object),
     Del(Arc<Object>),
+    // written to data file before commit, with old object if oid is reused
+    Written(ObjectPos, Option<Arc<Object>>),
 }
 
 impl ObjectState {
     #[inline]
     pub fn get_ref(&self) -> Option<&Object> {
         match self {
-            ObjectState::Readonly(obj) => Some(&*obj),
+            ObjectState::Readonly(obj) => Some(obj),
             ObjectState::Dirty(obj, _) => Some(obj),
             ObjectState::New(obj) => Some(obj),
             _ => None,
@@ -28,47 +31,14 @@ impl ObjectState {
         }
     }
     #[inline]
-    pub fn into_arc(self) -> Option<Arc<Object>> {
-        match self {
-            ObjectState::Readonly(obj) => Some(obj.clone()),
-            ObjectState::Dirty(obj, _) => Some(Arc::new(obj)),
-            ObjectState::New(obj) => Some(Arc::new(obj)),
-            _ => None,
-        }
-    }
-    #[inline]
-    pub fn to_dirty(self) -> Self {
+    pub fn into_dirty(self) -> Self {
         match self {
             ObjectState::Readonly(obj) => ObjectState::Dirty((*obj).clone(), obj.clone()),
             _ => panic!("object is not readonly"),
         }
     }
     #[inline]
-    pub fn is_dirty(&self) -> bool {
-        match self {
-            ObjectState::Dirty(_, _) => true,
-            _ => false,
-        }
-    }
-    #[inline]
-    pub fn is_new(&self) -> bool {
-        match self {
-            ObjectState::New(_) => true,
-            _ => false,
-        }
-    }
-    #[inline]
-    pub fn is_del(&self) -> bool {
-        match self {
-            ObjectState::Del(_) => true,
-            _ => false,
-        }
-    }
-    #[inline]
     pub fn is_readonly(&self) -> bool {
-        match self {
-            ObjectState::Readonly(_) => true,
-            _ => false,
-        }
+        matches!(self, ObjectState::Readonly(_))
     }
 }
@@ -0,0 +1,142 @@
+use super::{ObjectId, MAX_OBJ_SIZE, UNUSED_OID};
+use crate::error::TdbError;
+use crate::object::{AsObject, Object, ObjectTag};
+use crate::storage::{Deserialize, ObjectPos, Serialize};
+use crate::utils::{CrcReader, CrcWriter, CHECKSUM_SIZE};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+use std::mem;
+
+/// Part of value which can't be put in entry
+/// Overflow objects of a value are chained by next_oid
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Overflow {
+    pub data: Vec<u8>,
+    pub next_oid: ObjectId,
+    pos: ObjectPos,
+}
+
+impl Overflow {
+    pub fn new(data: Vec<u8>, next_oid: ObjectId) -> Self {
+        assert!(data.len() <= Self::max_data_size());
+        let size = Self::get_header_size() + data.len();
+        Self {
+            data,
+            next_oid,
+            pos: ObjectPos::new(0, size as u16, ObjectTag::Overflow),
+        }
+    }
+    /// Max size of data in one overflow object
+    #[inline]
+    pub fn max_data_size() -> usize {
+        MAX_OBJ_SIZE as usize - Self::get_header_size()
+    }
+}
+
+impl Default for Overflow {
+    fn default() -> Self {
+        Self {
+            data: Vec::with_capacity(0),
+            next_oid: UNUSED_OID,
+            pos: ObjectPos::default(),
+        }
+    }
+}
+
+impl Serialize for Overflow {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<usize, TdbError> {
+        let mut writer = CrcWriter::new(writer);
+        let mut size = 0;
+        // object info
+        writer.write_u64::<LittleEndian>(self.pos.0)?;
+        size += mem::size_of::<u64>();
+        // next oid
+        writer.write_u32::<LittleEndian>(self.next_oid)?;
+        size += mem::size_of::<ObjectId>();
+        // data len
+        writer.write_u16::<LittleEndian>(self.data.len() as u16)?;
+        size += mem::size_of::<u16>();
+        // data
+        writer.write_all(&self.data)?;
+        size += self.data.len();
+        // checksum
+        size += writer.write_checksum()?;
+        Ok(size)
+    }
+}
+
+impl Deserialize for Overflow {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self, TdbError> {
+        let mut reader = CrcReader::new(reader);
+        // object pos
+        let pos = ObjectPos(reader.read_u64::<LittleEndian>()?);
+        // next oid
+        let next_oid = reader.read_u32::<LittleEndian>()?;
+        // data len
+        let data_len: usize = reader.read_u16::<LittleEndian>()?.into();
+        // data
+        let mut data = vec![0; data_len];
+        reader.read_exact(&mut data)?;
+        // checksum
+        reader.check_checksum()?;
+        Ok(Overflow {
+            data,
+            next_oid,
+            pos,
+        })
+    }
+}
+
+impl AsObject for Overflow {
+    #[inline]
+    fn get_ref(obejct_ref: &Object) -> &Self {
+        match obejct_ref {
+            Object::O(overflow) => overflow,
+            _ => panic!("object isn't overflow"),
+        }
+    }
+    #[inline]
+    fn get_mut(object_mut: &mut Object) -> &mut Self {
+        match object_mut {
+            Object::O(overflow) => overflow,
+            _ => panic!("object isn't overflow"),
+        }
+    }
+    #[inline]
+    fn is(obejct_ref: &Object) -> bool {
+        matches!(obejct_ref, Object::O(_))
+    }
+    #[inline]
+    fn get_pos(&self) -> &ObjectPos {
+        &self.pos
+    }
+    #[inline]
+    fn get_pos_mut(&mut self) -> &mut ObjectPos {
+        &mut self.pos
+    }
+    #[inline]
+    fn get_header_size() -> usize {
+        // obj pos + next oid + data len + checksum
+        mem::size_of::<u64>() + mem::size_of::<ObjectId>() + mem::size_of::<u16>() + CHECKSUM_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_overflow_serialize_deserialize() {
+        let overflow0 = Overflow::new(vec![1; Overflow::max_data_size()], 1);
+        assert_eq!(overflow0.pos.get_len(), MAX_OBJ_SIZE);
+        let mut buf = vec![0; MAX_OBJ_SIZE as usize];
+        assert_eq!(
+            overflow0.serialize(&mut buf.as_mut_slice()),
+            Ok(MAX_OBJ_SIZE as usize)
+        );
+        let overflow1 = Overflow::deserialize(&mut buf.as_slice()).unwrap();
+        assert_eq!(overflow0, overflow1);
+        // flip a bit
+        buf[100] ^= 1;
+        assert!(Overflow::deserialize(&mut buf.as_slice()).is_err());
+    }
+}
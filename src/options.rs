@@ -0,0 +1,185 @@
+use crate::cache::DEFAULT_CACHE_SIZE;
+use crate::error::TdbError;
+use crate::kv::KVStore;
+use crate::object::{
+    DATA_ALIGN, DEFAULT_INLINE_VAL_SIZE, DEFAULT_NODE_SIZE, MAX_INLINE_VAL_SIZE, MAX_NODE_SIZE,
+};
+use crate::storage::{
+    Deserialize, Serialize, SyncMode, DEFAULT_DATA_BUF_SIZE, DEFAULT_META_BUF_SIZE,
+    DEFAULT_TABLE_BUF_SIZE, META_LOG_FILE_MAX_SIZE,
+};
+use crate::utils::{CrcReader, CrcWriter, CHECKSUM_SIZE};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+use std::mem;
+use std::path::Path;
+
+const MAGIC_NUM: u32 = 0xFBFB_FBFB;
+
+/// Options of KVStore, created by KVStore::builder
+/// Node size affects on-disk format, it is persisted when store is created and checked on reopen
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub(crate) cache_size: usize,
+    pub(crate) meta_log_max_size: usize,
+    pub(crate) data_buf_size: usize,
+    pub(crate) meta_buf_size: usize,
+    pub(crate) table_buf_size: usize,
+    // None to use the persisted one, or DEFAULT_NODE_SIZE for new store
+    pub(crate) node_size: Option<usize>,
+    pub(crate) sync_mode: SyncMode,
+    // None to disable auto compaction
+    pub(crate) compact_ratio: Option<f64>,
+    pub(crate) inline_val_size: usize,
+    // set by open_read_only, no file is created or written
+    pub(crate) read_only: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            cache_size: DEFAULT_CACHE_SIZE,
+            meta_log_max_size: META_LOG_FILE_MAX_SIZE,
+            data_buf_size: DEFAULT_DATA_BUF_SIZE,
+            meta_buf_size: DEFAULT_META_BUF_SIZE,
+            table_buf_size: DEFAULT_TABLE_BUF_SIZE,
+            node_size: None,
+            sync_mode: SyncMode::default(),
+            compact_ratio: None,
+            inline_val_size: DEFAULT_INLINE_VAL_SIZE,
+            read_only: false,
+        }
+    }
+}
+
+impl Options {
+    /// Number of nodes cached for readers
+    pub fn cache_size(mut self, size: usize) -> Self {
+        assert!(size > 0);
+        self.cache_size = size;
+        self
+    }
+    /// Meta log is rewritten to a single checkpoint when it is bigger than size
+    pub fn meta_log_max_size(mut self, size: usize) -> Self {
+        assert!(size >= DATA_ALIGN);
+        self.meta_log_max_size = size;
+        self
+    }
+    /// Buffer size of data segment reader and writer
+    pub fn data_buffer_size(mut self, size: usize) -> Self {
+        assert!(size > 0);
+        self.data_buf_size = size;
+        self
+    }
+    /// Buffer size of meta log reader and writer
+    pub fn meta_buffer_size(mut self, size: usize) -> Self {
+        assert!(size > 0);
+        self.meta_buf_size = size;
+        self
+    }
+    /// Buffer size of meta table writer
+    pub fn table_buffer_size(mut self, size: usize) -> Self {
+        assert!(size > 0);
+        self.table_buf_size = size;
+        self
+    }
+    /// Size that leaf and branch are split at, power of two from 4K to MAX_NODE_SIZE
+    /// # Notes
+    /// It can't be changed after store is created, open returns OptionsMismatch if it differs
+    pub fn node_size(mut self, size: usize) -> Self {
+        assert!(size.is_power_of_two() && (DATA_ALIGN..=MAX_NODE_SIZE).contains(&size));
+        self.node_size = Some(size);
+        self
+    }
+    /// Same as KVStore::set_sync_mode
+    pub fn sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+    /// Enable auto compaction, same as KVStore::set_compact_ratio with Some(ratio)
+    pub fn compact_ratio(mut self, ratio: f64) -> Self {
+        assert!(ratio > 0.0 && ratio <= 1.0);
+        self.compact_ratio = Some(ratio);
+        self
+    }
+    /// Same as KVStore::set_inline_val_size
+    pub fn inline_val_size(mut self, size: usize) -> Self {
+        assert!(size <= MAX_INLINE_VAL_SIZE);
+        self.inline_val_size = size;
+        self
+    }
+    pub fn open<P: AsRef<Path>>(self, dir_path: P) -> Result<KVStore, TdbError> {
+        KVStore::open_with_options(dir_path, self)
+    }
+    /// Same as KVStore::open_read_only, options of writer are ignored
+    pub fn open_read_only<P: AsRef<Path>>(mut self, dir_path: P) -> Result<KVStore, TdbError> {
+        self.read_only = true;
+        KVStore::open_with_options(dir_path, self)
+    }
+    #[inline]
+    pub(crate) fn get_node_size(&self) -> usize {
+        self.node_size.unwrap_or(DEFAULT_NODE_SIZE)
+    }
+}
+
+/// Options persisted in options file, which affect on-disk format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PersistedOptions {
+    pub node_size: u32,
+}
+
+impl PersistedOptions {
+    #[inline]
+    pub fn len() -> usize {
+        // magic + node_size + checksum
+        mem::size_of::<u32>() + mem::size_of::<u32>() + CHECKSUM_SIZE
+    }
+}
+
+impl Serialize for PersistedOptions {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<usize, TdbError> {
+        let mut writer = CrcWriter::new(writer);
+        writer.write_u32::<LittleEndian>(MAGIC_NUM)?;
+        writer.write_u32::<LittleEndian>(self.node_size)?;
+        writer.write_checksum()?;
+        Ok(Self::len())
+    }
+}
+
+impl Deserialize for PersistedOptions {
+    fn deserialize<R: Read>(reader: &mut R) -> Result<Self, TdbError> {
+        let mut reader = CrcReader::new(reader);
+        if reader.read_u32::<LittleEndian>()? != MAGIC_NUM {
+            return Err(TdbError::DeserializeError);
+        }
+        let node_size = reader.read_u32::<LittleEndian>()?;
+        reader.check_checksum()?;
+        Ok(Self { node_size })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_persisted_options_serialize_deserialize() {
+        let options = PersistedOptions { node_size: 8192 };
+        let mut buf = vec![0; PersistedOptions::len()];
+        assert_eq!(
+            options.serialize(&mut buf.as_mut_slice()),
+            Ok(PersistedOptions::len())
+        );
+        assert_eq!(
+            PersistedOptions::deserialize(&mut buf.as_slice()),
+            Ok(options)
+        );
+        buf[5] ^= 1;
+        assert!(PersistedOptions::deserialize(&mut buf.as_slice()).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_options_invalid_node_size() {
+        let _ = Options::default().node_size(6000);
+    }
+}
@@ -1,125 +1,273 @@
-use super::ObjectPos;
+use super::{Dev, ObjectPos, SEGMENT_SIZE};
 use crate::{
     error::TdbError,
-    object::{Entry, Object, ObjectId, ObjectState, DATA_ALIGN},
+    object::{node_disk_size, Object, ObjectId, ObjectState, DATA_ALIGN, UNUSED_OID},
 };
 use byteorder::WriteBytesExt;
 use log::debug;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::io::{BufReader, BufWriter, Seek, Write};
+use std::mem;
 
-const DEFAULT_BUF_SIZE: usize = 4096 * 2;
+pub const DEFAULT_DATA_BUF_SIZE: usize = 4096 * 2;
+// don't compact data file less than 4M
+const MIN_COMPACT_SIZE: u64 = 1 << 22;
 
+// Segment files are opened when first read
 pub struct DataFileReader {
-    reader: BufReader<File>,
+    dev: Dev,
+    readers: HashMap<u32, BufReader<File>>,
 }
 
 impl DataFileReader {
-    pub fn new(file: File) -> Self {
+    pub fn new(dev: Dev) -> Self {
         DataFileReader {
-            reader: BufReader::with_capacity(DEFAULT_BUF_SIZE, file),
+            dev,
+            readers: HashMap::default(),
         }
     }
 
     pub fn read_obj(&mut self, obj_pos: &ObjectPos) -> Result<Object, TdbError> {
-        self.reader.seek(obj_pos.clone().into())?;
+        let segment_id = obj_pos.get_segment_id();
+        if !self.readers.contains_key(&segment_id) {
+            let file = self.dev.open_data_segment(segment_id, false)?;
+            self.readers.insert(
+                segment_id,
+                BufReader::with_capacity(self.dev.options.data_buf_size, file),
+            );
+        }
+        let reader = self.readers.get_mut(&segment_id).unwrap();
+        reader.seek((*obj_pos).into())?;
         let obj_tag = obj_pos.get_tag();
-        Object::read(&mut self.reader, &obj_tag)
+        match Object::read(reader, &obj_tag) {
+            // object must be written at its pos
+            Ok(obj) if obj.get_pos() == obj_pos => Ok(obj),
+            Ok(_) | Err(TdbError::Corruption { .. }) => Err(TdbError::Corruption {
+                oid: UNUSED_OID,
+                pos: *obj_pos,
+            }),
+            Err(err) => Err(err),
+        }
     }
 }
 
 pub struct DataFilwWriter {
+    dev: Dev,
     writer: BufWriter<File>,
+    // segment of writer
+    segment_id: u32,
+    // segments before it are removed by compaction
+    first_segment_id: u32,
+    // new segment file is created since last sync
+    new_segment: bool,
     size: u64,
     removed_size: u64,
+    // bytes written after last commit, they are removed if not referenced by next commit
+    uncommitted_size: u64,
 }
 
 impl DataFilwWriter {
-    pub fn new(mut file: File, size: u64, removed_size: u64) -> Self {
-        file.seek(SeekFrom::Start(size)).unwrap();
+    pub fn new(dev: Dev, file: File, first_segment_id: u32, size: u64, removed_size: u64) -> Self {
         DataFilwWriter {
-            writer: BufWriter::with_capacity(DEFAULT_BUF_SIZE, file),
+            writer: BufWriter::with_capacity(dev.options.data_buf_size, file),
+            dev,
+            segment_id: (size / SEGMENT_SIZE) as u32,
+            first_segment_id,
+            new_segment: false,
             size,
             removed_size,
+            uncommitted_size: 0,
         }
     }
     pub fn flush(&mut self) -> Result<(), TdbError> {
         self.writer.flush()?;
         Ok(())
     }
+    /// Flush and sync current segment, sync dir if new segment is created
+    pub fn sync(&mut self) -> Result<(), TdbError> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        if self.new_segment {
+            self.dev.sync_dir()?;
+            self.new_segment = false;
+        }
+        Ok(())
+    }
     pub fn write_objs(
         &mut self,
         dirty_cache: &mut HashMap<ObjectId, ObjectState>,
     ) -> Result<(u64, u64), TdbError> {
+        // overflows streamed before commit and objects written by failed compaction aren't aligned
+        self.align()?;
+        // objects written before commit, such as overflow of replaced val, may be not referenced
+        let written_size: u64 = dirty_cache
+            .values()
+            .map(|mut_obj| match mut_obj {
+                ObjectState::Written(pos, _) => pos.get_len() as u64,
+                _ => 0,
+            })
+            .sum();
+        let unreferenced_size = self.uncommitted_size - written_size;
         // write branch and entry, align to 4k
         for (_, mut_obj) in dirty_cache.iter_mut() {
             match mut_obj {
-                ObjectState::Dirty(obj, _) | ObjectState::New(obj) => {
-                    if !obj.is::<Entry>() {
-                        obj.get_pos_mut().set_pos(self.size as u64);
-                        self.size += obj.write(&mut self.writer)? as u64;
-                        assert!(self.size % DATA_ALIGN as u64 == 0);
-                    }
+                ObjectState::Dirty(obj, _) | ObjectState::New(obj) if obj.is_node() => {
+                    self.write_obj(obj)?;
+                    assert!(self.size.is_multiple_of(DATA_ALIGN as u64));
                 }
                 _ => {}
             }
         }
-        // write entry, not align
+        // write entry and overflow, not align
         for (_, mut_obj) in dirty_cache.iter_mut() {
             match mut_obj {
-                ObjectState::Dirty(obj, _) | ObjectState::New(obj) => {
-                    if obj.is::<Entry>() {
-                        obj.get_pos_mut().set_pos(self.size as u64);
-                        debug!(
-                            "write obj{:?} at {:?} {:?}",
-                            obj,
-                            obj.get_pos(),
-                            self.writer.seek(SeekFrom::Current(0))?
-                        );
-                        self.size += obj.write(&mut self.writer)? as u64;
-                    }
+                ObjectState::Dirty(obj, _) | ObjectState::New(obj) if !obj.is_node() => {
+                    debug!(
+                        "write obj{:?} at {:?} {:?}",
+                        obj,
+                        obj.get_pos(),
+                        self.writer.stream_position()?
+                    );
+                    self.write_obj(obj)?;
                 }
                 _ => {}
             }
         }
         // make commit align to 4K
-        if self.size % DATA_ALIGN as u64 != 0 {
-            for _ in self.size % DATA_ALIGN as u64..DATA_ALIGN as u64 {
-                self.writer.write_u8(0)?;
-                self.size += 1;
-            }
-        }
+        self.align()?;
         // static removed obj size
         for (_, mut_obj) in dirty_cache.iter_mut() {
             match mut_obj {
-                ObjectState::Dirty(_, arc_obj) | ObjectState::Del(arc_obj) => {
-                    self.removed_size += arc_obj.get_pos().get_len() as u64;
+                ObjectState::Dirty(_, arc_obj)
+                | ObjectState::Del(arc_obj)
+                | ObjectState::Written(_, Some(arc_obj)) => {
+                    self.removed_size += Self::get_disk_size(arc_obj);
                 }
                 _ => {}
             }
         }
+        self.removed_size += unreferenced_size;
+        self.uncommitted_size = 0;
         debug!("current write file size is {:?}", self.size);
         Ok((self.size, self.removed_size))
     }
+    /// Append object at the end of data file and set its pos
+    /// # Notes
+    /// branch and leaf should be written before entry to keep them align to 4K
+    pub fn write_obj(&mut self, obj: &mut Object) -> Result<ObjectPos, TdbError> {
+        let offset = self.size % SEGMENT_SIZE;
+        if offset + Self::get_disk_size(obj) > SEGMENT_SIZE {
+            // object can't cross segments, skip the rest of current segment
+            self.removed_size += SEGMENT_SIZE - offset;
+            self.size += SEGMENT_SIZE - offset;
+        }
+        self.switch_segment()?;
+        obj.get_pos_mut().set_pos(self.size);
+        let len = obj.write(&mut self.writer)? as u64;
+        self.size += len;
+        self.uncommitted_size += len;
+        Ok(*obj.get_pos())
+    }
+    /// Pad data file to 4K
+    /// Return data file size
+    pub fn align(&mut self) -> Result<u64, TdbError> {
+        while !self.size.is_multiple_of(DATA_ALIGN as u64) {
+            self.writer.write_u8(0)?;
+            self.size += 1;
+            self.uncommitted_size += 1;
+        }
+        Ok(self.size)
+    }
+    /// Move to the start of a new segment, so that objects written later will not share segment with before
+    /// Return new segment id
+    pub fn seal_segment(&mut self) -> Result<u32, TdbError> {
+        if !self.size.is_multiple_of(SEGMENT_SIZE) {
+            self.removed_size += SEGMENT_SIZE - self.size % SEGMENT_SIZE;
+            self.size += SEGMENT_SIZE - self.size % SEGMENT_SIZE;
+        }
+        self.switch_segment()?;
+        Ok(self.segment_id)
+    }
+    /// Forget segments before first_segment_id after compaction
+    #[inline]
+    pub fn set_first_segment_id(&mut self, first_segment_id: u32) {
+        self.first_segment_id = first_segment_id;
+        self.removed_size = 0;
+        // copied objects are committed by compaction
+        self.uncommitted_size = 0;
+    }
+    /// Count bytes written after last commit as removed, called when changes are discarded
+    #[inline]
+    pub fn rollback(&mut self) {
+        self.removed_size += mem::take(&mut self.uncommitted_size);
+    }
+    /// Return (first segment id, size, removed size), which is restored if compaction fails
+    #[inline]
+    pub fn get_state(&self) -> (u32, u64, u64) {
+        (self.first_segment_id, self.size, self.removed_size)
+    }
+    /// Keep segments before failed compaction, bytes written since state are removed
+    #[inline]
+    pub fn rollback_compact(&mut self, (first_segment_id, size, removed_size): (u32, u64, u64)) {
+        self.first_segment_id = first_segment_id;
+        self.removed_size = removed_size + (self.size - size);
+        self.uncommitted_size = 0;
+    }
+    #[inline]
+    pub fn get_first_segment_id(&self) -> u32 {
+        self.first_segment_id
+    }
+    /// Return true if removed objects take more than ratio of data file
+    #[inline]
+    pub fn should_compact(&self, ratio: f64) -> bool {
+        let size = self.size - self.first_segment_id as u64 * SEGMENT_SIZE;
+        size >= MIN_COMPACT_SIZE && self.removed_size as f64 >= size as f64 * ratio
+    }
+    // Open next segment if current one is full
+    // Full segment is synced, so that sync only need to care about current segment
+    fn switch_segment(&mut self) -> Result<(), TdbError> {
+        let segment_id = (self.size / SEGMENT_SIZE) as u32;
+        if segment_id != self.segment_id {
+            self.writer.flush()?;
+            self.writer.get_ref().sync_data()?;
+            self.new_segment = true;
+            let file = self.dev.open_data_segment(segment_id, true)?;
+            self.writer = BufWriter::with_capacity(self.dev.options.data_buf_size, file);
+            self.segment_id = segment_id;
+            debug!("switch to data segment {:?}", segment_id);
+        }
+        Ok(())
+    }
+    // Branch and leaf are padded to 4K on disk
+    #[inline]
+    fn get_disk_size(obj: &Object) -> u64 {
+        if obj.is_node() {
+            node_disk_size(obj.get_pos().get_len()) as u64
+        } else {
+            obj.get_pos().get_len() as u64
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::object::{Entry, Object, ObjectId, ObjectState};
-    use crate::storage::Dev;
+    use crate::error::TdbError;
+    use crate::object::{Entry, Object, ObjectId, ObjectState, DATA_ALIGN, UNUSED_OID};
+    use crate::storage::{Dev, SEGMENT_SIZE};
     use std::collections::HashMap;
-    use std::env;
+    use std::fs;
+    use tempfile::tempdir;
     fn init() {
         let _ = env_logger::builder().is_test(true).try_init();
     }
     #[test]
     fn test_data_file() {
         init();
-        let dev = Dev::open(env::current_dir().unwrap()).unwrap();
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
+        let mut data_writer = dev.get_data_writer(0, 0, 0).unwrap();
         let mut data_reader = dev.get_data_reader().unwrap();
-        let mut data_writer = dev.get_data_writer(0, 0).unwrap();
         let obj0 = ObjectState::New(Object::E(Entry::new(vec![1, 1, 1], vec![1, 1, 1])));
         let obj1 = ObjectState::New(Object::E(Entry::new(vec![2, 2, 2], vec![2, 2, 2])));
         let obj2 = ObjectState::New(Object::E(Entry::new(vec![3, 3, 3], vec![3, 3, 3])));
@@ -127,24 +275,90 @@ mod tests {
         objs.insert(0, obj0);
         objs.insert(1, obj1);
         objs.insert(2, obj2);
-        data_writer.write_objs(&mut objs);
-        data_writer.flush();
+        assert!(data_writer.write_objs(&mut objs).is_ok());
+        assert!(data_writer.flush().is_ok());
         for (_, objstate) in objs.iter() {
             let obj_ref = objstate.get_ref().unwrap();
             let pos = obj_ref.get_pos();
-            println!("{:?}", obj_ref);
             assert_eq!(data_reader.read_obj(pos), Ok(obj_ref.clone()));
         }
-        println!("{:?}", data_writer.size);
+        let end = objs
+            .values()
+            .map(|objstate| {
+                let pos = objstate.get_ref().unwrap().get_pos();
+                pos.get_pos() + pos.get_len() as u64
+            })
+            .max()
+            .unwrap();
+        // data size is aligned after objects are written
+        assert!(end <= data_writer.size && data_writer.size < end + DATA_ALIGN as u64);
+        assert!(data_writer.size.is_multiple_of(DATA_ALIGN as u64));
 
-        let dev = Dev::open(env::current_dir().unwrap()).unwrap();
+        drop(dev);
+        let dev = Dev::open(dir.path()).unwrap();
         let mut data_reader = dev.get_data_reader().unwrap();
         for (_, objstate) in objs.iter() {
             let obj_ref = objstate.get_ref().unwrap();
             let pos = obj_ref.get_pos();
-            println!("{:?}", obj_ref);
             assert_eq!(data_reader.read_obj(pos), Ok(obj_ref.clone()));
         }
     }
 
+    #[test]
+    fn test_data_segment() {
+        init();
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
+        let mut data_writer = dev.get_data_writer(0, 0, 0).unwrap();
+        let mut objs = vec![];
+        // each entry takes nearly 64K, so some of them will be written to the next segment
+        for i in 0..300u32 {
+            let mut obj = Object::E(Entry::new(i.to_le_bytes().to_vec(), vec![i as u8; 60000]));
+            let pos = data_writer.write_obj(&mut obj).unwrap();
+            assert!(pos.get_segment_offset() + pos.get_len() as u64 <= SEGMENT_SIZE);
+            objs.push(obj);
+        }
+        data_writer.align().unwrap();
+        data_writer.flush().unwrap();
+        assert_eq!(dev.list_data_segments(), Ok(vec![0, 1]));
+        assert!(data_writer.removed_size > 0);
+        let mut data_reader = dev.get_data_reader().unwrap();
+        for obj in objs.iter() {
+            assert_eq!(data_reader.read_obj(obj.get_pos()).as_ref(), Ok(obj));
+        }
+        // reopen writer and seal segment
+        let mut data_writer = dev
+            .get_data_writer(0, data_writer.size, data_writer.removed_size)
+            .unwrap();
+        assert_eq!(data_writer.seal_segment(), Ok(2));
+        let mut obj = Object::E(Entry::new(vec![1], vec![1]));
+        assert_eq!(data_writer.write_obj(&mut obj).unwrap().get_segment_id(), 2);
+        data_writer.flush().unwrap();
+        assert_eq!(dev.list_data_segments(), Ok(vec![0, 1, 2]));
+        assert_eq!(data_reader.read_obj(obj.get_pos()), Ok(obj));
+    }
+
+    #[test]
+    fn test_data_corruption() {
+        init();
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
+        let mut data_writer = dev.get_data_writer(0, 0, 0).unwrap();
+        let mut obj = Object::E(Entry::new(vec![1, 1, 1], vec![1, 1, 1]));
+        let pos = data_writer.write_obj(&mut obj).unwrap();
+        data_writer.flush().unwrap();
+        // flip a bit of val
+        let path = dev.data_segment_path(0);
+        let mut buf = fs::read(&path).unwrap();
+        buf[pos.get_segment_offset() as usize + 15] ^= 1;
+        fs::write(&path, buf).unwrap();
+        let mut data_reader = dev.get_data_reader().unwrap();
+        assert_eq!(
+            data_reader.read_obj(&pos),
+            Err(TdbError::Corruption {
+                oid: UNUSED_OID,
+                pos
+            })
+        );
+    }
 }
@@ -1,80 +1,273 @@
 use crate::error::TdbError;
+use crate::options::{Options, PersistedOptions};
 use crate::storage::{
-    DataFileReader, DataFilwWriter, MetaFileWriter, MetaLogFileReader, TableFileReader,
-    TableFileWriter,
+    DataFileReader, DataFilwWriter, Deserialize, MetaFileWriter, MetaLogFileReader, Serialize,
+    TableFileReader, TableFileWriter, SEGMENT_SIZE,
 };
-use std::fs;
-use std::io::{Seek, SeekFrom};
+use log::{info, warn};
+use std::fs::{self, File, TryLockError};
+use std::io::{BufWriter, ErrorKind, Seek, SeekFrom};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 
-#[derive(Clone)]
+const DATA_SEGMENT_PREFIX: &str = "data_segment_";
+const DATA_SEGMENT_SUFFIX: &str = ".db";
+const LEGACY_DATA_LOG_FILE: &str = "data_log_file.db";
+
 pub struct Dev {
     pub dir_path: PathBuf,
     pub meta_table_path: PathBuf,
     pub meta_log_file_path: PathBuf,
-    pub data_log_file_path: PathBuf,
+    pub options_path: PathBuf,
+    pub options: Options,
+    // flocks on LOCK file or dir, only held by dev returned by open, not by clones
+    _lock_files: Vec<File>,
+}
+
+impl Clone for Dev {
+    fn clone(&self) -> Self {
+        Self {
+            dir_path: self.dir_path.clone(),
+            meta_table_path: self.meta_table_path.clone(),
+            meta_log_file_path: self.meta_log_file_path.clone(),
+            options_path: self.options_path.clone(),
+            options: self.options.clone(),
+            _lock_files: vec![],
+        }
+    }
 }
 
 impl Dev {
+    #[cfg(test)]
     pub fn open<P: AsRef<Path>>(dir_path: P) -> Result<Self, TdbError> {
+        Self::with_options(dir_path, Options::default())
+    }
+    pub fn with_options<P: AsRef<Path>>(dir_path: P, options: Options) -> Result<Self, TdbError> {
         let dir_path = PathBuf::from(dir_path.as_ref());
-        let mut options = fs::OpenOptions::new();
-        let options_mut = options.create(true).read(true).write(true);
+        // data log of old format is a single file, it isn't migrated to segments
+        // checked before LOCK file is created
+        if dir_path.join(LEGACY_DATA_LOG_FILE).exists() {
+            warn!(
+                "database at {:?} has {:?} of unsupported format",
+                dir_path, LEGACY_DATA_LOG_FILE
+            );
+            return Err(TdbError::UnsupportedFormat);
+        }
+        let lock_files = Self::lock(&dir_path, options.read_only)?;
+        let mut open_options = fs::OpenOptions::new();
+        // store must exist if read only
+        let options_mut = if options.read_only {
+            open_options.read(true)
+        } else {
+            open_options.create(true).read(true).write(true)
+        };
         let mut meta_table_path = PathBuf::from(&dir_path);
         meta_table_path.push("meta_table.db");
         options_mut.open(&meta_table_path)?;
         let mut meta_log_file_path = PathBuf::from(&dir_path);
         meta_log_file_path.push("meta_log_file.db");
         options_mut.open(&meta_log_file_path)?;
-        let mut data_log_file_path = PathBuf::from(&dir_path);
-        data_log_file_path.push("data_log_file.db");
-        options_mut.open(&data_log_file_path)?;
-        Ok(Dev {
+        let mut options_path = PathBuf::from(&dir_path);
+        options_path.push("options.db");
+        let dev = Dev {
             dir_path,
             meta_table_path,
             meta_log_file_path,
-            data_log_file_path,
-        })
+            options_path,
+            options,
+            _lock_files: lock_files,
+        };
+        if !dev.options.read_only {
+            dev.sync_dir()?;
+        }
+        Ok(dev)
+    }
+    /// Acquire exclusive lock on both LOCK file and dir, or shared lock on one of them if read only
+    /// Read only store without LOCK file locks dir, LOCK can't be created on read only mount
+    /// # Errors
+    /// Return Locked if lock is held by another store
+    fn lock(dir_path: &Path, read_only: bool) -> Result<Vec<File>, TdbError> {
+        let mut lock_path = PathBuf::from(dir_path);
+        lock_path.push("LOCK");
+        if read_only {
+            let file = match File::open(&lock_path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == ErrorKind::NotFound => File::open(dir_path)?,
+                Err(err) => return Err(err.into()),
+            };
+            Self::try_lock(dir_path, &file, true)?;
+            return Ok(vec![file]);
+        }
+        // dir is locked first, LOCK isn't created while a reader without it holds the store
+        let dir = File::open(dir_path)?;
+        Self::try_lock(dir_path, &dir, false)?;
+        let lock_file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+        Self::try_lock(dir_path, &lock_file, false)?;
+        Ok(vec![dir, lock_file])
+    }
+    fn try_lock(dir_path: &Path, file: &File, shared: bool) -> Result<(), TdbError> {
+        let res = if shared {
+            file.try_lock_shared()
+        } else {
+            file.try_lock()
+        };
+        match res {
+            Ok(()) => Ok(()),
+            Err(TryLockError::WouldBlock) => {
+                warn!("database at {:?} is locked", dir_path);
+                Err(TdbError::Locked)
+            }
+            Err(TryLockError::Error(err)) => Err(err.into()),
+        }
     }
+    /// Sync dir to make created, removed or renamed files durable
+    pub fn sync_dir(&self) -> Result<(), TdbError> {
+        File::open(&self.dir_path)?.sync_all()?;
+        Ok(())
+    }
+    /// Return path of data segment, data log is split into segments of SEGMENT_SIZE
+    pub fn data_segment_path(&self, segment_id: u32) -> PathBuf {
+        let mut data_segment_path = PathBuf::from(&self.dir_path);
+        data_segment_path.push(format!(
+            "{}{:08}{}",
+            DATA_SEGMENT_PREFIX, segment_id, DATA_SEGMENT_SUFFIX
+        ));
+        data_segment_path
+    }
+    /// Return ids of all data segments in dir, sorted
+    pub fn list_data_segments(&self) -> Result<Vec<u32>, TdbError> {
+        let mut segment_ids = vec![];
+        for dir_entry in fs::read_dir(&self.dir_path)? {
+            let file_name = dir_entry?.file_name();
+            let file_name = match file_name.to_str() {
+                Some(file_name) => file_name,
+                None => continue,
+            };
+            if !file_name.starts_with(DATA_SEGMENT_PREFIX)
+                || !file_name.ends_with(DATA_SEGMENT_SUFFIX)
+            {
+                continue;
+            }
+            let id =
+                &file_name[DATA_SEGMENT_PREFIX.len()..file_name.len() - DATA_SEGMENT_SUFFIX.len()];
+            if let Ok(id) = id.parse::<u32>() {
+                segment_ids.push(id);
+            }
+        }
+        segment_ids.sort_unstable();
+        Ok(segment_ids)
+    }
+    /// Remove data segments out of live range, which are left by compaction or crash
+    pub fn remove_stale_data_segments(&self, live_segments: Range<u32>) -> Result<(), TdbError> {
+        for segment_id in self.list_data_segments()? {
+            if !live_segments.contains(&segment_id) {
+                info!("remove stale data segment {:?}", segment_id);
+                fs::remove_file(self.data_segment_path(segment_id))?;
+            }
+        }
+        Ok(())
+    }
+    #[cfg(test)]
     pub fn remove_all(&self) -> Result<(), TdbError> {
         fs::remove_file(&self.meta_log_file_path)?;
         fs::remove_file(&self.meta_table_path)?;
-        fs::remove_file(&self.data_log_file_path)?;
+        if self.options_path.exists() {
+            fs::remove_file(&self.options_path)?;
+        }
+        self.remove_stale_data_segments(0..0)?;
         Ok(())
     }
+    /// Read options persisted when store is created
+    /// Return None if options file doesn't exist, it is created before options are persisted
+    pub fn read_persisted_options(&self) -> Result<Option<PersistedOptions>, TdbError> {
+        match File::open(&self.options_path) {
+            Ok(mut file) => Ok(Some(PersistedOptions::deserialize(&mut file)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+    /// Write options to template file and rename it to options file
+    pub fn write_persisted_options(&self, options: &PersistedOptions) -> Result<(), TdbError> {
+        let mut temp_path = PathBuf::from(&self.dir_path);
+        temp_path.push("options_temp.db");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        options.serialize(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&temp_path, &self.options_path)?;
+        self.sync_dir()
+    }
+    /// Open data segment for read, or for write and create it if not exist
+    pub fn open_data_segment(&self, segment_id: u32, write: bool) -> Result<File, TdbError> {
+        let mut options = fs::OpenOptions::new();
+        let options_mut = if write {
+            options.create(true).write(true)
+        } else {
+            options.read(true)
+        };
+        Ok(options_mut.open(self.data_segment_path(segment_id))?)
+    }
 }
 
 impl Dev {
     pub fn get_data_reader(&self) -> Result<DataFileReader, TdbError> {
-        let mut options = fs::OpenOptions::new();
-        let options_mut = options.read(true);
-        let file = options_mut.open(&self.data_log_file_path)?;
-        Ok(DataFileReader::new(file))
+        Ok(DataFileReader::new(self.clone()))
     }
+    /// Open last data segment for append
     pub fn get_data_writer(
         &self,
+        first_segment_id: u32,
         size: u64,
         removed_size: u64,
     ) -> Result<DataFilwWriter, TdbError> {
-        let mut options = fs::OpenOptions::new();
-        let options_mut = options.write(true);
-        let mut file = options_mut.open(&self.data_log_file_path)?;
-        file.seek(SeekFrom::Start(size as u64))?;
-        Ok(DataFilwWriter::new(file, size, removed_size))
+        let mut file = self.open_data_segment((size / SEGMENT_SIZE) as u32, true)?;
+        file.seek(SeekFrom::Start(size % SEGMENT_SIZE))?;
+        Ok(DataFilwWriter::new(
+            self.clone(),
+            file,
+            first_segment_id,
+            size,
+            removed_size,
+        ))
     }
     pub fn get_meta_reader(&self) -> Result<MetaLogFileReader, TdbError> {
         let mut options = fs::OpenOptions::new();
         let options_mut = options.read(true);
         let mut file = options_mut.open(&self.meta_log_file_path)?;
         file.seek(SeekFrom::Start(0))?;
-        Ok(MetaLogFileReader::new(file))
+        Ok(MetaLogFileReader::new(file, self.options.meta_buf_size))
+    }
+    /// Truncate meta log to size, discard torn or invalid tail
+    /// Return truncated bytes
+    pub fn truncate_meta_log(&self, size: u64) -> Result<u64, TdbError> {
+        let mut options = fs::OpenOptions::new();
+        let options_mut = options.write(true);
+        let file = options_mut.open(&self.meta_log_file_path)?;
+        let file_size = file.metadata()?.len();
+        if file_size <= size {
+            return Ok(0);
+        }
+        file.set_len(size)?;
+        file.sync_all()?;
+        Ok(file_size - size)
     }
     pub fn get_meta_writer(&self, size: usize) -> Result<MetaFileWriter, TdbError> {
         let mut options = fs::OpenOptions::new();
         let options_mut = options.write(true);
         let mut file = options_mut.open(&self.meta_log_file_path)?;
         file.seek(SeekFrom::Start(size as u64))?;
-        Ok(MetaFileWriter::new(file, size))
+        Ok(MetaFileWriter::new(
+            file,
+            size,
+            self.options.meta_buf_size,
+            self.options.meta_log_max_size,
+        ))
     }
     pub fn get_table_reader(&self) -> Result<TableFileReader, TdbError> {
         let mut options = fs::OpenOptions::new();
@@ -87,16 +280,97 @@ impl Dev {
         let mut options = fs::OpenOptions::new();
         let options_mut = options.write(true);
         let file = options_mut.open(&self.meta_table_path)?;
-        Ok(TableFileWriter::new(file, used_page_num))
+        Ok(TableFileWriter::new(
+            file,
+            used_page_num,
+            self.options.table_buf_size,
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::env;
+    use tempfile::tempdir;
     #[test]
     fn test_dev() {
-        assert!(Dev::open(env::current_dir().unwrap()).is_ok());
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
+        assert!(dev.get_data_writer(0, 0, 0).is_ok());
+        assert!(dev.get_data_writer(0, SEGMENT_SIZE, 0).is_ok());
+        assert!(dev.get_data_writer(0, SEGMENT_SIZE * 2 + 1, 0).is_ok());
+        assert_eq!(dev.list_data_segments(), Ok(vec![0, 1, 2]));
+        assert_eq!(dev.read_persisted_options(), Ok(None));
+        let options = PersistedOptions { node_size: 8192 };
+        assert!(dev.write_persisted_options(&options).is_ok());
+        assert_eq!(dev.read_persisted_options(), Ok(Some(options)));
+        assert!(dev.remove_stale_data_segments(1..2).is_ok());
+        assert_eq!(dev.list_data_segments(), Ok(vec![1]));
+        assert!(dev.open_data_segment(0, false).is_err());
+        assert!(dev.open_data_segment(1, false).is_ok());
+        assert!(dev.remove_all().is_ok());
+        assert_eq!(dev.list_data_segments(), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_dev_lock() {
+        let dir = tempdir().unwrap();
+        let read_only = Options {
+            read_only: true,
+            ..Options::default()
+        };
+        drop(Dev::open(dir.path()).unwrap());
+        // store created before LOCK file
+        fs::remove_file(dir.path().join("LOCK")).unwrap();
+        let dev1 = Dev::with_options(dir.path(), read_only.clone()).unwrap();
+        let dev2 = Dev::with_options(dir.path(), read_only.clone()).unwrap();
+        // reader without LOCK file still locks out writer
+        assert_eq!(Dev::open(dir.path()).err(), Some(TdbError::Locked));
+        assert!(!dir.path().join("LOCK").exists());
+        drop(dev1);
+        drop(dev2);
+        let dev = Dev::open(dir.path()).unwrap();
+        fs::remove_file(dir.path().join("LOCK")).unwrap();
+        assert_eq!(
+            Dev::with_options(dir.path(), read_only.clone()).err(),
+            Some(TdbError::Locked)
+        );
+        drop(dev);
+        let dev = Dev::open(dir.path()).unwrap();
+        let dev_clone = dev.clone();
+        assert_eq!(Dev::open(dir.path()).err(), Some(TdbError::Locked));
+        assert_eq!(
+            Dev::with_options(dir.path(), read_only.clone()).err(),
+            Some(TdbError::Locked)
+        );
+        drop(dev);
+        // clone doesn't hold lock
+        drop(Dev::open(dir.path()).unwrap());
+        drop(dev_clone);
+        let dev1 = Dev::with_options(dir.path(), read_only.clone()).unwrap();
+        let dev2 = Dev::with_options(dir.path(), read_only).unwrap();
+        assert_eq!(Dev::open(dir.path()).err(), Some(TdbError::Locked));
+        drop(dev1);
+        drop(dev2);
+        assert!(Dev::open(dir.path()).is_ok());
+    }
+
+    #[test]
+    fn test_dev_legacy_data_log() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join(LEGACY_DATA_LOG_FILE), [0; 16]).unwrap();
+        assert_eq!(
+            Dev::open(dir.path()).err(),
+            Some(TdbError::UnsupportedFormat)
+        );
+        // nothing is created
+        assert!(!dir.path().join("meta_log_file.db").exists());
+        assert!(!dir.path().join("LOCK").exists());
+        assert_eq!(
+            fs::metadata(dir.path().join(LEGACY_DATA_LOG_FILE))
+                .unwrap()
+                .len(),
+            16
+        );
     }
 }
@@ -1,26 +1,35 @@
 use crate::error::TdbError;
 use crate::meta::CheckPoint;
-use crate::storage::{Deserialize, Serialize};
+use crate::object::UNUSED_OID;
+use crate::storage::{Deserialize, ObjectPos, Serialize};
+use byteorder::{ByteOrder, LittleEndian};
+use log::warn;
 use std::fs::{self, File};
-use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
 use std::path::{Path, PathBuf};
 
 // meta log file less than 2M
 pub const META_LOG_FILE_MAX_SIZE: usize = 1 << 21;
 
-const DEFAULT_BUF_SIZE: usize = 4096;
+pub const DEFAULT_META_BUF_SIZE: usize = 4096;
 
 pub struct MetaFileWriter {
     writer: BufWriter<File>,
     size: usize,
+    buf_size: usize,
+    // checkpoints are applied and meta file is rewritten when it's bigger than max size
+    max_size: usize,
 }
 
 impl MetaFileWriter {
-    pub fn new(mut file: File, size: usize) -> Self {
+    pub fn new(mut file: File, size: usize, buf_size: usize, max_size: usize) -> Self {
         file.seek(SeekFrom::Start(size as u64)).unwrap();
         MetaFileWriter {
-            writer: BufWriter::with_capacity(DEFAULT_BUF_SIZE, file),
-            size: size,
+            writer: BufWriter::with_capacity(buf_size, file),
+            size,
+            buf_size,
+            max_size,
         }
     }
     /// Write checkpoint to meta file
@@ -30,16 +39,23 @@ impl MetaFileWriter {
         cp.meta_size = self.size as u32;
         cp.serialize(&mut self.writer)?;
         self.writer.flush()?;
-        if self.size <= META_LOG_FILE_MAX_SIZE {
+        if self.size <= self.max_size {
             Ok(false)
         } else {
             Ok(true)
         }
     }
 
+    pub fn sync(&mut self) -> Result<(), TdbError> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+
     /// Write checkpoint to template meta file and rename template meta file to meta file
+    /// Template meta file is synced before rename and dir is synced after rename
     /// # Notes
-    /// checkpoint should be applied before write to template meta file
+    /// checkpoint should be applied and synced before write to template meta file
     pub fn write_cp_rename<P: AsRef<Path>>(
         &mut self,
         mut cp: CheckPoint,
@@ -51,15 +67,19 @@ impl MetaFileWriter {
         temp_path.pop();
         temp_path.push("meta_log_file_temp.db");
         let file = options_mut.open(&temp_path)?;
-        self.writer = BufWriter::with_capacity(DEFAULT_BUF_SIZE, file);
+        self.writer = BufWriter::with_capacity(self.buf_size, file);
+        // obj_changes were cleared, size must match the serialized checkpoint
+        cp.size = cp.len() as u32;
         self.size = cp.len();
-        assert!(self.size < META_LOG_FILE_MAX_SIZE && cp.obj_changes.len() == 0);
+        assert!(self.size < self.max_size && cp.obj_changes.is_empty());
         cp.meta_size = self.size as u32;
         cp.serialize(&mut self.writer)?;
-        self.writer.flush()?;
+        self.sync()?;
         fs::rename(&temp_path, &meta_log_file_path)?;
+        temp_path.pop();
+        File::open(&temp_path)?.sync_all()?;
         self.writer =
-            BufWriter::with_capacity(DEFAULT_BUF_SIZE, options_mut.open(&meta_log_file_path)?);
+            BufWriter::with_capacity(self.buf_size, options_mut.open(&meta_log_file_path)?);
         Ok(())
     }
 }
@@ -69,24 +89,55 @@ pub struct MetaLogFileReader {
 }
 
 impl MetaLogFileReader {
-    pub fn new(file: File) -> Self {
+    pub fn new(file: File, buf_size: usize) -> Self {
         Self {
-            reader: BufReader::with_capacity(DEFAULT_BUF_SIZE, file),
+            reader: BufReader::with_capacity(buf_size, file),
         }
     }
+    /// Read checkpoints until a partial or invalid one at the end of meta log, which is left by a crash
+    /// A partial first checkpoint is left by a crash during the first commit, no checkpoint is returned
+    /// # Errors
+    /// Return UnsupportedFormat if the first checkpoint is of other format,
+    /// or Corruption if the first one is invalid, or any one before the last is invalid
     pub fn read_cps(&mut self) -> Result<Vec<CheckPoint>, TdbError> {
         self.reader.seek(SeekFrom::Start(0))?;
+        let mut buf = vec![];
+        self.reader.read_to_end(&mut buf)?;
         let mut cps = Vec::default();
-        loop {
-            match CheckPoint::deserialize(&mut self.reader) {
-                Ok(cp) => {
-                    if cp.obj_changes.is_empty() {
-                        cps.clear();
-                    }
-                    cps.push(cp);
+        let mut offset = 0;
+        // applied checkpoint is always the first one in meta file,
+        // so checkpoint without obj change (empty commit) doesn't discard previous ones
+        while offset < buf.len() {
+            let rest = &buf[offset..];
+            let size = match rest.get(..mem::size_of::<u32>()) {
+                Some(size) => LittleEndian::read_u32(size) as usize,
+                None => 0,
+            };
+            // size isn't written or checkpoint isn't fully written
+            if size == 0 || size > rest.len() {
+                warn!("find partial checkpoint at {:?}, stop reading", offset);
+                break;
+            }
+            match CheckPoint::deserialize(&mut &rest[..size]) {
+                Ok(cp) => cps.push(cp),
+                // format of store is decided by the first one
+                Err(TdbError::UnsupportedFormat) if cps.is_empty() => {
+                    return Err(TdbError::UnsupportedFormat)
+                }
+                // last one may be torn even if its size is written
+                Err(err) if !cps.is_empty() && size == rest.len() => {
+                    warn!("find invalid checkpoint {:?} at the end, stop reading", err);
+                    break;
+                }
+                Err(err) => {
+                    warn!("find invalid checkpoint {:?} at {:?}", err, offset);
+                    return Err(TdbError::Corruption {
+                        oid: UNUSED_OID,
+                        pos: ObjectPos::default(),
+                    });
                 }
-                Err(_) => break,
             }
+            offset += size;
         }
         Ok(cps)
     }
@@ -94,7 +145,7 @@ impl MetaLogFileReader {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+
     use crate::meta::CheckPoint;
     use crate::storage::Dev;
     use crate::storage::ObjectPos;
@@ -109,11 +160,11 @@ mod tests {
         let dev = Dev::open(dir.path()).unwrap();
         let mut meta_reader = dev.get_meta_reader().unwrap();
         let mut meta_writer = dev.get_meta_writer(0).unwrap();
-        let mut cp0 = CheckPoint::new(0, 0, 0, 0, 0, vec![]);
+        let mut cp0 = CheckPoint::new(0, 0, 0, 0, 0, 0, 0, vec![]);
         assert!(meta_writer.write_cp(&mut cp0).is_ok());
         assert_eq!(meta_reader.read_cps(), Ok(vec![cp0.clone()]));
-        let mut cp1 = CheckPoint::new(0, 0, 0, 0, 0, vec![(0, ObjectPos::default())]);
-        let mut cp2 = CheckPoint::new(0, 0, 0, 0, 0, vec![(1, ObjectPos::default())]);
+        let mut cp1 = CheckPoint::new(0, 0, 0, 0, 0, 0, 0, vec![(0, ObjectPos::default())]);
+        let mut cp2 = CheckPoint::new(0, 0, 0, 0, 0, 0, 0, vec![(1, ObjectPos::default())]);
         assert!(meta_writer.write_cp(&mut cp1).is_ok());
         assert!(meta_writer.write_cp(&mut cp2).is_ok());
         assert_eq!(
@@ -121,5 +172,4 @@ mod tests {
             Ok(vec![cp0.clone(), cp1.clone(), cp2.clone()])
         );
     }
-
 }
@@ -4,13 +4,31 @@ mod meta_file;
 mod obj_pos;
 mod table_file;
 use crate::error::TdbError;
-pub use data_file::{DataFileReader, DataFilwWriter};
+pub use data_file::{DataFileReader, DataFilwWriter, DEFAULT_DATA_BUF_SIZE};
 pub use dev::Dev;
-pub use meta_file::{MetaFileWriter, MetaLogFileReader};
-pub use obj_pos::ObjectPos;
-pub use table_file::{TableFileReader, TableFileWriter};
+pub use meta_file::{
+    MetaFileWriter, MetaLogFileReader, DEFAULT_META_BUF_SIZE, META_LOG_FILE_MAX_SIZE,
+};
+pub use obj_pos::{ObjectPos, SEGMENT_SIZE};
+pub use table_file::{TableFileReader, TableFileWriter, DEFAULT_TABLE_BUF_SIZE};
 
 use std::io::{Read, Write};
+use std::time::Duration;
+
+/// When commit is synced to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// Never sync, leave it to os, commits may be lost or corrupted by power loss
+    None,
+    /// Sync data and checkpoint before every commit returns
+    #[default]
+    PerCommit,
+    /// Sync once duration passed since last sync, at commit or by a background thread of store
+    /// Commits between syncs may be lost
+    Periodic(Duration),
+    /// Like PerCommit, but batches of concurrent KVStore::write are merged into one commit and sync
+    GroupCommit,
+}
 
 pub trait Serialize {
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<usize, TdbError>;
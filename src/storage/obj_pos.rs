@@ -1,15 +1,18 @@
 use crate::object::ObjectTag;
 use std::fmt;
 use std::io::SeekFrom;
-use std::u64;
 
 // [20~63)
+// offset in data log, high bits are segment id
 
 // [4~20)
 
 // [1~4)
 
-#[derive(Eq, PartialEq, Clone, Copy, Hash)]
+// data log is split into segment files of fixed size, object never cross segments
+pub const SEGMENT_SIZE: u64 = 1 << 24;
+
+#[derive(Eq, PartialEq, Clone, Copy, Hash, Default)]
 pub struct ObjectPos(pub u64);
 
 impl fmt::Debug for ObjectPos {
@@ -24,9 +27,9 @@ impl fmt::Debug for ObjectPos {
     }
 }
 
-impl Into<SeekFrom> for ObjectPos {
-    fn into(self) -> SeekFrom {
-        SeekFrom::Start(self.get_pos())
+impl From<ObjectPos> for SeekFrom {
+    fn from(val: ObjectPos) -> Self {
+        SeekFrom::Start(val.get_segment_offset())
     }
 }
 
@@ -46,6 +49,16 @@ impl ObjectPos {
         self.0 = (self.0 & 0xfffff) + (pos << 20);
     }
 
+    #[inline]
+    pub fn get_segment_id(&self) -> u32 {
+        (self.get_pos() / SEGMENT_SIZE) as u32
+    }
+
+    #[inline]
+    pub fn get_segment_offset(&self) -> u64 {
+        self.get_pos() % SEGMENT_SIZE
+    }
+
     #[inline]
     pub fn get_len(&self) -> u16 {
         ((self.0 >> 4) & 0xffff) as u16
@@ -79,16 +92,9 @@ impl ObjectPos {
     }
 }
 
-impl Default for ObjectPos {
-    fn default() -> Self {
-        Self(0)
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::u16;
     #[test]
     fn test_obj_pos() {
         let obj_pos = ObjectPos::default();
@@ -109,7 +115,14 @@ mod tests {
         assert_eq!(obj_pos.get_pos(), 1 << 40);
         assert_eq!(obj_pos.get_len(), u16::MAX);
         assert_eq!(obj_pos.get_tag(), ObjectTag::Leaf);
-        let mut obj_pos = ObjectPos::new(1, 127, ObjectTag::Entry);
+        assert_eq!(
+            obj_pos.get_segment_id(),
+            ((1u64 << 40) / SEGMENT_SIZE) as u32
+        );
+        let obj_pos = ObjectPos::new(1, 127, ObjectTag::Entry);
         assert_eq!(obj_pos.get_tag(), ObjectTag::Entry);
+        let obj_pos = ObjectPos::new(SEGMENT_SIZE * 3 + 5, 127, ObjectTag::Entry);
+        assert_eq!(obj_pos.get_segment_id(), 3);
+        assert_eq!(obj_pos.get_segment_offset(), 5);
     }
 }
@@ -4,20 +4,19 @@ use crate::object::ObjectRef;
 use crate::storage::{Deserialize, Serialize};
 use crate::utils::BitMap;
 use byteorder::{LittleEndian, ReadBytesExt};
-use log::debug;
 use std::fs::File;
 use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 
-const DEFAULT_BUF_SIZE: usize = 4096 * 2;
+pub const DEFAULT_TABLE_BUF_SIZE: usize = 4096 * 2;
 pub struct TableFileWriter {
     writer: BufWriter<File>,
     pub used_page_num: u32,
 }
 
 impl TableFileWriter {
-    pub fn new(file: File, used_page_num: u32) -> Self {
+    pub fn new(file: File, used_page_num: u32, buf_size: usize) -> Self {
         TableFileWriter {
-            writer: BufWriter::with_capacity(DEFAULT_BUF_SIZE, file),
+            writer: BufWriter::with_capacity(buf_size, file),
             used_page_num,
         }
     }
@@ -30,8 +29,9 @@ impl TableFileWriter {
         page.serialize(&mut self.writer)?;
         Ok(())
     }
-    pub fn flush(&mut self) -> Result<(), TdbError> {
+    pub fn sync(&mut self) -> Result<(), TdbError> {
         self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
         Ok(())
     }
 }
@@ -80,7 +80,7 @@ impl TableFileReader {
                 table.remove(*oid, 0, 0).unwrap();
                 bitmap.set_bit(*oid as usize, false);
             } else {
-                let version = ObjectRef::on_disk(obj_pos.clone(), 0);
+                let version = ObjectRef::on_disk(*obj_pos, 0);
                 table.insert(*oid, version, 0).unwrap();
                 bitmap.set_bit(*oid as usize, true);
             }
@@ -114,7 +114,7 @@ mod tests {
         bitmap.set_bit(1, true);
         let page_ref = table.get_page_ref(0);
         assert!(table_writer.write_page(0, page_ref).is_ok());
-        assert!(table_writer.flush().is_ok());
+        assert!(table_writer.sync().is_ok());
         let mut cp = CheckPoint::default();
         let pos = ObjectPos::new(2, 3, ObjectTag::Entry);
         assert!(table.insert(2, ObjectRef::on_disk(pos, 0), 0).is_ok());
@@ -127,5 +127,4 @@ mod tests {
             assert_eq!(bitmap0.get_bit(i), bitmap.get_bit(i));
         }
     }
-
 }
@@ -1,11 +1,16 @@
-use super::TimeStamp;
+use super::{prefix_range, to_owned_bound, TimeStamp};
 use crate::cache::ImMutCache;
 use crate::error::TdbError;
 use crate::meta::{ImMutTable, InnerTable};
-use crate::object::{Branch, Entry, Key, Leaf, Object, ObjectId, Val, UNUSED_OID};
+use crate::object::{
+    Branch, Entry, Key, Leaf, LeafVal, Object, ObjectId, Overflow, Val, UNUSED_OID,
+};
 use crate::storage::DataFileReader;
 use std::borrow::Borrow;
-use std::ops::Range;
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::iter::Rev;
+use std::ops::{Bound, RangeBounds};
 use std::sync::Arc;
 
 pub struct ImMutContext {
@@ -30,37 +35,123 @@ impl ImMutContext {
     }
 }
 
-pub struct Iter<'a, K: Borrow<[u8]>> {
+/// Double-ended iterator of (key, val) in range
+pub struct Iter<'a> {
     ctx: &'a mut ImMutContext,
+    // path from root to leaf of front, (oid, obj, index in parent)
     path: Vec<(ObjectId, Arc<Object>, usize)>,
-    range: Range<&'a K>,
+    // index of next entry in front leaf
     entry_index: usize,
+    // path from root to leaf of back, seeked when next_back is first called
+    back_path: Option<Vec<(ObjectId, Arc<Object>, usize)>>,
+    // next entry of back is back_entry_index - 1
+    back_entry_index: usize,
+    // bounds are moved when entry is returned from either end
+    start: Bound<Key>,
+    end: Bound<Key>,
 }
 
-impl<'a, K: Borrow<[u8]>> Iter<'a, K> {
-    pub fn next_path(&mut self) -> Result<(), TdbError> {
+impl<'a> Iter<'a> {
+    // Find the first leaf and entry index in start bound
+    fn seek(&mut self) -> Result<(), TdbError> {
+        if self.ctx.root_oid == UNUSED_OID {
+            return Ok(());
+        }
+        let mut current_oid = self.ctx.root_oid;
+        let mut index = 0;
+        loop {
+            let current_obj = self.ctx.table.get_obj(current_oid, self.ctx.ts)?;
+            self.path.push((current_oid, current_obj.clone(), index));
+            match &*current_obj {
+                Object::E(_) | Object::O(_) => unreachable!(),
+                Object::B(branch) => {
+                    let (_oid, _index) = match &self.start {
+                        Bound::Included(key) | Bound::Excluded(key) => branch.search(key),
+                        Bound::Unbounded => (branch.children[0], 0),
+                    };
+                    current_oid = _oid;
+                    index = _index;
+                }
+                Object::L(leaf) => {
+                    self.entry_index = match &self.start {
+                        Bound::Included(key) => leaf.search_index(key),
+                        Bound::Excluded(key) => match leaf.search_index(key) {
+                            _index
+                                if _index < leaf.entrys.len() && &leaf.entrys[_index].0 == key =>
+                            {
+                                _index + 1
+                            }
+                            _index => _index,
+                        },
+                        Bound::Unbounded => 0,
+                    };
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Find the last leaf and entry index in end bound
+    fn seek_back(&mut self) -> Result<(), TdbError> {
+        let mut back_path = vec![];
+        if self.ctx.root_oid == UNUSED_OID {
+            self.back_path = Some(back_path);
+            return Ok(());
+        }
+        let mut current_oid = self.ctx.root_oid;
+        let mut index = 0;
         loop {
-            if let Some((_, _, index)) = self.path.pop() {
-                if let Some((_, _obj, _)) = self.path.last() {
-                    let mut parent_obj = _obj.clone();
-                    if index + 1 < parent_obj.get_ref::<Branch>().children.len() {
-                        let mut new_index = index + 1;
-                        loop {
-                            let new_oid = parent_obj.get_ref::<Branch>().children[new_index];
-                            let new_obj = self.ctx.table.get_obj(new_oid, self.ctx.ts)?;
-                            self.path.push((new_oid, new_obj.clone(), new_index));
-                            if new_obj.is::<Leaf>() {
-                                break;
-                            } else {
-                                parent_obj = new_obj;
-                                new_index = 0;
+            let current_obj = self.ctx.table.get_obj(current_oid, self.ctx.ts)?;
+            back_path.push((current_oid, current_obj.clone(), index));
+            match &*current_obj {
+                Object::E(_) | Object::O(_) => unreachable!(),
+                Object::B(branch) => {
+                    let (_oid, _index) = match &self.end {
+                        Bound::Included(key) | Bound::Excluded(key) => branch.search(key),
+                        Bound::Unbounded => {
+                            (*branch.children.last().unwrap(), branch.children.len() - 1)
+                        }
+                    };
+                    current_oid = _oid;
+                    index = _index;
+                }
+                Object::L(leaf) => {
+                    self.back_entry_index = match &self.end {
+                        Bound::Included(key) => match leaf.search_index(key) {
+                            _index
+                                if _index < leaf.entrys.len() && &leaf.entrys[_index].0 == key =>
+                            {
+                                _index + 1
                             }
+                            _index => _index,
+                        },
+                        Bound::Excluded(key) => leaf.search_index(key),
+                        Bound::Unbounded => leaf.entrys.len(),
+                    };
+                    self.back_path = Some(back_path);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    pub fn next_path(&mut self) -> Result<(), TdbError> {
+        while let Some((_, _, index)) = self.path.pop() {
+            if let Some((_, _obj, _)) = self.path.last() {
+                let mut parent_obj = _obj.clone();
+                if index + 1 < parent_obj.get_ref::<Branch>().children.len() {
+                    let mut new_index = index + 1;
+                    loop {
+                        let new_oid = parent_obj.get_ref::<Branch>().children[new_index];
+                        let new_obj = self.ctx.table.get_obj(new_oid, self.ctx.ts)?;
+                        self.path.push((new_oid, new_obj.clone(), new_index));
+                        if new_obj.is::<Leaf>() {
+                            break;
+                        } else {
+                            parent_obj = new_obj;
+                            new_index = 0;
                         }
-                        break;
-                    } else {
-                        continue;
                     }
-                } else {
                     break;
                 }
             } else {
@@ -71,97 +162,339 @@ impl<'a, K: Borrow<[u8]>> Iter<'a, K> {
         assert!(self.path.is_empty() || self.path.last().unwrap().1.is::<Leaf>());
         Ok(())
     }
-}
 
-impl<'a, K: Borrow<[u8]>> Iterator for Iter<'a, K> {
-    type Item = Result<Val, TdbError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.path.is_empty() {
-            None
-        } else {
-            let (_, leaf, _) = self.path.last().unwrap();
-            let mut leaf_ref = leaf.get_ref::<Leaf>();
-            if self.entry_index >= leaf_ref.entrys.len() {
-                match self.next_path() {
-                    Err(e) => {
-                        self.path.clear();
-                        return Some(Err(e));
+    pub fn prev_path(&mut self) -> Result<(), TdbError> {
+        let back_path = self.back_path.as_mut().unwrap();
+        while let Some((_, _, index)) = back_path.pop() {
+            if let Some((_, _obj, _)) = back_path.last() {
+                let mut parent_obj = _obj.clone();
+                if index > 0 {
+                    let mut new_index = index - 1;
+                    loop {
+                        let new_oid = parent_obj.get_ref::<Branch>().children[new_index];
+                        let new_obj = self.ctx.table.get_obj(new_oid, self.ctx.ts)?;
+                        back_path.push((new_oid, new_obj.clone(), new_index));
+                        if let Object::B(branch) = &*new_obj {
+                            new_index = branch.children.len() - 1;
+                            parent_obj = new_obj.clone();
+                        } else {
+                            break;
+                        }
                     }
-                    Ok(()) => {}
+                    break;
                 }
-                if let Some((_, leaf, _)) = self.path.last() {
-                    leaf_ref = leaf.get_ref::<Leaf>();
-                } else {
+            } else {
+                break;
+            }
+        }
+        self.back_entry_index = match back_path.last() {
+            Some((_, leaf, _)) => leaf.get_ref::<Leaf>().entrys.len(),
+            None => 0,
+        };
+        Ok(())
+    }
+
+    fn before_end(&self, key: &[u8]) -> bool {
+        match &self.end {
+            Bound::Included(end) => key <= end.as_slice(),
+            Bound::Excluded(end) => key < end.as_slice(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn after_start(&self, key: &[u8]) -> bool {
+        match &self.start {
+            Bound::Included(start) => key >= start.as_slice(),
+            Bound::Excluded(start) => key > start.as_slice(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    // Both ends meet or error happen
+    fn finish(&mut self) {
+        self.path.clear();
+        self.back_path = Some(vec![]);
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<(Key, Val), TdbError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let leaf = self.path.last()?.1.clone();
+            let leaf_ref = leaf.get_ref::<Leaf>();
+            if self.entry_index < leaf_ref.entrys.len() {
+                let (key, val) = &leaf_ref.entrys[self.entry_index];
+                if !self.before_end(key) {
+                    self.finish();
                     return None;
                 }
-            }
-            let (key, oid) = &leaf_ref.entrys[self.entry_index];
-            if key.as_slice() <= self.range.end.borrow() {
-                let obj = self.ctx.table.get_obj(*oid, self.ctx.ts);
                 self.entry_index += 1;
-                match obj {
-                    Ok(obj) => Some(Ok(obj.get_ref::<Entry>().val.clone())),
+                let kv = match val {
+                    LeafVal::Oid(oid) => {
+                        self.ctx.table.get_obj(*oid, self.ctx.ts).and_then(|obj| {
+                            let entry = obj.get_ref::<Entry>();
+                            Ok((entry.key.clone(), self.ctx.read_val(entry)?))
+                        })
+                    }
+                    LeafVal::Inline(val) => Ok((key.clone(), val.clone())),
+                };
+                return match kv {
+                    Ok(kv) => {
+                        self.start = Bound::Excluded(key.clone());
+                        Some(Ok(kv))
+                    }
                     Err(err) => {
-                        self.path.clear();
+                        self.finish();
                         Some(Err(err))
                     }
+                };
+            }
+            if let Err(err) = self.next_path() {
+                self.finish();
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.back_path.is_none() {
+            if let Err(err) = self.seek_back() {
+                self.finish();
+                return Some(Err(err));
+            }
+        }
+        loop {
+            let leaf = self.back_path.as_ref().unwrap().last()?.1.clone();
+            let leaf_ref = leaf.get_ref::<Leaf>();
+            if self.back_entry_index > 0 {
+                let (key, val) = &leaf_ref.entrys[self.back_entry_index - 1];
+                if !self.after_start(key) {
+                    self.finish();
+                    return None;
                 }
-            } else {
-                None
+                self.back_entry_index -= 1;
+                let kv = match val {
+                    LeafVal::Oid(oid) => {
+                        self.ctx.table.get_obj(*oid, self.ctx.ts).and_then(|obj| {
+                            let entry = obj.get_ref::<Entry>();
+                            Ok((entry.key.clone(), self.ctx.read_val(entry)?))
+                        })
+                    }
+                    LeafVal::Inline(val) => Ok((key.clone(), val.clone())),
+                };
+                return match kv {
+                    Ok(kv) => {
+                        self.end = Bound::Excluded(key.clone());
+                        Some(Ok(kv))
+                    }
+                    Err(err) => {
+                        self.finish();
+                        Some(Err(err))
+                    }
+                };
             }
+            if let Err(err) = self.prev_path() {
+                self.finish();
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+/// Reader of val, read entry and its overflow chain one by one
+pub struct ValReader<'a> {
+    ctx: &'a mut ImMutContext,
+    // (oid, offset in val) of entry and overflow objects visited
+    parts: Vec<(ObjectId, u64)>,
+    // (index in parts, object) of current part
+    current: Option<(usize, Arc<Object>)>,
+    pos: u64,
+    // known after last part is visited
+    len: Option<u64>,
+}
+
+impl<'a> ValReader<'a> {
+    #[inline]
+    fn get_data(obj: &Object) -> (&[u8], ObjectId) {
+        match obj {
+            Object::E(entry) => (&entry.val, entry.overflow_oid),
+            Object::O(overflow) => (&overflow.data, overflow.next_oid),
+            _ => unreachable!(),
+        }
+    }
+
+    // Load the part containing pos
+    // Return false if pos is not less than val len
+    fn load(&mut self) -> Result<bool, TdbError> {
+        let mut index = match self
+            .parts
+            .binary_search_by_key(&self.pos, |(_, offset)| *offset)
+        {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        };
+        loop {
+            let (oid, offset) = self.parts[index];
+            let obj = match &self.current {
+                Some((current_index, obj)) if *current_index == index => obj.clone(),
+                _ => self.ctx.table.get_obj(oid, self.ctx.ts)?,
+            };
+            let (data, next_oid) = Self::get_data(&obj);
+            let end = offset + data.len() as u64;
+            self.current = Some((index, obj.clone()));
+            if self.pos < end {
+                return Ok(true);
+            }
+            if next_oid == UNUSED_OID {
+                self.len = Some(end);
+                return Ok(false);
+            }
+            if index + 1 == self.parts.len() {
+                self.parts.push((next_oid, end));
+            }
+            index += 1;
+        }
+    }
+
+    /// Return len of val
+    pub fn get_len(&mut self) -> Result<u64, TdbError> {
+        if let Some(len) = self.len {
+            return Ok(len);
+        }
+        let pos = self.pos;
+        self.pos = u64::MAX;
+        let result = self.load();
+        self.pos = pos;
+        result?;
+        Ok(self.len.unwrap())
+    }
+}
+
+impl<'a> Read for ValReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || !self.load()? {
+            return Ok(0);
+        }
+        let (index, obj) = self.current.as_ref().unwrap();
+        let (data, _) = Self::get_data(obj);
+        let offset = (self.pos - self.parts[*index].1) as usize;
+        let size = cmp::min(buf.len(), data.len() - offset);
+        buf[..size].copy_from_slice(&data[offset..offset + size]);
+        self.pos += size as u64;
+        Ok(size)
+    }
+}
+
+impl<'a> Seek for ValReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.get_len()?.checked_add_signed(delta),
+        };
+        match new_pos {
+            Some(new_pos) => {
+                self.pos = new_pos;
+                Ok(new_pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
         }
     }
 }
 
 impl ImMutContext {
-    pub fn get<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<Val>, TdbError> {
+    // Read val of entry followed by its overflow chain
+    fn read_val(&mut self, entry: &Entry) -> Result<Val, TdbError> {
+        let mut val = entry.val.clone();
+        let mut oid = entry.overflow_oid;
+        while oid != UNUSED_OID {
+            let obj = self.table.get_obj(oid, self.ts)?;
+            let overflow = obj.get_ref::<Overflow>();
+            val.extend_from_slice(&overflow.data);
+            oid = overflow.next_oid;
+        }
+        Ok(val)
+    }
+
+    /// Return reader of val, which don't read the whole val into memory
+    pub fn get_reader<K: Borrow<[u8]>>(
+        &mut self,
+        key: &K,
+    ) -> Result<Option<ValReader<'_>>, TdbError> {
         if self.root_oid == UNUSED_OID {
             return Ok(None);
         }
         let mut current_oid = self.root_oid;
         loop {
             let current_obj = self.table.get_obj(current_oid, self.ts)?;
-
             match &*current_obj {
-                Object::E(entry) => {
-                    // Notice that , we don't cache entry
-                    return Ok(Some(entry.val.clone()));
+                Object::E(_) => {
+                    return Ok(Some(ValReader {
+                        ctx: self,
+                        parts: vec![(current_oid, 0)],
+                        current: Some((0, current_obj)),
+                        pos: 0,
+                        len: None,
+                    }));
                 }
                 Object::L(leaf) => match leaf.search(key) {
-                    Ok(oid) => current_oid = oid,
+                    Ok(LeafVal::Oid(oid)) => current_oid = *oid,
+                    // val in leaf is the only part of reader
+                    Ok(LeafVal::Inline(val)) => {
+                        let entry = Entry::new(key.borrow().to_vec(), val.clone());
+                        return Ok(Some(ValReader {
+                            ctx: self,
+                            parts: vec![(UNUSED_OID, 0)],
+                            current: Some((0, Arc::new(Object::E(entry)))),
+                            pos: 0,
+                            len: Some(val.len() as u64),
+                        }));
+                    }
                     Err(_) => return Ok(None),
                 },
                 Object::B(branch) => {
                     let (oid, _) = branch.search(key);
                     current_oid = oid;
                 }
+                Object::O(_) => unreachable!(),
             }
         }
     }
 
-    pub fn get_min(&mut self) -> Result<Option<(Key, Val)>, TdbError> {
+    pub fn get<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<Val>, TdbError> {
         if self.root_oid == UNUSED_OID {
             return Ok(None);
         }
         let mut current_oid = self.root_oid;
         loop {
             let current_obj = self.table.get_obj(current_oid, self.ts)?;
+
             match &*current_obj {
                 Object::E(entry) => {
                     // Notice that , we don't cache entry
-                    return Ok(Some((entry.key.clone(), entry.val.clone())));
-                }
-                Object::L(leaf) => {
-                    current_oid = leaf.entrys[0].1;
+                    return Ok(Some(self.read_val(entry)?));
                 }
+                Object::O(_) => unreachable!(),
+                Object::L(leaf) => match leaf.search(key) {
+                    Ok(LeafVal::Oid(oid)) => current_oid = *oid,
+                    Ok(LeafVal::Inline(val)) => return Ok(Some(val.clone())),
+                    Err(_) => return Ok(None),
+                },
                 Object::B(branch) => {
-                    current_oid = branch.children[0];
+                    let (oid, _) = branch.search(key);
+                    current_oid = oid;
                 }
             }
         }
     }
 
-    pub fn get_max(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>, TdbError> {
+    pub fn get_min(&mut self) -> Result<Option<(Key, Val)>, TdbError> {
         if self.root_oid == UNUSED_OID {
             return Ok(None);
         }
@@ -171,52 +504,77 @@ impl ImMutContext {
             match &*current_obj {
                 Object::E(entry) => {
                     // Notice that , we don't cache entry
-                    return Ok(Some((entry.key.clone(), entry.val.clone())));
-                }
-                Object::L(leaf) => {
-                    current_oid = leaf.entrys.last().unwrap().1;
+                    return Ok(Some((entry.key.clone(), self.read_val(entry)?)));
                 }
+                Object::O(_) => unreachable!(),
+                Object::L(leaf) => match &leaf.entrys[0] {
+                    (_, LeafVal::Oid(oid)) => current_oid = *oid,
+                    (key, LeafVal::Inline(val)) => return Ok(Some((key.clone(), val.clone()))),
+                },
                 Object::B(branch) => {
-                    current_oid = *branch.children.last().unwrap();
+                    current_oid = branch.children[0];
                 }
             }
         }
     }
 
-    pub fn range<'a, K: Borrow<[u8]>>(
-        &'a mut self,
-        range: Range<&'a K>,
-    ) -> Result<Option<Iter<'a, K>>, TdbError> {
+    pub fn get_max(&mut self) -> Result<Option<(Key, Val)>, TdbError> {
         if self.root_oid == UNUSED_OID {
             return Ok(None);
         }
         let mut current_oid = self.root_oid;
-        let mut index = 0;
-        let mut entry_index = 0;
-        let mut path = vec![];
         loop {
             let current_obj = self.table.get_obj(current_oid, self.ts)?;
-
-            path.push((current_oid, current_obj.clone(), index));
             match &*current_obj {
-                Object::E(_) => unreachable!(),
-                Object::B(branch) => {
-                    let (_oid, _index) = branch.search(range.start);
-                    current_oid = _oid;
-                    index = _index;
+                Object::E(entry) => {
+                    // Notice that , we don't cache entry
+                    return Ok(Some((entry.key.clone(), self.read_val(entry)?)));
                 }
-                Object::L(leaf) => {
-                    entry_index = leaf.search_index(range.start);
-                    break;
+                Object::O(_) => unreachable!(),
+                Object::L(leaf) => match leaf.entrys.last().unwrap() {
+                    (_, LeafVal::Oid(oid)) => current_oid = *oid,
+                    (key, LeafVal::Inline(val)) => return Ok(Some((key.clone(), val.clone()))),
+                },
+                Object::B(branch) => {
+                    current_oid = *branch.children.last().unwrap();
                 }
             }
         }
-        Ok(Some(Iter {
+    }
+
+    /// Return double-ended iterator of (key, val) in range
+    pub fn range<K: Borrow<[u8]>, R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<Iter<'_>, TdbError> {
+        let start = to_owned_bound(range.start_bound());
+        let end = to_owned_bound(range.end_bound());
+        let mut iter = Iter {
             ctx: self,
-            path,
-            range,
-            entry_index,
-        }))
+            path: vec![],
+            entry_index: 0,
+            back_path: None,
+            back_entry_index: 0,
+            start,
+            end,
+        };
+        iter.seek()?;
+        Ok(iter)
+    }
+
+    /// Return iterator of (key, val) in range from the last one
+    pub fn range_rev<K: Borrow<[u8]>, R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<Rev<Iter<'_>>, TdbError> {
+        let mut iter = self.range(range)?;
+        iter.seek_back()?;
+        Ok(iter.rev())
+    }
+
+    /// Return iterator of (key, val) whose key starts with prefix
+    pub fn scan_prefix(&mut self, prefix: &[u8]) -> Result<Iter<'_>, TdbError> {
+        self.range(prefix_range(prefix))
     }
 }
 
@@ -226,11 +584,13 @@ mod tests {
     use crate::cache::ImMutCache;
     use crate::object::{Entry, ObjectRef};
     use crate::storage::{Dev, ObjectPos};
-    use std::env;
     use std::sync::Arc;
+    use tempfile::tempdir;
     #[test]
     fn test_immut_ctx() {
-        let dev = Dev::open(env::current_dir().unwrap()).unwrap();
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
+        dev.get_data_writer(0, 0, 0).unwrap();
         let data_reader = dev.get_data_reader().unwrap();
         let cache = ImMutCache::default();
         let table = InnerTable::with_capacity(1);
@@ -252,15 +612,15 @@ mod tests {
         let _ = table.insert(4, obj4, 0);
 
         let mut l1 = Leaf::default();
-        l1.insert_non_full(0, vec![1], 1);
-        l1.insert_non_full(1, vec![2], 2);
+        l1.insert_non_full(0, vec![1], LeafVal::Oid(1));
+        l1.insert_non_full(1, vec![2], LeafVal::Oid(2));
         let l1 = Arc::new(Object::L(l1));
         let obj5 = ObjectRef::new(&l1, ObjectPos::default(), 0);
         let _ = table.insert(5, obj5, 0);
 
         let mut l2 = Leaf::default();
-        l2.insert_non_full(0, vec![3], 3);
-        l2.insert_non_full(1, vec![4], 4);
+        l2.insert_non_full(0, vec![3], LeafVal::Oid(3));
+        l2.insert_non_full(1, vec![4], LeafVal::Oid(4));
         let l2 = Arc::new(Object::L(l2));
         let obj6 = ObjectRef::new(&l2, ObjectPos::default(), 0);
         let _ = table.insert(6, obj6, 0);
@@ -273,7 +633,8 @@ mod tests {
         let obj7 = ObjectRef::new(&b1, ObjectPos::default(), 0);
         let _ = table.insert(7, obj7, 0);
 
-        let mut reader = ImMutContext::new(7, 1, Arc::new(table), data_reader, cache);
+        let table = Arc::new(table);
+        let mut reader = ImMutContext::new(7, 1, table.clone(), data_reader, cache);
 
         assert_eq!(reader.get(&vec![1]).unwrap(), Some(vec![1]));
         assert_eq!(reader.get(&vec![2]).unwrap(), Some(vec![2]));
@@ -281,17 +642,59 @@ mod tests {
         assert_eq!(reader.get(&vec![4]).unwrap(), Some(vec![4]));
         let low = vec![1];
         let high = vec![4];
-        let mut range = reader.range(&low..&high).unwrap().unwrap();
-        assert_eq!(range.next(), Some(Ok(vec![1])));
-        assert_eq!(range.next(), Some(Ok(vec![2])));
-        assert_eq!(range.next(), Some(Ok(vec![3])));
-        assert_eq!(range.next(), Some(Ok(vec![4])));
+        let range: Vec<Key> = reader
+            .range(low.as_slice()..high.as_slice())
+            .unwrap()
+            .map(|kv| kv.unwrap().0)
+            .collect();
+        assert_eq!(range, vec![vec![1], vec![2], vec![3]]);
+        let mut range = reader.range(low.as_slice()..=high.as_slice()).unwrap();
+        assert_eq!(range.next(), Some(Ok((vec![1], vec![1]))));
+        assert_eq!(range.next(), Some(Ok((vec![2], vec![2]))));
+        assert_eq!(range.next(), Some(Ok((vec![3], vec![3]))));
+        assert_eq!(range.next(), Some(Ok((vec![4], vec![4]))));
         assert_eq!(range.next(), None);
         let low = vec![4];
         let high = vec![5];
-        let mut range = reader.range(&low..&high).unwrap().unwrap();
-        assert_eq!(range.next(), Some(Ok(vec![4])));
+        let mut range = reader.range(low.as_slice()..high.as_slice()).unwrap();
+        assert_eq!(range.next(), Some(Ok((vec![4], vec![4]))));
+        assert_eq!(range.next(), None);
+        assert_eq!(reader.range::<Key, _>(..).unwrap().count(), 4);
+        assert_eq!(reader.range(vec![2]..).unwrap().count(), 3);
+        assert_eq!(reader.range(..vec![2]).unwrap().count(), 1);
+        assert_eq!(reader.range(..=vec![2]).unwrap().count(), 2);
+        let range = (Bound::Excluded(vec![2]), Bound::Excluded(vec![4]));
+        let range: Vec<Key> = reader
+            .range(range)
+            .unwrap()
+            .map(|kv| kv.unwrap().0)
+            .collect();
+        assert_eq!(range, vec![vec![3]]);
+        assert_eq!(reader.range(vec![5]..).unwrap().count(), 0);
+        let mut range = reader.range_rev(vec![1]..vec![4]).unwrap();
+        assert_eq!(range.next(), Some(Ok((vec![3], vec![3]))));
+        assert_eq!(range.next(), Some(Ok((vec![2], vec![2]))));
+        assert_eq!(range.next(), Some(Ok((vec![1], vec![1]))));
+        assert_eq!(range.next(), None);
+        let mut range = reader.range::<Key, _>(..).unwrap();
+        assert_eq!(range.next_back(), Some(Ok((vec![4], vec![4]))));
+        assert_eq!(range.next(), Some(Ok((vec![1], vec![1]))));
+        assert_eq!(range.next_back(), Some(Ok((vec![3], vec![3]))));
+        assert_eq!(range.next(), Some(Ok((vec![2], vec![2]))));
+        assert_eq!(range.next_back(), None);
         assert_eq!(range.next(), None);
+        let range = (Bound::Excluded(vec![1]), Bound::Excluded(vec![3]));
+        assert_eq!(reader.range_rev(range).unwrap().count(), 1);
+        // empty tree
+        let mut empty = ImMutContext::new(
+            UNUSED_OID,
+            1,
+            table,
+            dev.get_data_reader().unwrap(),
+            ImMutCache::default(),
+        );
+        assert_eq!(empty.range::<Key, _>(..).unwrap().count(), 0);
+        assert_eq!(empty.range_rev::<Key, _>(..).unwrap().count(), 0);
 
         assert_eq!(reader.get_max(), Ok(Some((vec![4], vec![4]))));
         assert_eq!(reader.get_min(), Ok(Some((vec![1], vec![1]))));
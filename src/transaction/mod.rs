@@ -1,9 +1,44 @@
 mod immut_context;
 mod mut_context;
-pub use immut_context::{ImMutContext, Iter};
-pub use mut_context::MutContext;
+pub use immut_context::{ImMutContext, Iter, ValReader};
+pub use mut_context::{MutContext, MutIter};
 
-use std::u64;
+use crate::object::Key;
+use std::borrow::Borrow;
+use std::ops::Bound;
 
 pub type TimeStamp = u64;
 pub const MAX_TS: u64 = u64::MAX;
+
+/// Return the bounds of keys starting with prefix
+pub fn prefix_range(prefix: &[u8]) -> (Bound<Key>, Bound<Key>) {
+    let mut end = prefix.to_vec();
+    // strip trailing 0xFF, no key sharing prefix is greater than the increased one
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return (Bound::Included(prefix.to_vec()), Bound::Excluded(end));
+        }
+    }
+    (Bound::Included(prefix.to_vec()), Bound::Unbounded)
+}
+
+/// Return bound of owned key
+pub fn to_owned_bound<K: Borrow<[u8]>>(bound: Bound<&K>) -> Bound<Key> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.borrow().to_vec()),
+        Bound::Excluded(key) => Bound::Excluded(key.borrow().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Return false if range contains no key, BTreeMap::range panics on it
+pub fn is_valid_range(start: &Bound<Key>, end: &Bound<Key>) -> bool {
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start <= end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start < end,
+        (Bound::Excluded(start), Bound::Excluded(end)) => start < end,
+        _ => true,
+    }
+}
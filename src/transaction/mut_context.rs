@@ -1,44 +1,81 @@
-use super::TimeStamp;
+use super::{prefix_range, to_owned_bound, TimeStamp};
+use crate::batch::{BatchOp, WriteBatch};
 use crate::cache::ImMutCache;
 use crate::error::TdbError;
 use crate::kv::Context;
-use crate::meta::{CheckPoint, InnerTable, MutTable};
+use crate::meta::{CheckPoint, InnerTable, MutTable, ObjChanges};
 use crate::object::{
-    AsObject, Branch, Entry, Key, Leaf, Object, ObjectId, Val, MAX_KEY_SIZE, MAX_OBJ_SIZE,
-    UNUSED_OID,
+    Branch, Entry, Key, Leaf, LeafVal, Object, ObjectId, Overflow, Val, MAX_INLINE_VAL_SIZE,
+    MAX_KEY_SIZE, UNUSED_OID,
 };
-use crate::storage::{DataFilwWriter, Dev, MetaFileWriter, TableFileWriter};
-use log::debug;
+use crate::storage::{DataFilwWriter, Dev, MetaFileWriter, SyncMode, TableFileWriter};
+use log::{debug, info, warn};
 use std::borrow::Borrow;
-use std::collections::{HashSet, VecDeque};
+use std::cmp;
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::fs;
+use std::io::Read;
+use std::mem;
+use std::ops::{Bound, Range, RangeBounds};
 use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
 pub struct MutContext {
     root_oid: ObjectId,
+    // root of last commit, restored by rollback
+    committed_root_oid: ObjectId,
     ts: TimeStamp,
+    // ts of last commit, restored by rollback
+    committed_ts: TimeStamp,
     table: MutTable,
     meta_writer: MetaFileWriter,
     table_writer: TableFileWriter,
     data_writer: DataFilwWriter,
+    // segments replaced by compaction at ts, remove them after older ctxs are dropped
+    stale_segments: Option<(TimeStamp, Range<u32>)>,
+    // None to disable auto compaction
+    compact_ratio: Option<f64>,
+    inline_val_size: usize,
+    // leaf and branch are split at it
+    node_size: usize,
+    sync_mode: SyncMode,
+    last_sync: Instant,
+    // commits are written after last sync
+    unsynced: bool,
     gc_ctx: VecDeque<(Weak<Context>, TimeStamp, Vec<ObjectId>)>,
+    // keys changed by uncommitted and committed but not visible changes, for conflict detection
+    changed_keys: BTreeSet<Key>,
     dev: Dev,
 }
 
 impl MutContext {
     // Find no cheakpoint
     pub fn new_empty(dev: Dev) -> Result<(Self, Arc<InnerTable>, ImMutCache), TdbError> {
+        let data_writer = dev.get_data_writer(0, 0, 0)?;
         let data_log_reader = dev.get_data_reader()?;
         let meta_writer = dev.get_meta_writer(0)?;
         let table_writer = dev.get_table_writer(0)?;
-        let data_writer = dev.get_data_writer(0, 0)?;
         let mut_ctx = Self {
             root_oid: UNUSED_OID,
+            committed_root_oid: UNUSED_OID,
             ts: 0,
-            table: MutTable::new_empty(data_log_reader),
+            committed_ts: 0,
+            table: MutTable::new_empty(
+                data_log_reader,
+                ImMutCache::with_capacity(dev.options.cache_size),
+            ),
             meta_writer,
             table_writer,
             data_writer,
+            stale_segments: None,
+            compact_ratio: dev.options.compact_ratio,
+            inline_val_size: dev.options.inline_val_size,
+            node_size: dev.options.get_node_size(),
+            sync_mode: dev.options.sync_mode,
+            last_sync: Instant::now(),
+            unsynced: false,
             gc_ctx: VecDeque::default(),
+            changed_keys: BTreeSet::default(),
             dev,
         };
         let table = mut_ctx.table.get_inner_table();
@@ -47,21 +84,38 @@ impl MutContext {
     }
     // Find at least one checkpoint
     pub fn new(dev: Dev, cp: CheckPoint) -> Result<(Self, Arc<InnerTable>, ImMutCache), TdbError> {
+        let data_writer =
+            dev.get_data_writer(cp.first_segment_id, cp.data_size, cp.data_removed_size)?;
         let data_log_reader = dev.get_data_reader()?;
         let meta_writer = dev.get_meta_writer(cp.meta_size as usize)?;
         let table_writer = dev.get_table_writer(cp.tablepage_nums)?;
-        let data_writer = dev.get_data_writer(cp.data_size, cp.data_removed_size)?;
         let (table, bitmap) = dev.get_table_reader()?.read_table(&cp)?;
         let dirty_pages = cp.get_dirty_pages();
         let mut_ctx = Self {
             root_oid: cp.root_oid,
-            ts: 0,
-            table: MutTable::new(data_log_reader, table, bitmap, dirty_pages),
-            meta_writer: meta_writer,
-            table_writer: table_writer,
-            data_writer: data_writer,
+            committed_root_oid: cp.root_oid,
+            ts: cp.ts,
+            committed_ts: cp.ts,
+            table: MutTable::new(
+                data_log_reader,
+                table,
+                bitmap,
+                dirty_pages,
+                ImMutCache::with_capacity(dev.options.cache_size),
+            ),
+            meta_writer,
+            table_writer,
+            data_writer,
+            stale_segments: None,
+            compact_ratio: dev.options.compact_ratio,
+            inline_val_size: dev.options.inline_val_size,
+            node_size: dev.options.get_node_size(),
+            sync_mode: dev.options.sync_mode,
+            last_sync: Instant::now(),
+            unsynced: false,
             gc_ctx: VecDeque::default(),
-            dev: dev,
+            changed_keys: BTreeSet::default(),
+            dev,
         };
         let table = mut_ctx.table.get_inner_table();
         let cache = mut_ctx.table.get_immut_cache();
@@ -72,134 +126,211 @@ impl MutContext {
     pub fn increase_ts(&mut self) {
         self.ts += 1;
     }
+    /// Compact data file after commit if removed size more than ratio of data file, None to disable
+    #[inline]
+    pub fn set_compact_ratio(&mut self, ratio: Option<f64>) {
+        assert!(ratio.is_none_or(|ratio| ratio > 0.0 && ratio <= 1.0));
+        self.compact_ratio = ratio;
+    }
+    /// Return true if auto compaction is enabled and removed size reaches its ratio
+    #[inline]
+    pub fn should_compact(&self) -> bool {
+        self.compact_ratio
+            .is_some_and(|ratio| self.data_writer.should_compact(ratio))
+    }
+    /// Put val in leaf instead of entry object if its len is not more than size
+    #[inline]
+    pub fn set_inline_val_size(&mut self, size: usize) {
+        assert!(size <= MAX_INLINE_VAL_SIZE);
+        self.inline_val_size = size;
+    }
+    #[inline]
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.sync_mode = sync_mode;
+    }
+    #[inline]
+    pub fn get_sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
     pub fn insert<K: Into<Key>, V: Into<Val>>(&mut self, key: K, val: V) -> Result<(), TdbError> {
         let key: Key = key.into();
         let val: Val = val.into();
-        if key.len() > MAX_KEY_SIZE as usize
-            || Entry::get_header_size() + key.len() + val.len() > MAX_OBJ_SIZE as usize
-        {
+        if key.len() > MAX_KEY_SIZE as usize {
+            return Err(TdbError::ObjectTooBig);
+        }
+        self.changed_keys.insert(key.clone());
+        if val.len() <= self.inline_val_size {
+            if let Some(LeafVal::Oid(old_oid)) = self.insert_leaf_val(key, LeafVal::Inline(val))? {
+                self.remove_entry(old_oid)?;
+            }
+            return Ok(());
+        }
+        let (val, overflow_oid) = self.insert_overflow(key.len(), val);
+        self.insert_entry(key, val, overflow_oid)
+    }
+
+    /// Insert val of len read from reader, the part which can't be put in entry is written to data file directly
+    /// # Errors
+    /// Return IoError with UnexpectedEof if reader has less than len bytes
+    pub fn insert_from<K: Into<Key>, R: Read>(
+        &mut self,
+        key: K,
+        mut reader: R,
+        len: u64,
+    ) -> Result<(), TdbError> {
+        let key: Key = key.into();
+        if key.len() > MAX_KEY_SIZE as usize {
             return Err(TdbError::ObjectTooBig);
         }
-        if let Some(oid) = self.get_oid(&key)? {
+        self.changed_keys.insert(key.clone());
+        if len <= self.inline_val_size as u64 {
+            let mut val = vec![0; len as usize];
+            reader.read_exact(&mut val)?;
+            return self.insert(key, val);
+        }
+        let val_len = cmp::min(len, Entry::max_val_size(key.len()) as u64);
+        let mut val = vec![0; val_len as usize];
+        reader.read_exact(&mut val)?;
+        let overflow_oid = self.write_overflow(&mut reader, len - val_len)?;
+        self.insert_entry(key, val, overflow_oid)
+    }
+
+    fn insert_entry(&mut self, key: Key, val: Val, overflow_oid: ObjectId) -> Result<(), TdbError> {
+        if let Some(LeafVal::Oid(oid)) = self.get_leaf_val(&key)? {
+            // remove overflow chain of old val
+            let old_overflow_oid = self
+                .table
+                .get_ref(oid, self.ts)?
+                .get_ref::<Entry>()
+                .overflow_oid;
+            self.remove_overflow(old_overflow_oid)?;
             // make oid dirty
             let obj_mut = self.table.get_mut(oid, self.ts)?;
             assert!(obj_mut.is::<Entry>());
             let entry_mut = obj_mut.get_mut::<Entry>();
             assert!(entry_mut.key == key);
-            entry_mut.update(val);
-            return Ok(());
+            entry_mut.update(val, overflow_oid);
+            Ok(())
         } else {
-            // create empty leaf if tree is empty
-            if self.root_oid == UNUSED_OID {
-                let new_leaf = Leaf::default();
-                self.root_oid = self.table.insert(Object::L(new_leaf));
-            }
-            let mut current_oid = self.root_oid;
-            let mut current_index = 0;
-            let mut parent_oid = self.root_oid;
-            // allocate new node
-            let entry_obj = Object::E(Entry::new(key.clone(), val));
-            assert!(entry_obj.get_pos().get_len() <= MAX_OBJ_SIZE);
+            // allocate new node, old val is inlined in leaf if exists
+            let entry_obj = Object::E(Entry::with_overflow(key.clone(), val, overflow_oid));
             let entry_oid = self.table.insert(entry_obj);
-            loop {
-                let current_obj = self.table.get_ref(current_oid, self.ts)?;
-                match current_obj {
-                    Object::E(_) => unreachable!(),
-                    Object::L(_) => {
-                        let obj_mut = self.table.get_mut(current_oid, self.ts)?.get_mut::<Leaf>();
-                        let insert_index = obj_mut.search(&key).unwrap_err();
-                        obj_mut.insert_non_full(insert_index, key, entry_oid);
-                        // split if leaf is full
-                        if obj_mut.should_split() {
-                            let (split_key, new_leaf) = obj_mut.split();
-                            let new_leaf_oid = self.table.insert(Object::L(new_leaf));
-                            // leaf is root
-                            if current_oid == self.root_oid {
-                                let branch = Branch::new(split_key, current_oid, new_leaf_oid);
-                                self.root_oid = self.table.insert(Object::B(branch));
-                            }
-                            // insert parent branch
-                            else {
-                                let parent_branch =
-                                    self.table.get_mut(parent_oid, self.ts)?.get_mut::<Branch>();
-                                parent_branch.insert_non_full(
-                                    current_index,
-                                    split_key,
-                                    new_leaf_oid,
-                                );
-                            }
+            self.insert_leaf_val(key, LeafVal::Oid(entry_oid))?;
+            Ok(())
+        }
+    }
+
+    // Insert or replace val of key in leaf
+    // Return old val
+    fn insert_leaf_val(&mut self, key: Key, val: LeafVal) -> Result<Option<LeafVal>, TdbError> {
+        // create empty leaf if tree is empty
+        if self.root_oid == UNUSED_OID {
+            let new_leaf = Leaf::default();
+            self.root_oid = self.table.insert(Object::L(new_leaf));
+        }
+        let mut current_oid = self.root_oid;
+        let mut current_index = 0;
+        let mut parent_oid = self.root_oid;
+        loop {
+            let current_obj = self.table.get_ref(current_oid, self.ts)?;
+            match current_obj {
+                Object::E(_) | Object::O(_) => unreachable!(),
+                Object::L(_) => {
+                    let obj_mut = self.table.get_mut(current_oid, self.ts)?.get_mut::<Leaf>();
+                    let index = obj_mut.search_index(&key);
+                    let old_val = if index < obj_mut.entrys.len() && obj_mut.entrys[index].0 == key
+                    {
+                        Some(obj_mut.update_val(index, val))
+                    } else {
+                        obj_mut.insert_non_full(index, key, val);
+                        None
+                    };
+                    // split if leaf is full
+                    if obj_mut.should_split(self.node_size) {
+                        let (split_key, new_leaf) = obj_mut.split(self.node_size);
+                        let new_leaf_oid = self.table.insert(Object::L(new_leaf));
+                        // leaf is root
+                        if current_oid == self.root_oid {
+                            let branch = Branch::new(split_key, current_oid, new_leaf_oid);
+                            self.root_oid = self.table.insert(Object::B(branch));
+                        }
+                        // insert parent branch
+                        else {
+                            let parent_branch =
+                                self.table.get_mut(parent_oid, self.ts)?.get_mut::<Branch>();
+                            parent_branch.insert_non_full(current_index, split_key, new_leaf_oid);
                         }
-                        return Ok(());
                     }
-                    Object::B(branch) => {
-                        if branch.should_split() {
-                            let obj_mut = self
-                                .table
-                                .get_mut(current_oid, self.ts)?
-                                .get_mut::<Branch>();
-                            let (split_key, new_branch) = obj_mut.split();
-                            let new_branch_oid = self.table.insert(Object::B(new_branch));
-                            let val_in_left = split_key <= key;
-                            // leaf is root
-                            if current_oid == self.root_oid {
-                                // new  root
-                                let branch = Branch::new(split_key, current_oid, new_branch_oid);
-                                self.root_oid = self.table.insert(Object::B(branch));
-                            }
-                            // insert parent branch
-                            else {
-                                let parent_branch =
-                                    self.table.get_mut(parent_oid, self.ts)?.get_mut::<Branch>();
-                                parent_branch.insert_non_full(
-                                    current_index,
-                                    split_key,
-                                    new_branch_oid,
-                                );
-                            }
-                            // reset current obj
-                            if val_in_left {
-                                current_oid = new_branch_oid;
-                                current_index += 1;
-                            }
+                    return Ok(old_val);
+                }
+                Object::B(branch) => {
+                    if branch.should_split(self.node_size) {
+                        let obj_mut = self
+                            .table
+                            .get_mut(current_oid, self.ts)?
+                            .get_mut::<Branch>();
+                        let (split_key, new_branch) = obj_mut.split(self.node_size);
+                        let new_branch_oid = self.table.insert(Object::B(new_branch));
+                        let val_in_left = split_key <= key;
+                        // leaf is root
+                        if current_oid == self.root_oid {
+                            // new  root
+                            let branch = Branch::new(split_key, current_oid, new_branch_oid);
+                            self.root_oid = self.table.insert(Object::B(branch));
                         }
-                        // find next child
+                        // insert parent branch
                         else {
-                            let (oid, index) = branch.search(&key);
-                            parent_oid = current_oid;
-                            current_oid = oid;
-                            current_index = index;
+                            let parent_branch =
+                                self.table.get_mut(parent_oid, self.ts)?.get_mut::<Branch>();
+                            parent_branch.insert_non_full(current_index, split_key, new_branch_oid);
+                        }
+                        // reset current obj
+                        if val_in_left {
+                            current_oid = new_branch_oid;
+                            current_index += 1;
                         }
                     }
+                    // find next child
+                    else {
+                        let (oid, index) = branch.search(&key);
+                        parent_oid = current_oid;
+                        current_oid = oid;
+                        current_index = index;
+                    }
                 }
             }
         }
     }
 
     pub fn remove<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<(Key, Val)>, TdbError> {
-        if let Some(entry_oid) = self.get_oid(key)? {
+        if let Some(leaf_val) = self.get_leaf_val(key)? {
+            self.changed_keys.insert(key.borrow().to_vec());
             let mut current_oid = self.root_oid;
             let mut current_index = 0;
             let mut parent_oid = self.root_oid;
+            // branch is rebalanced or merged once, it may still be small with long keys
+            let mut balanced_oid = UNUSED_OID;
             loop {
                 let current_obj = self.table.get_ref(current_oid, self.ts)?;
                 match current_obj {
                     Object::E(entry) => {
-                        let kv = entry.get_key_val();
+                        let (key, val) = entry.get_key_val();
+                        let overflow_oid = entry.overflow_oid;
                         self.table.remove(current_oid, self.ts)?;
-                        return Ok(Some(kv));
+                        let val = self.read_overflow(val, overflow_oid)?;
+                        self.remove_overflow(overflow_oid)?;
+                        return Ok(Some((key, val)));
                     }
+                    Object::O(_) => unreachable!(),
                     Object::L(_) => {
                         let obj_mut = self.table.get_mut(current_oid, self.ts)?.get_mut::<Leaf>();
                         // remove entry
-                        let (_key, _oid) = obj_mut.remove(key).unwrap();
-                        assert_eq!(_oid, entry_oid);
-                        if obj_mut.should_rebalance_merge() {
-                            // leaf is root, don't merge
-                            if self.root_oid == current_oid {
-                                current_oid = _oid;
-                                continue;
-                            }
-                            // leaf is not root
+                        let (_key, _val) = obj_mut.remove(key).unwrap();
+                        assert_eq!(_val, leaf_val);
+                        // leaf is root, don't merge
+                        if obj_mut.should_rebalance_merge(self.node_size)
+                            && self.root_oid != current_oid
+                        {
                             let parent_branch =
                                 self.table.get_ref(parent_oid, self.ts)?.get_ref::<Branch>();
                             // use next obj to rebalance or merge
@@ -214,7 +345,11 @@ impl MutContext {
                                         self.table.get_mut(current_oid, self.ts)?.get_mut::<Leaf>()
                                             as *mut _;
                                     // merge is possible
-                                    if Leaf::should_merge(&*current_leaf_ptr, &*next_leaf_ptr) {
+                                    if Leaf::should_merge(
+                                        &*current_leaf_ptr,
+                                        &*next_leaf_ptr,
+                                        self.node_size,
+                                    ) {
                                         let current_leaf_mut = &mut *current_leaf_ptr;
                                         let next_leaf_mut = &mut *next_leaf_ptr;
                                         current_leaf_mut.merge(next_leaf_mut);
@@ -229,7 +364,7 @@ impl MutContext {
                                             parent_branch_mut.remove_index(current_index);
                                         assert_eq!(next_obj_tup.1, next_oid);
                                         if parent_branch_mut.keys.is_empty() {
-                                            // parent mut be root , non-root branch at least has 3 child (4K page, 255 max key size)
+                                            // parent mut be root , non-root branch at least has 3 child before merging its children
                                             assert!(
                                                 parent_branch_mut.children.len() == 1
                                                     && parent_oid == self.root_oid
@@ -242,7 +377,8 @@ impl MutContext {
                                     else {
                                         let current_leaf_mut = &mut *current_leaf_ptr;
                                         let next_leaf_mut = &mut *next_leaf_ptr;
-                                        let new_key = current_leaf_mut.rebalance(next_leaf_mut);
+                                        let new_key = current_leaf_mut
+                                            .rebalance(next_leaf_mut, self.node_size);
                                         let parent_branch_mut = self
                                             .table
                                             .get_mut(parent_oid, self.ts)?
@@ -264,7 +400,11 @@ impl MutContext {
                                         self.table.get_mut(current_oid, self.ts)?.get_mut::<Leaf>()
                                             as *mut _;
                                     // merge is possible
-                                    if Leaf::should_merge(&*prev_leaf_ptr, &*current_leaf_ptr) {
+                                    if Leaf::should_merge(
+                                        &*prev_leaf_ptr,
+                                        &*current_leaf_ptr,
+                                        self.node_size,
+                                    ) {
                                         let prev_leaf_mut = &mut *prev_leaf_ptr;
                                         let current_leaf_mut = &mut *current_leaf_ptr;
                                         prev_leaf_mut.merge(current_leaf_mut);
@@ -279,7 +419,7 @@ impl MutContext {
                                             parent_branch_mut.remove_index(current_index - 1);
                                         assert_eq!(current_obj_tup.1, current_oid);
                                         if parent_branch_mut.keys.is_empty() {
-                                            // parent mut be root , non-root branch at least has 3 child (4K page, 255 max key size)
+                                            // parent mut be root , non-root branch at least has 3 child before merging its children
                                             assert!(
                                                 parent_branch_mut.children.len() == 1
                                                     && parent_oid == self.root_oid
@@ -292,11 +432,13 @@ impl MutContext {
                                     else {
                                         assert!(Leaf::should_rebalance(
                                             &*prev_leaf_ptr,
-                                            &*current_leaf_ptr
+                                            &*current_leaf_ptr,
+                                            self.node_size
                                         ));
                                         let prev_leaf_mut = &mut *prev_leaf_ptr;
                                         let current_leaf_mut = &mut *current_leaf_ptr;
-                                        let new_key = prev_leaf_mut.rebalance(current_leaf_mut);
+                                        let new_key = prev_leaf_mut
+                                            .rebalance(current_leaf_mut, self.node_size);
                                         let parent_branch_mut = self
                                             .table
                                             .get_mut(parent_oid, self.ts)?
@@ -307,17 +449,25 @@ impl MutContext {
                                 }
                             }
                         }
-                        current_oid = _oid;
+                        match _val {
+                            LeafVal::Oid(oid) => current_oid = oid,
+                            LeafVal::Inline(val) => return Ok(Some((_key, val))),
+                        }
                     }
                     Object::B(branch) => {
                         // leaf is root, don't merge
-                        if branch.should_rebalance_merge() && self.root_oid != current_oid {
+                        if branch.should_rebalance_merge(self.node_size)
+                            && self.root_oid != current_oid
+                            && balanced_oid != current_oid
+                        {
                             // leaf is not root
                             let parent_branch =
                                 self.table.get_ref(parent_oid, self.ts)?.get_ref::<Branch>();
 
                             if current_index + 1 < parent_branch.children.len() {
                                 let next_oid = parent_branch.children[current_index + 1];
+                                // key of next branch's first child
+                                let next_key = parent_branch.keys[current_index].clone();
                                 unsafe {
                                     let next_branch_ptr =
                                         self.table.get_mut(next_oid, self.ts)?.get_mut::<Branch>()
@@ -328,15 +478,13 @@ impl MutContext {
                                         .get_mut::<Branch>()
                                         as *mut _;
                                     // merge is possible
-                                    if Branch::should_merge(&*current_branch_ptr, &*next_branch_ptr)
-                                    {
+                                    if Branch::should_merge(
+                                        &*current_branch_ptr,
+                                        &*next_branch_ptr,
+                                        self.node_size,
+                                    ) {
                                         let current_branch_mut = &mut *current_branch_ptr;
                                         let next_branch_mut = &mut *next_branch_ptr;
-                                        let next_key = self
-                                            .table
-                                            .get_ref(next_branch_mut.children[0], self.ts)?
-                                            .get_key()
-                                            .to_vec();
                                         current_branch_mut.merge(next_branch_mut, next_key);
                                         // remove next oid in obj table
                                         self.table.remove(next_oid, self.ts)?;
@@ -349,7 +497,7 @@ impl MutContext {
                                             parent_branch_mut.remove_index(current_index);
                                         assert_eq!(next_obj_tup.1, next_oid);
                                         if parent_branch_mut.keys.is_empty() {
-                                            // parent mut be root , non-root branch at least has 3 child (4K page, 255 max key size)
+                                            // parent mut be root , non-root branch at least has 3 child before merging its children
                                             assert!(
                                                 parent_branch_mut.children.len() == 1
                                                     && parent_oid == self.root_oid
@@ -364,29 +512,30 @@ impl MutContext {
                                     else {
                                         let current_branch_mut = &mut *current_branch_ptr;
                                         let next_branch_mut = &mut *next_branch_ptr;
-                                        let next_key = self
-                                            .table
-                                            .get_ref(next_branch_mut.children[0], self.ts)?
-                                            .get_key()
-                                            .to_vec();
-                                        let new_key =
-                                            current_branch_mut.rebalance(next_branch_mut, next_key);
+                                        let new_key = current_branch_mut.rebalance(
+                                            next_branch_mut,
+                                            next_key,
+                                            self.node_size,
+                                        );
                                         let parent_branch_mut = self
                                             .table
                                             .get_mut(parent_oid, self.ts)?
                                             .get_mut::<Branch>();
-                                        if new_key.as_slice() <= key.borrow() {
+                                        let in_next = new_key.as_slice() <= key.borrow();
+                                        // change key
+                                        parent_branch_mut.update_key(current_index, new_key);
+                                        if in_next {
                                             current_oid = next_oid;
                                             current_index += 1;
                                         }
-                                        // change key
-                                        parent_branch_mut.update_key(current_index, new_key);
                                     }
                                 }
                             }
                             // use prev obj to rebalance or merge
                             else {
                                 let prev_oid = parent_branch.children[current_index - 1];
+                                // key of current branch's first child
+                                let next_key = parent_branch.keys[current_index - 1].clone();
                                 // hack to get two mut ref at one time
                                 unsafe {
                                     let prev_branch_ptr =
@@ -398,15 +547,13 @@ impl MutContext {
                                         .get_mut::<Branch>()
                                         as *mut _;
                                     // merge is possible
-                                    if Branch::should_merge(&*prev_branch_ptr, &*current_branch_ptr)
-                                    {
+                                    if Branch::should_merge(
+                                        &*prev_branch_ptr,
+                                        &*current_branch_ptr,
+                                        self.node_size,
+                                    ) {
                                         let prev_branch_mut = &mut *prev_branch_ptr;
                                         let current_branch_mut = &mut *current_branch_ptr;
-                                        let next_key = self
-                                            .table
-                                            .get_ref(current_branch_mut.children[0], self.ts)?
-                                            .get_key()
-                                            .to_vec();
                                         prev_branch_mut.merge(current_branch_mut, next_key);
                                         // remove cur oid in obj table
                                         self.table.remove(current_oid, self.ts)?;
@@ -419,7 +566,7 @@ impl MutContext {
                                             parent_branch_mut.remove_index(current_index - 1);
                                         assert_eq!(current_obj_tup.1, current_oid);
                                         if parent_branch_mut.keys.is_empty() {
-                                            // parent mut be root , non-root branch at least has 3 child (4K page, 255 max key size)
+                                            // parent mut be root , non-root branch at least has 3 child before merging its children
                                             assert!(
                                                 parent_branch_mut.children.len() == 1
                                                     && parent_oid == self.root_oid
@@ -436,26 +583,26 @@ impl MutContext {
                                     else {
                                         let prev_branch_mut = &mut *prev_branch_ptr;
                                         let current_branch_mut = &mut *current_branch_ptr;
-                                        let next_key = self
-                                            .table
-                                            .get_ref(current_branch_mut.children[0], self.ts)?
-                                            .get_key()
-                                            .to_vec();
-                                        let new_key =
-                                            prev_branch_mut.rebalance(current_branch_mut, next_key);
+                                        let new_key = prev_branch_mut.rebalance(
+                                            current_branch_mut,
+                                            next_key,
+                                            self.node_size,
+                                        );
                                         let parent_branch_mut = self
                                             .table
                                             .get_mut(parent_oid, self.ts)?
                                             .get_mut::<Branch>();
-                                        if new_key.as_slice() > key.borrow() {
+                                        let in_prev = new_key.as_slice() > key.borrow();
+                                        // change key
+                                        parent_branch_mut.update_key(current_index - 1, new_key);
+                                        if in_prev {
                                             current_oid = prev_oid;
                                             current_index -= 1;
                                         }
-                                        // change key
-                                        parent_branch_mut.update_key(current_index - 1, new_key)
                                     }
                                 }
                             }
+                            balanced_oid = current_oid;
                         } else {
                             let (oid, index) = branch.search(key);
                             parent_oid = current_oid;
@@ -470,7 +617,7 @@ impl MutContext {
         }
     }
 
-    fn get_oid<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<ObjectId>, TdbError> {
+    fn get_leaf_val<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<LeafVal>, TdbError> {
         // tree is empty
         if self.root_oid == UNUSED_OID {
             return Ok(None);
@@ -479,9 +626,9 @@ impl MutContext {
         loop {
             let current_obj = self.table.get_ref(current_oid, self.ts)?;
             match current_obj {
-                Object::E(_) => unreachable!(),
+                Object::E(_) | Object::O(_) => unreachable!(),
                 Object::L(leaf) => match leaf.search(key) {
-                    Ok(oid) => return Ok(Some(oid)),
+                    Ok(val) => return Ok(Some(val.clone())),
                     Err(_) => return Ok(None),
                 },
                 Object::B(branch) => {
@@ -492,8 +639,170 @@ impl MutContext {
         }
     }
 
+    pub fn get<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<Val>, TdbError> {
+        match self.get_leaf_val(key)? {
+            Some(LeafVal::Oid(oid)) => Ok(Some(self.get_key_val(oid)?.1)),
+            Some(LeafVal::Inline(val)) => Ok(Some(val)),
+            None => Ok(None),
+        }
+    }
+
+    /// Set val of key to new if its val equals expected, None means key is absent or removed
+    /// Return true if applied
+    pub fn compare_and_swap<K: Into<Key>>(
+        &mut self,
+        key: K,
+        expected: Option<Val>,
+        new: Option<Val>,
+    ) -> Result<bool, TdbError> {
+        let key: Key = key.into();
+        if self.get(&key)? != expected {
+            return Ok(false);
+        }
+        match new {
+            Some(val) => self.insert(key, val)?,
+            None => {
+                self.remove(&key)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Insert key only if it is absent, return true if inserted
+    pub fn insert_if_absent<K: Into<Key>, V: Into<Val>>(
+        &mut self,
+        key: K,
+        val: V,
+    ) -> Result<bool, TdbError> {
+        self.compare_and_swap(key, None, Some(val.into()))
+    }
+
+    /// Update val of key only if it is present, return true if updated
+    pub fn update<K: Into<Key>, V: Into<Val>>(&mut self, key: K, val: V) -> Result<bool, TdbError> {
+        let key: Key = key.into();
+        if self.get_leaf_val(&key)?.is_none() {
+            return Ok(false);
+        }
+        self.insert(key, val)?;
+        Ok(true)
+    }
+
+    // Return key and whole val of entry
+    fn get_key_val(&mut self, oid: ObjectId) -> Result<(Key, Val), TdbError> {
+        let entry = self.table.get_ref(oid, self.ts)?.get_ref::<Entry>();
+        let (key, val) = entry.get_key_val();
+        let overflow_oid = entry.overflow_oid;
+        Ok((key, self.read_overflow(val, overflow_oid)?))
+    }
+
+    // Append data of overflow chain to val
+    fn read_overflow(&mut self, mut val: Val, mut oid: ObjectId) -> Result<Val, TdbError> {
+        while oid != UNUSED_OID {
+            let obj = self.table.get_arc(oid, self.ts)?;
+            let overflow = obj.get_ref::<Overflow>();
+            val.extend_from_slice(&overflow.data);
+            oid = overflow.next_oid;
+        }
+        Ok(val)
+    }
+
+    // Split the part of val which can't be put in entry to overflow chain
+    // Return (val in entry, first overflow oid)
+    fn insert_overflow(&mut self, key_len: usize, mut val: Val) -> (Val, ObjectId) {
+        let mut next_oid = UNUSED_OID;
+        let max_val_size = Entry::max_val_size(key_len);
+        if val.len() > max_val_size {
+            let rest = val.split_off(max_val_size);
+            // insert from the last one, so that each one knows the next
+            for data in rest.chunks(Overflow::max_data_size()).rev() {
+                next_oid = self
+                    .table
+                    .insert(Object::O(Overflow::new(data.to_vec(), next_oid)));
+            }
+        }
+        (val, next_oid)
+    }
+
+    // Write len bytes from reader to overflow chain in data file
+    // Return first overflow oid
+    fn write_overflow<R: Read>(&mut self, reader: &mut R, len: u64) -> Result<ObjectId, TdbError> {
+        let mut oids = vec![];
+        let result = self.write_overflow_chain(reader, len, &mut oids);
+        // objects must be readable before commit, they are aligned by commit
+        self.data_writer.flush()?;
+        match result {
+            Ok(()) => Ok(oids.first().copied().unwrap_or(UNUSED_OID)),
+            Err(err) => {
+                // release oids reserved for the chain
+                for oid in oids {
+                    self.table.remove(oid, self.ts)?;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn write_overflow_chain<R: Read>(
+        &mut self,
+        reader: &mut R,
+        mut len: u64,
+        oids: &mut Vec<ObjectId>,
+    ) -> Result<(), TdbError> {
+        if len == 0 {
+            return Ok(());
+        }
+        // reserve oid of next object before writing, so that each one knows the next
+        let mut oid = self.table.reserve();
+        oids.push(oid);
+        while len > 0 {
+            let size = cmp::min(len, Overflow::max_data_size() as u64);
+            let mut data = vec![0; size as usize];
+            reader.read_exact(&mut data)?;
+            len -= size;
+            let next_oid = if len > 0 {
+                let next_oid = self.table.reserve();
+                oids.push(next_oid);
+                next_oid
+            } else {
+                UNUSED_OID
+            };
+            let mut obj = Object::O(Overflow::new(data, next_oid));
+            let pos = self.data_writer.write_obj(&mut obj)?;
+            self.table.set_written_pos(oid, pos);
+            oid = next_oid;
+        }
+        Ok(())
+    }
+
+    // Remove entry and its overflow chain
+    fn remove_entry(&mut self, oid: ObjectId) -> Result<(), TdbError> {
+        let overflow_oid = self
+            .table
+            .get_ref(oid, self.ts)?
+            .get_ref::<Entry>()
+            .overflow_oid;
+        self.table.remove(oid, self.ts)?;
+        self.remove_overflow(overflow_oid)
+    }
+
+    // Remove all objects of overflow chain
+    fn remove_overflow(&mut self, mut oid: ObjectId) -> Result<(), TdbError> {
+        while oid != UNUSED_OID {
+            let next_oid = self
+                .table
+                .get_arc(oid, self.ts)?
+                .get_ref::<Overflow>()
+                .next_oid;
+            self.table.remove(oid, self.ts)?;
+            oid = next_oid;
+        }
+        Ok(())
+    }
+
+    /// Return None if key doesn't exist or its val is inlined in leaf
+    #[cfg(test)]
     pub fn get_entry<K: Borrow<[u8]>>(&mut self, key: &K) -> Result<Option<&Entry>, TdbError> {
-        if let Some(oid) = self.get_oid(key)? {
+        if let Some(LeafVal::Oid(oid)) = self.get_leaf_val(key)? {
             Ok(Some(self.table.get_ref(oid, self.ts)?.get_ref::<Entry>()))
         } else {
             Ok(None)
@@ -526,33 +835,189 @@ impl MutContext {
         // write objs to data file
         let (data_size, data_removed_size) =
             self.data_writer.write_objs(self.table.get_mut_cache())?;
-        self.data_writer.flush()?;
+        let sync = self.should_sync();
+        // data must be durable before checkpoint refer to it
+        if sync {
+            self.data_writer.sync()?;
+        } else {
+            self.data_writer.flush()?;
+        }
         debug!("data writer complete");
         // apply obj change to table
         let (cur_gc_ctx, obj_changes) = self.table.apply(self.ts, min_ts);
         debug!("table apply complete, obj_changes is {:?}", obj_changes);
+        self.write_cp(data_removed_size, data_size, obj_changes)?;
+        if sync {
+            self.meta_writer.sync()?;
+            self.last_sync = Instant::now();
+        }
+        self.unsynced = !sync;
+        Ok(self.new_ctx(cur_gc_ctx))
+    }
+
+    /// Copy live objects to new segments and switch to them by a new checkpoint
+    /// Old segments are still readable by old readers, they are removed by remove_stale_segments
+    /// # Errors
+    /// Nothing is changed if compaction fails, copied objects are counted as removed
+    pub fn compact(&mut self) -> Result<Arc<Context>, TdbError> {
+        let min_ts = self.gc();
+        self.increase_ts();
+        let data_state = self.data_writer.get_state();
+        let stale_segments = self.stale_segments.clone();
+        let mut obj_changes = vec![];
+        let result = self.compact_segments(min_ts, &mut obj_changes);
+        if let Err(err) = result.as_ref() {
+            warn!("compact fails: {:?}, roll back", err);
+            self.table.rollback_compact(self.ts, &obj_changes);
+            self.data_writer.rollback_compact(data_state);
+            self.stale_segments = stale_segments;
+            self.ts = self.committed_ts;
+        }
+        result
+    }
+
+    // obj_changes are set once new versions are added to table
+    fn compact_segments(
+        &mut self,
+        min_ts: TimeStamp,
+        obj_changes: &mut ObjChanges,
+    ) -> Result<Arc<Context>, TdbError> {
+        let old_first_segment_id = self.data_writer.get_first_segment_id();
+        let first_segment_id = self.data_writer.seal_segment()?;
+        info!(
+            "compact data segments {:?} to {:?}",
+            old_first_segment_id..first_segment_id,
+            first_segment_id
+        );
+        let cur_gc_ctx;
+        (cur_gc_ctx, *obj_changes) = self.table.compact(self.ts, min_ts, &mut self.data_writer)?;
+        let data_size = self.data_writer.align()?;
+        self.data_writer.sync()?;
+        self.data_writer.set_first_segment_id(first_segment_id);
+        // merge with segments of last compaction if they are not removed
+        let start = match self.stale_segments.take() {
+            Some((_, segments)) => segments.start,
+            None => old_first_segment_id,
+        };
+        self.stale_segments = Some((self.ts, start..first_segment_id));
+        self.write_cp(0, data_size, obj_changes.clone())?;
+        // always sync, old segments will be removed
+        self.meta_writer.sync()?;
+        self.last_sync = Instant::now();
+        info!("compact complete, data size is {:?}", data_size);
+        Ok(self.new_ctx(cur_gc_ctx))
+    }
+
+    /// Discard changes since last commit
+    pub fn rollback(&mut self) {
+        self.table.rollback();
+        self.data_writer.rollback();
+        self.root_oid = self.committed_root_oid;
+        self.ts = self.committed_ts;
+        self.changed_keys.clear();
+    }
+
+    /// Return keys inserted or removed since last call, it's called when commit is visible
+    pub fn take_changed_keys(&mut self) -> BTreeSet<Key> {
+        mem::take(&mut self.changed_keys)
+    }
+
+    /// Sync data and checkpoints written before
+    pub fn sync(&mut self) -> Result<(), TdbError> {
+        self.data_writer.sync()?;
+        self.meta_writer.sync()?;
+        self.last_sync = Instant::now();
+        self.unsynced = false;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn has_unsynced(&self) -> bool {
+        self.unsynced
+    }
+
+    /// Sync commits written before if interval of SyncMode::Periodic passed since last sync
+    /// Return duration after which it should be called again
+    pub fn sync_if_due(&mut self) -> Result<Duration, TdbError> {
+        let interval = match self.sync_mode {
+            SyncMode::Periodic(interval) => interval,
+            _ => return Ok(Duration::MAX),
+        };
+        let elapsed = self.last_sync.elapsed();
+        if !self.unsynced {
+            Ok(interval)
+        } else if elapsed >= interval {
+            self.sync()?;
+            Ok(interval)
+        } else {
+            Ok(interval - elapsed)
+        }
+    }
+
+    fn should_sync(&self) -> bool {
+        match self.sync_mode {
+            SyncMode::None => false,
+            SyncMode::PerCommit | SyncMode::GroupCommit => true,
+            SyncMode::Periodic(interval) => self.last_sync.elapsed() >= interval,
+        }
+    }
+
+    /// Remove segments replaced by compaction if no ctx before compaction is alive
+    /// # Notes
+    /// Should be called after new ctx is visible, so that no new reader will open old segments
+    pub fn remove_stale_segments(&mut self) -> Result<(), TdbError> {
+        if let Some((ts, segments)) = self.stale_segments.clone() {
+            let alive = self
+                .gc_ctx
+                .iter()
+                .take_while(|(_, ctx_ts, _)| *ctx_ts < ts)
+                .any(|(w_ptr, _, _)| w_ptr.strong_count() > 0);
+            if alive {
+                return Ok(());
+            }
+            for segment_id in segments {
+                debug!("remove stale data segment {:?}", segment_id);
+                let path = self.dev.data_segment_path(segment_id);
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+            }
+            self.stale_segments = None;
+        }
+        Ok(())
+    }
+
+    fn write_cp(
+        &mut self,
+        data_removed_size: u64,
+        data_size: u64,
+        obj_changes: ObjChanges,
+    ) -> Result<(), TdbError> {
         // make new checkpoint
         let mut cp = CheckPoint::new(
             data_removed_size,
             data_size,
+            self.data_writer.get_first_segment_id(),
             self.root_oid,
+            self.ts,
             0,
-            self.table_writer.used_page_num as u32,
+            self.table_writer.used_page_num,
             obj_changes,
         );
         debug!("generate checkpoint {:?}", cp);
         // write checkpoint
         if self.meta_writer.write_cp(&mut cp)? {
             // apply checkpoint if meta file is overflow
+            // always sync, meta log will be replaced
+            self.data_writer.sync()?;
+            self.meta_writer.sync()?;
             let dirty_pages = self.table.drain_dirty_pages();
             // write table file
             for pid in dirty_pages.iter() {
-                if *pid < cp.tablepage_nums {
-                    let page = self.table.get_page(*pid);
-                    self.table_writer.write_page(*pid, page)?;
-                }
+                let page = self.table.get_page(*pid);
+                self.table_writer.write_page(*pid, page)?;
             }
-            self.table_writer.flush()?;
+            self.table_writer.sync()?;
             debug!("table writer complete");
             cp.obj_changes.clear();
             cp.tablepage_nums = self.table_writer.used_page_num;
@@ -561,6 +1026,17 @@ impl MutContext {
                 .write_cp_rename(cp, &self.dev.meta_log_file_path)?;
         }
         debug!("meta writer complete");
+        Ok(())
+    }
+
+    /// Return ctx of last commit, which is tracked for gc like ctxs of new commits
+    pub fn init_ctx(&mut self) -> Arc<Context> {
+        self.new_ctx(vec![])
+    }
+
+    fn new_ctx(&mut self, cur_gc_ctx: Vec<ObjectId>) -> Arc<Context> {
+        self.committed_root_oid = self.root_oid;
+        self.committed_ts = self.ts;
         // push current ctx to gc ctx
         let ctx = Arc::new(Context {
             ts: self.ts,
@@ -569,7 +1045,200 @@ impl MutContext {
         debug!("generate new ctx {:?}", ctx);
         self.gc_ctx
             .push_back((Arc::downgrade(&ctx), ctx.ts, cur_gc_ctx));
-        Ok(ctx)
+        ctx
+    }
+}
+
+/// Iterator of (key, val) in range, uncommitted changes are visible
+pub struct MutIter<'a> {
+    ctx: &'a mut MutContext,
+    // path from root to leaf, (oid, index in parent)
+    path: Vec<(ObjectId, usize)>,
+    // index of next entry in leaf
+    entry_index: usize,
+    end: Bound<Key>,
+}
+
+impl<'a> MutIter<'a> {
+    // Find the first leaf and entry index in start bound
+    fn seek(&mut self, start: &Bound<Key>) -> Result<(), TdbError> {
+        if self.ctx.root_oid == UNUSED_OID {
+            return Ok(());
+        }
+        let mut current_oid = self.ctx.root_oid;
+        let mut index = 0;
+        loop {
+            self.path.push((current_oid, index));
+            match self.ctx.table.get_ref(current_oid, self.ctx.ts)? {
+                Object::E(_) | Object::O(_) => unreachable!(),
+                Object::B(branch) => {
+                    let (_oid, _index) = match start {
+                        Bound::Included(key) | Bound::Excluded(key) => branch.search(key),
+                        Bound::Unbounded => (branch.children[0], 0),
+                    };
+                    current_oid = _oid;
+                    index = _index;
+                }
+                Object::L(leaf) => {
+                    self.entry_index = match start {
+                        Bound::Included(key) => leaf.search_index(key),
+                        Bound::Excluded(key) => match leaf.search_index(key) {
+                            _index
+                                if _index < leaf.entrys.len() && &leaf.entrys[_index].0 == key =>
+                            {
+                                _index + 1
+                            }
+                            _index => _index,
+                        },
+                        Bound::Unbounded => 0,
+                    };
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn next_path(&mut self) -> Result<(), TdbError> {
+        while let Some((_, index)) = self.path.pop() {
+            if let Some((parent_oid, _)) = self.path.last() {
+                let mut parent_oid = *parent_oid;
+                let mut new_index = index + 1;
+                if new_index
+                    < self
+                        .ctx
+                        .table
+                        .get_ref(parent_oid, self.ctx.ts)?
+                        .get_ref::<Branch>()
+                        .children
+                        .len()
+                {
+                    loop {
+                        let new_oid = self
+                            .ctx
+                            .table
+                            .get_ref(parent_oid, self.ctx.ts)?
+                            .get_ref::<Branch>()
+                            .children[new_index];
+                        self.path.push((new_oid, new_index));
+                        if self.ctx.table.get_ref(new_oid, self.ctx.ts)?.is::<Leaf>() {
+                            break;
+                        } else {
+                            parent_oid = new_oid;
+                            new_index = 0;
+                        }
+                    }
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+        self.entry_index = 0;
+        Ok(())
+    }
+
+    fn before_end(end: &Bound<Key>, key: &[u8]) -> bool {
+        match end {
+            Bound::Included(end) => key <= end.as_slice(),
+            Bound::Excluded(end) => key < end.as_slice(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    // Read next (key, leaf val) in range
+    fn next_entry(&mut self) -> Result<Option<(Key, LeafVal)>, TdbError> {
+        loop {
+            let leaf_oid = match self.path.last() {
+                Some((oid, _)) => *oid,
+                None => return Ok(None),
+            };
+            let leaf = self
+                .ctx
+                .table
+                .get_ref(leaf_oid, self.ctx.ts)?
+                .get_ref::<Leaf>();
+            if self.entry_index < leaf.entrys.len() {
+                let (key, val) = &leaf.entrys[self.entry_index];
+                if !Self::before_end(&self.end, key) {
+                    self.path.clear();
+                    return Ok(None);
+                }
+                self.entry_index += 1;
+                return Ok(Some((key.clone(), val.clone())));
+            }
+            self.next_path()?;
+        }
+    }
+}
+
+impl<'a> Iterator for MutIter<'a> {
+    type Item = Result<(Key, Val), TdbError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match self.next_entry() {
+            Ok(Some((_, LeafVal::Oid(oid)))) => self.ctx.get_key_val(oid),
+            Ok(Some((key, LeafVal::Inline(val)))) => Ok((key, val)),
+            Ok(None) => return None,
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            self.path.clear();
+        }
+        Some(result)
+    }
+}
+
+impl MutContext {
+    /// Return iterator of (key, val) in range, including changes not committed
+    pub fn range<K: Borrow<[u8]>, R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<MutIter<'_>, TdbError> {
+        let start = to_owned_bound(range.start_bound());
+        let end = to_owned_bound(range.end_bound());
+        let mut iter = MutIter {
+            ctx: self,
+            path: vec![],
+            entry_index: 0,
+            end,
+        };
+        iter.seek(&start)?;
+        Ok(iter)
+    }
+
+    /// Return iterator of (key, val) whose key starts with prefix
+    pub fn scan_prefix(&mut self, prefix: &[u8]) -> Result<MutIter<'_>, TdbError> {
+        self.range(prefix_range(prefix))
+    }
+
+    /// Apply changes of batch in order
+    pub fn write_batch(&mut self, batch: &WriteBatch) -> Result<(), TdbError> {
+        for op in batch.ops.iter() {
+            match op {
+                BatchOp::Insert(key, val) => self.insert(key.clone(), val.clone())?,
+                BatchOp::Remove(key) => {
+                    self.remove(key)?;
+                }
+                BatchOp::RemoveRange(start, end) => {
+                    self.remove_range::<Key, _>((start.as_ref(), end.as_ref()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove all keys in range, return number of removed keys
+    pub fn remove_range<K: Borrow<[u8]>, R: RangeBounds<K>>(
+        &mut self,
+        range: R,
+    ) -> Result<usize, TdbError> {
+        let keys = self
+            .range(range)?
+            .map(|item| item.map(|(key, _)| key))
+            .collect::<Result<Vec<Key>, TdbError>>()?;
+        for key in keys.iter() {
+            self.remove(key)?;
+        }
+        Ok(keys.len())
     }
 }
 
@@ -577,11 +1246,14 @@ impl MutContext {
 mod tests {
     use super::*;
     use crate::storage::Dev;
-    use std::env;
+    use tempfile::tempdir;
     #[test]
     fn test_mut_ctx() {
-        let dev = Dev::open(env::current_dir().unwrap()).unwrap();
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
         let (mut mut_ctx, _, _) = MutContext::new_empty(dev).unwrap();
+        // keep vals in entrys
+        mut_ctx.set_inline_val_size(0);
 
         let _ = mut_ctx.insert(vec![0; 255], vec![1]);
         assert_eq!(
@@ -674,4 +1346,43 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_mut_ctx_rollback() {
+        let dir = tempdir().unwrap();
+        let dev = Dev::open(dir.path()).unwrap();
+        let (mut mut_ctx, _, _) = MutContext::new_empty(dev.clone()).unwrap();
+        let last_cp = || {
+            let cps = dev.get_meta_reader().unwrap().read_cps().unwrap();
+            cps.last().unwrap().clone()
+        };
+        mut_ctx.increase_ts();
+        assert_eq!(mut_ctx.insert(vec![1], vec![1]), Ok(()));
+        let _ctx = mut_ctx.commit().unwrap();
+        let cp0 = last_cp();
+        let ts = mut_ctx.ts;
+
+        mut_ctx.increase_ts();
+        let val = vec![2; 100_000];
+        assert_eq!(
+            mut_ctx.insert_from(vec![2], &val[..], val.len() as u64),
+            Ok(())
+        );
+        // reader is shorter than len
+        assert!(mut_ctx.insert_from(vec![3], &val[..], 200_000).is_err());
+        mut_ctx.rollback();
+        assert_eq!(mut_ctx.ts, ts);
+
+        mut_ctx.increase_ts();
+        let _ctx = mut_ctx.commit().unwrap();
+        let cp1 = last_cp();
+        assert_eq!(mut_ctx.ts, ts + 1);
+        // overflow objects written before rollback and their padding are removed
+        assert!(cp1.data_size > cp0.data_size);
+        assert_eq!(
+            cp1.data_removed_size - cp0.data_removed_size,
+            cp1.data_size - cp0.data_size
+        );
+        assert_eq!(mut_ctx.get(&vec![2]), Ok(None));
+    }
 }
@@ -0,0 +1,96 @@
+use crate::error::TdbError;
+use crate::object::UNUSED_OID;
+use crate::storage::ObjectPos;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc32c::crc32c_append;
+use std::io::{self, Read, Write};
+use std::mem;
+
+pub const CHECKSUM_SIZE: usize = mem::size_of::<u32>();
+
+/// Writer compute crc32c of written bytes
+pub struct CrcWriter<'a, W: Write> {
+    writer: &'a mut W,
+    crc: u32,
+}
+
+impl<'a, W: Write> CrcWriter<'a, W> {
+    pub fn new(writer: &'a mut W) -> Self {
+        Self { writer, crc: 0 }
+    }
+    /// Write checksum of bytes written before
+    /// Return checksum size
+    pub fn write_checksum(&mut self) -> Result<usize, TdbError> {
+        self.writer.write_u32::<LittleEndian>(self.crc)?;
+        Ok(CHECKSUM_SIZE)
+    }
+}
+
+impl<'a, W: Write> Write for CrcWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.writer.write(buf)?;
+        self.crc = crc32c_append(self.crc, &buf[..size]);
+        Ok(size)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reader compute crc32c of read bytes
+pub struct CrcReader<'a, R: Read> {
+    reader: &'a mut R,
+    crc: u32,
+}
+
+impl<'a, R: Read> CrcReader<'a, R> {
+    pub fn new(reader: &'a mut R) -> Self {
+        Self { reader, crc: 0 }
+    }
+    /// Read checksum and compare it with bytes read before
+    /// # Errors
+    /// Return TdbError::Corruption if mismatch, oid and pos should be filled by caller
+    pub fn check_checksum(&mut self) -> Result<(), TdbError> {
+        let crc = self.reader.read_u32::<LittleEndian>()?;
+        if crc != self.crc {
+            return Err(TdbError::Corruption {
+                oid: UNUSED_OID,
+                pos: ObjectPos::default(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R: Read> Read for CrcReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.reader.read(buf)?;
+        self.crc = crc32c_append(self.crc, &buf[..size]);
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_crc() {
+        let mut buf = vec![];
+        let mut writer = CrcWriter::new(&mut buf);
+        writer.write_all(&[1, 2, 3]).unwrap();
+        assert_eq!(writer.write_checksum(), Ok(CHECKSUM_SIZE));
+        assert_eq!(buf.len(), 3 + CHECKSUM_SIZE);
+
+        let mut data = [0; 3];
+        let mut slice = buf.as_slice();
+        let mut reader = CrcReader::new(&mut slice);
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(reader.check_checksum(), Ok(()));
+
+        buf[1] = 0;
+        let mut slice = buf.as_slice();
+        let mut reader = CrcReader::new(&mut slice);
+        reader.read_exact(&mut data).unwrap();
+        assert!(reader.check_checksum().is_err());
+    }
+}
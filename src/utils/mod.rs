@@ -1,2 +1,4 @@
 mod bitmap;
-pub use bitmap::{AsBitBlock, BitMap};
+mod crc;
+pub use bitmap::BitMap;
+pub use crc::{CrcReader, CrcWriter, CHECKSUM_SIZE};